@@ -18,7 +18,7 @@ import time
 import collections
 from os.path import join, dirname, exists
 from shutil import rmtree
-from json import load
+from json import load, loads
 from zipfile import ZipFile
 from appdirs import user_data_dir
 from ._native import ffi, lib
@@ -62,6 +62,16 @@ class Cache (object):
                         connection timeout and low speed timeout for
                         downloading things.
     :type no_timeouts: bool
+    :param proxy: The URL of an HTTP proxy for downloads; when None, the
+                  HTTPS_PROXY and HTTP_PROXY environment variables are used
+    :type proxy: str
+    :param ca_file: A PEM file of extra root certificates to trust
+    :type ca_file: str
+
+    After each update or install, ``last_report`` lists every file with its
+    ``source``, ``dest``, ``outcome`` (one of ``downloaded``, ``unchanged``,
+    ``failed`` or ``deprecated``), HTTP ``status``, ``bytes`` and
+    ``error``.
     """
     def __init__(
             self,
@@ -69,7 +79,9 @@ class Cache (object):
             __,
             json_path=None,
             data_path=None,
-            vidx_list=None
+            vidx_list=None,
+            proxy=None,
+            ca_file=None
     ):
         default_path = user_data_dir('cmsis-pack-manager')
         json_path = default_path if not json_path else json_path
@@ -79,7 +91,10 @@ class Cache (object):
         self.aliases_path = join(json_path, "aliases.json")
         self.data_path = default_path if not data_path else data_path
         self.vidx_list = vidx_list
+        self.proxy = proxy
+        self.ca_file = ca_file
         self.silent = silent
+        self.last_report = []
 
     def get_flash_algorithm_binary(self, device_name, all=False):
         """Retrieve the flash algorithm file for a particular part.
@@ -216,9 +231,21 @@ class Cache (object):
         else:
             cdata_path = ffi.NULL
         with _RaiseRust():
-            poll_obj = lib.update_packs(cdata_path, parsed_packs)
+            poll_obj = lib.update_packs(cdata_path, parsed_packs,
+                                        *self._connection_args())
         return self._poll_rust_update(poll_obj, on_tick_fn)
 
+    def _connection_args(self):
+        if self.proxy:
+            cproxy = ffi.new("char[]", self.proxy.encode("utf-8"))
+        else:
+            cproxy = ffi.NULL
+        if self.ca_file:
+            cca_file = ffi.new("char[]", self.ca_file.encode("utf-8"))
+        else:
+            cca_file = ffi.NULL
+        return cproxy, cca_file
+
     def _verbose_on_tick_fn(self, total, current):
         if total:
             total = "{:03}".format(total)
@@ -239,7 +266,8 @@ class Cache (object):
         else:
             cvidx_path = ffi.NULL
         with _RaiseRust():
-            poll_obj = lib.update_pdsc_index(cdata_path, cvidx_path)
+            poll_obj = lib.update_pdsc_index(cdata_path, cvidx_path,
+                                             *self._connection_args())
         return self._poll_rust_update(poll_obj, on_tick_fn)
 
     def _poll_rust_update(self, poll_obj, on_tick_fn):
@@ -248,27 +276,54 @@ class Cache (object):
             current_downloads = 0
             while not lib.update_pdsc_poll(poll_obj):
                 prev_downloads = current_downloads
-                time.sleep(1/2)
+                try:
+                    time.sleep(1/2)
+                except KeyboardInterrupt:
+                    self._cancel_rust_update(poll_obj)
+                    raise
                 message = ffi.gc(
                     lib.update_pdsc_get_status(poll_obj),
                     lib.update_pdsc_status_free
                 )
                 while message:
-                    if message.is_size:
-                        total_downloads = message.size
-                    else:
+                    if message.kind == lib.FilesQueued:
+                        total_downloads = (total_downloads or 0) + message.size
+                    elif message.kind == lib.Completed:
                         current_downloads += 1
+                    elif message.kind == lib.Failed and not self.silent:
+                        self._report_failure(message)
                     message = ffi.gc(
                         lib.update_pdsc_get_status(poll_obj),
                         lib.update_pdsc_status_free
                     )
                 if on_tick_fn and current_downloads != prev_downloads:
                     on_tick_fn(total_downloads, current_downloads)
+            report = lib.update_pdsc_report(poll_obj)
+            if report:
+                self.last_report = loads(ffi.string(report).decode("utf-8"))["items"]
+                lib.cstring_free(report)
             pdsc_index = ffi.gc(
                 lib.update_pdsc_result(poll_obj), lib.update_pdsc_index_free
             )
         return pdsc_index
 
+    def _cancel_rust_update(self, poll_obj):
+        lib.update_pdsc_cancel(poll_obj)
+        while not lib.update_pdsc_poll(poll_obj):
+            time.sleep(0.1)
+
+    def failed_downloads(self):
+        """The items of ``last_report`` that failed to download or install.
+        """
+        return [item for item in self.last_report
+                if item["outcome"] == "failed"]
+
+    def _report_failure(self, message):
+        file_name = ffi.string(message.file) if message.file else b"a file"
+        error = ffi.string(message.message) if message.message else b""
+        sys.stderr.write("\nDownload of {} failed: {}\n".format(
+            file_name.decode("utf-8"), error.decode("utf-8")))
+
     def _call_rust_parse(self, pdsc_index):
         with _RaiseRust():
             parsed_packs = ffi.gc(lib.parse_packs(pdsc_index),
@@ -288,6 +343,32 @@ class Cache (object):
             lib.dump_pdsc_json(parsed_packs, cindex_path, calias_path)
         return parsed_packs
 
+    _SECTIONS = ("examples", "generators", "apis", "taxonomy",
+                 "requirements", "keywords", "license_sets")
+
+    def pack_section(self, parsed_packs, section):
+        """One section of the parsed Pack Descriptions, across all packs.
+
+        :param parsed_packs: as returned by ``cache_descriptors``
+        :param section: one of "examples", "generators", "apis", "taxonomy",
+        "requirements", "keywords" or "license_sets"
+
+        :Example:
+
+        >>> from cmsis_pack_manager import Cache
+        >>> c = Cache()
+        >>> c.pack_section(c.cache_descriptors(), "keywords")
+        ['RTOS', 'Kernel']
+        """
+        if section not in self._SECTIONS:
+            raise ValueError("unknown pack section {}".format(section))
+        with _RaiseRust():
+            dumped = getattr(lib, "dumps_" + section)(parsed_packs)
+        try:
+            return loads(ffi.string(dumped).decode("utf-8"))
+        finally:
+            lib.cstring_free(dumped)
+
     def cache_descriptors(self):
         """Cache all Pack Descriptions and generate an index of them.
 
@@ -7,6 +7,7 @@ extern crate pack_index as pi;
 extern crate pdsc as pack_desc;
 extern crate utils as cmsis_utils;
 extern crate failure;
+extern crate serde_json;
 
 macro_rules! with_from_raw {
     (let $boxed:ident = $ptr:ident, $block:block) => {
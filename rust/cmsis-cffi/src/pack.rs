@@ -12,12 +12,14 @@ use cmsis_update::install;
 use pi::config::ConfigBuilder;
 
 use pdsc::ParsedPacks;
-use pack_index::{DownloadSender, UpdatePoll, RunningUpdateContext, UpdateReturn};
+use pack_index::{with_connection, DownloadSender, UpdatePoll, RunningUpdateContext};
 
 cffi!{
     fn update_packs(
         pack_store: *const c_char,
-        parsed_packs: *mut ParsedPacks
+        parsed_packs: *mut ParsedPacks,
+        proxy: *const c_char,
+        ca_file: *const c_char
     ) -> Result<*mut UpdatePoll> {
         let conf_bld = ConfigBuilder::new();
         let conf_bld = if !pack_store.is_null() {
@@ -26,7 +28,8 @@ cffi!{
         } else {
             conf_bld
         };
-        let conf = conf_bld.build()?;
+        let conf = with_connection(conf_bld, proxy, ca_file).build()?;
+        let cancel = conf.download.cancel.clone();
         let (send, recv) = channel();
         let done_flag = Arc::new(AtomicBool::new(false));
         let threads_done_flag = done_flag.clone();
@@ -49,7 +52,7 @@ cffi!{
                             packs.iter(), 
                             &log, 
                             DownloadSender::from_sender(send)
-                        ).map(UpdateReturn);
+                        );
                         threads_done_flag.store(true, Ordering::Release);
                         res
                     })?;
@@ -57,6 +60,7 @@ cffi!{
                     thread_handle: thread,
                     done_flag,
                     result_stream: recv,
+                    cancel,
                 }))))
             })
         } else {
@@ -1,5 +1,6 @@
 use slog::Logger;
 use std::borrow::{Borrow, BorrowMut};
+use std::cell::Cell;
 use std::os::raw::c_char;
 use std::ffi::{CStr, CString};
 use std::path::PathBuf;
@@ -9,57 +10,157 @@ use std::mem;
 use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use failure::{err_msg, Error};
 
 use cmsis_update::update;
-use cmsis_update::DownloadProgress;
-use pi::config::ConfigBuilder;
+use cmsis_update::{Cancelled, DownloadProgress, UpdateReport};
+use pi::config::{CancelToken, ConfigBuilder, Proxy};
 use utils::set_last_error;
 
 pub struct UpdateReturn(pub(crate) Vec<PathBuf>);
 
 pub struct RunningUpdateContext {
-    pub(crate) thread_handle: thread::JoinHandle<Result<UpdateReturn, Error>>,
+    pub(crate) thread_handle: thread::JoinHandle<Result<UpdateReport, Error>>,
     pub(crate) done_flag: Arc<AtomicBool>,
-    pub(crate) result_stream: Receiver<DownloadUpdate>,
+    pub(crate) result_stream: Receiver<DownloadEvent>,
+    pub(crate) cancel: CancelToken,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DownloadUpdateKind {
+    FilesQueued,
+    FileSize,
+    BytesReceived,
+    Resumed,
+    Failed,
+    Completed,
 }
 
 #[repr(C)]
 pub struct DownloadUpdate{
+    /// Set for `FilesQueued`, as it was before there were other kinds.
     pub is_size: bool,
+    /// Files for `FilesQueued`; bytes for `FileSize`, `BytesReceived` and
+    /// `Resumed`. `BytesReceived` counts the bytes since the last one for
+    /// the same file.
     pub size: usize,
+    pub kind: DownloadUpdateKind,
+    /// The file being downloaded, or null.
+    pub file: *mut c_char,
+    /// Why a `Failed` download failed, or null.
+    pub message: *mut c_char,
+}
+
+/// A `DownloadUpdate` as sent from the update thread.
+pub(crate) struct DownloadEvent {
+    kind: DownloadUpdateKind,
+    size: usize,
+    file: Option<String>,
+    message: Option<String>,
 }
 
-pub(crate) struct DownloadSender(Sender<DownloadUpdate>);
+fn into_c_string(string: Option<String>) -> *mut c_char {
+    string
+        .and_then(|string| CString::new(string).ok())
+        .map(|cstr| cstr.into_raw())
+        .unwrap_or_else(null_mut)
+}
+
+impl From<DownloadEvent> for DownloadUpdate {
+    fn from(event: DownloadEvent) -> Self {
+        DownloadUpdate {
+            is_size: event.kind == DownloadUpdateKind::FilesQueued,
+            size: event.size,
+            kind: event.kind,
+            file: into_c_string(event.file),
+            message: into_c_string(event.message),
+        }
+    }
+}
+
+/// Received bytes are merged into one `BytesReceived` event per this many
+/// bytes, or per `MERGE_TIME`, whichever comes first.
+const MERGE_BYTES: usize = 64 * 1024;
+const MERGE_TIME: Duration = Duration::from_millis(100);
+
+pub(crate) struct DownloadSender {
+    sender: Sender<DownloadEvent>,
+    file: Option<String>,
+    /// Bytes received but not yet sent, and when bytes were last sent.
+    received: Cell<usize>,
+    sent_at: Cell<Instant>,
+}
 
 impl DownloadSender {
-    pub(crate) fn from_sender(from: Sender<DownloadUpdate>) -> Self {
-        DownloadSender(from)
+    pub(crate) fn from_sender(from: Sender<DownloadEvent>) -> Self {
+        DownloadSender {
+            sender: from,
+            file: None,
+            received: Cell::new(0),
+            sent_at: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Send the bytes received since the last `BytesReceived` event.
+    fn flush(&self) {
+        let received = self.received.replace(0);
+        if received > 0 {
+            self.sent_at.set(Instant::now());
+            self.send(DownloadUpdateKind::BytesReceived, received, None);
+        }
+    }
+
+    fn send(&self, kind: DownloadUpdateKind, size: usize, message: Option<String>) {
+        let _ = self.sender.send(DownloadEvent {
+            kind,
+            size,
+            file: self.file.clone(),
+            message,
+        });
     }
 }
 
 impl DownloadProgress for DownloadSender {
     fn size(&self, size: usize){
-        let _ = self.0.send(DownloadUpdate{
-            is_size: true,
-            size
-        });
+        self.send(DownloadUpdateKind::FilesQueued, size, None);
     }
 
-    fn progress(&self, _: usize) {
-        /* not implemented */
+    fn file_size(&self, bytes: usize) {
+        self.send(DownloadUpdateKind::FileSize, bytes, None);
+    }
+
+    fn progress(&self, bytes: usize) {
+        self.received.set(self.received.get() + bytes);
+        if self.received.get() >= MERGE_BYTES || self.sent_at.get().elapsed() >= MERGE_TIME {
+            self.flush();
+        }
+    }
+
+    fn resumed(&self, bytes: usize) {
+        self.flush();
+        self.send(DownloadUpdateKind::Resumed, bytes, None);
+    }
+
+    fn failed(&self, error: &str) {
+        self.flush();
+        self.send(DownloadUpdateKind::Failed, 0, Some(error.to_string()));
     }
 
     fn complete(&self) {
-        let _ = self.0.send(DownloadUpdate{
-            is_size: false,
-            size: 0
-        });
+        self.flush();
+        self.send(DownloadUpdateKind::Completed, 0, None);
     }
 
-    fn for_file(&self, _: &str) -> Self {
-        DownloadSender(self.0.clone())
+    fn for_file(&self, file: &str) -> Self {
+        DownloadSender {
+            sender: self.sender.clone(),
+            file: Some(file.to_string()),
+            received: Cell::new(0),
+            sent_at: Cell::new(Instant::now()),
+        }
     }
 }
 
@@ -67,7 +168,9 @@ impl DownloadProgress for DownloadSender {
  * on any closure that takes an UpdatePoll as an argument */
 pub enum UpdatePoll {
     Running(RunningUpdateContext),
-    Complete(Result<UpdateReturn, Error>),
+    Complete(Result<UpdateReport, Error>),
+    /// The update stopped after `update_pdsc_cancel`.
+    Cancelled,
     Drained,
 }
 
@@ -81,10 +184,34 @@ impl UpdateReturn {
     }
 }
 
+/// Apply the optional `proxy` URL and `ca_file` PEM path of an update.
+pub(crate) fn with_connection(
+    conf_bld: ConfigBuilder,
+    proxy: *const c_char,
+    ca_file: *const c_char,
+) -> ConfigBuilder {
+    let conf_bld = if !proxy.is_null() {
+        let url = unsafe { CStr::from_ptr(proxy) }.to_string_lossy();
+        let mut proxy = Proxy::new(url.into_owned());
+        proxy.no_proxy = Proxy::from_env().map(|env| env.no_proxy).unwrap_or_default();
+        conf_bld.with_proxy(proxy)
+    } else {
+        conf_bld
+    };
+    if !ca_file.is_null() {
+        let pem = unsafe { CStr::from_ptr(ca_file) }.to_string_lossy();
+        conf_bld.with_ca_roots(pem.into_owned())
+    } else {
+        conf_bld
+    }
+}
+
 cffi!{
     fn update_pdsc_index(
         pack_store: *const c_char,
         vidx_list: *const c_char,
+        proxy: *const c_char,
+        ca_file: *const c_char,
     ) -> Result<*mut UpdatePoll> {
         let conf_bld = ConfigBuilder::new();
         let conf_bld = if !pack_store.is_null() {
@@ -99,7 +226,8 @@ cffi!{
         } else {
             conf_bld
         };
-        let conf = conf_bld.build()?;
+        let conf = with_connection(conf_bld, proxy, ca_file).build()?;
+        let cancel = conf.download.cancel.clone();
         let (send, recv) = channel();
         let done_flag = Arc::new(AtomicBool::new(false));
         let threads_done_flag = done_flag.clone();
@@ -119,7 +247,7 @@ cffi!{
                     vidx_list, 
                     &log, 
                     DownloadSender::from_sender(send)
-                ).map(UpdateReturn);
+                );
                 threads_done_flag.store(true, Ordering::Release);
                 res
             })?;
@@ -127,6 +255,7 @@ cffi!{
             thread_handle: thread,
             done_flag,
             result_stream: recv,
+            cancel,
         }))))
     }
 }
@@ -138,6 +267,7 @@ pub extern "C" fn update_pdsc_poll(ptr: *mut UpdatePoll) -> bool {
         with_from_raw!(let mut boxed = ptr,{
             let (ret, next_state) = match mem::replace(boxed.borrow_mut(), UpdatePoll::Drained) {
                 UpdatePoll::Complete(inner) => (true, UpdatePoll::Complete(inner)),
+                UpdatePoll::Cancelled => (true, UpdatePoll::Cancelled),
                 UpdatePoll::Drained => (true, UpdatePoll::Drained),
                 UpdatePoll::Running(cont) => {
                     if cont.done_flag.load(Ordering::Acquire) {
@@ -146,7 +276,12 @@ pub extern "C" fn update_pdsc_poll(ptr: *mut UpdatePoll) -> bool {
                             Ok(inner) => inner,
                             Err(_) => Err(err_msg("thread paniced"))
                         };
-                        (true, UpdatePoll::Complete(response))
+                        match response {
+                            Err(ref e) if e.downcast_ref::<Cancelled>().is_some() => {
+                                (true, UpdatePoll::Cancelled)
+                            }
+                            response => (true, UpdatePoll::Complete(response)),
+                        }
                     } else {
                         (false, UpdatePoll::Running(cont))
                     }
@@ -166,11 +301,12 @@ pub extern "C" fn update_pdsc_get_status(ptr: *mut UpdatePoll) -> *mut DownloadU
         with_from_raw!(let boxed = ptr,{
             match boxed.borrow() {
                 &UpdatePoll::Complete(_) => null_mut(),
+                &UpdatePoll::Cancelled => null_mut(),
                 &UpdatePoll::Drained => null_mut(),
                 &UpdatePoll::Running(ref cont) => {
                     let response = cont.result_stream.try_recv();
                     match response {
-                        Ok(inner) => Box::into_raw(Box::new(inner)),
+                        Ok(inner) => Box::into_raw(Box::new(DownloadUpdate::from(inner))),
                         Err(_) => null_mut()
                     }
                 }
@@ -184,7 +320,12 @@ pub extern "C" fn update_pdsc_get_status(ptr: *mut UpdatePoll) -> *mut DownloadU
 cffi!{
     fn update_pdsc_status_free(ptr: *mut DownloadUpdate) {
         if !ptr.is_null() {
-            drop(unsafe { Box::from_raw(ptr) })
+            let update = unsafe { Box::from_raw(ptr) };
+            for string in &[update.file, update.message] {
+                if !string.is_null() {
+                    drop(unsafe { CString::from_raw(*string) })
+                }
+            }
         }
     }
 }
@@ -195,12 +336,13 @@ pub extern "C" fn update_pdsc_result(ptr: *mut UpdatePoll) -> *mut UpdateReturn
         with_from_raw!(let mut boxed = ptr,{
             let (ret, next_state) = match mem::replace(boxed.borrow_mut(), UpdatePoll::Drained) {
                 UpdatePoll::Complete(inner) => (Some(inner), UpdatePoll::Drained),
+                UpdatePoll::Cancelled => (Some(Err(Cancelled.into())), UpdatePoll::Cancelled),
                 UpdatePoll::Drained => (None, UpdatePoll::Drained),
                 UpdatePoll::Running(cont) => (None, UpdatePoll::Running(cont))
             };
             mem::replace(boxed.borrow_mut(), next_state);
             match ret {
-                Some(Ok(inner)) => Box::into_raw(Box::new(inner)),
+                Some(Ok(report)) => Box::into_raw(Box::new(UpdateReturn(report.paths()))),
                 Some(Err(inner)) => {
                     println!("{:?}", inner);
                     set_last_error(inner);
@@ -214,6 +356,47 @@ pub extern "C" fn update_pdsc_result(ptr: *mut UpdatePoll) -> *mut UpdateReturn
     }
 }
 
+/// Stop a running update. Once polling it is done, `update_pdsc_result`
+/// returns null with the error of cancelled downloads.
+#[no_mangle]
+pub extern "C" fn update_pdsc_cancel(ptr: *mut UpdatePoll) {
+    if !ptr.is_null() {
+        with_from_raw!(let boxed = ptr,{
+            if let UpdatePoll::Running(ref cont) = *boxed {
+                cont.cancel.cancel();
+            }
+        })
+    }
+}
+
+/// The `UpdateReport` of a complete update as JSON, to be freed with
+/// `cstring_free`. Call it before `update_pdsc_result`, which drains the
+/// update.
+#[no_mangle]
+pub extern "C" fn update_pdsc_report(ptr: *mut UpdatePoll) -> *mut c_char {
+    if !ptr.is_null() {
+        let json = with_from_raw!(let boxed = ptr,{
+            match boxed.borrow() {
+                &UpdatePoll::Complete(Ok(ref report)) => Some(serde_json::to_string(report)),
+                _ => None
+            }
+        });
+        let cstr = match json {
+            Some(json) => json.map_err(Error::from).and_then(|json| Ok(CString::new(json)?)),
+            None => return null_mut(),
+        };
+        match cstr {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_last_error(e);
+                null_mut()
+            }
+        }
+    } else {
+        null_mut()
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn update_pdsc_index_new() -> *mut UpdateReturn {
     Box::into_raw(Box::new(UpdateReturn(Vec::new())))
@@ -107,3 +107,90 @@ cffi!{
         })
     }
 }
+
+cffi!{
+    fn dumps_examples(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_examples(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_generators(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_generators(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_apis(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_apis(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_taxonomy(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_taxonomy(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_requirements(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_requirements(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_keywords(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_keywords(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_license_sets(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        with_from_raw!(let boxed = ptr, {
+            let dumped = pack_desc::dumps_license_sets(boxed.iter())?;
+            Ok(CString::new(dumped).unwrap().into_raw())
+        })
+    }
+}
+
+cffi!{
+    fn dumps_components_for_device(
+        ptr: *mut ParsedPacks,
+        device: *const c_char,
+        compiler: *const c_char,
+    ) -> Result<*const c_char> {
+        if !ptr.is_null() && !device.is_null() && !compiler.is_null() {
+            let decorator = TermDecorator::new().build();
+            let drain = FullFormat::new(decorator).build().fuse();
+            let drain = Async::new(drain).build().fuse();
+            let log = Logger::root(drain, o!());
+            let device = unsafe { CStr::from_ptr(device) }.to_string_lossy();
+            let compiler = unsafe { CStr::from_ptr(compiler) }.to_string_lossy();
+            with_from_raw!(let boxed = ptr, {
+                let dumped_components = pack_desc::dumps_components_for_device(
+                    boxed.iter(), &device, &compiler, &log)?;
+                Ok(CString::new(dumped_components).unwrap().into_raw())
+            })
+        } else {
+            Err(err_msg("Null passed into dumps components for device."))
+        }
+    }
+}
@@ -1,4 +1,5 @@
 extern crate clap;
+extern crate ctrlc;
 extern crate failure;
 
 #[macro_use]
@@ -9,50 +10,238 @@ extern crate utils;
 extern crate pack_index;
 extern crate pdsc;
 extern crate pbr;
+extern crate serde_json;
 
+use std::fs::{create_dir_all, remove_file};
+#[cfg(feature = "serve")]
+use std::net::SocketAddr;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::io::Stdout;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use slog::Logger;
-use failure::Error;
+use failure::{err_msg, Error};
 use clap::{ArgMatches, App, Arg, SubCommand};
-use pbr::ProgressBar;
+use pbr::{MultiBar, Pipe, ProgressBar, Units};
 
-use cmsis_update::{install, update, DownloadProgress};
-use pack_index::config::Config;
-use pdsc::{dump_devices, Component, FileRef, Package};
+use cmsis_update::{delete, file_url, install, mirror, plan_gc, plan_remove, update, DownloadProgress,
+                   LockFile, Outcome, PackRelease, PackState, Registry, Removal, UpdateReport};
+#[cfg(feature = "serve")]
+use cmsis_update::PackServer;
+use pack_index::config::{Config, ConfigBuilder, DownloadOptions, Proxy};
+use pdsc::{dump_devices, dumps_components_for_device, Board, Component, FileRef, Package, Version};
+use utils::ResultLogExt;
 use utils::parse::FromElem;
 
-struct CliProgress(Arc<Mutex<ProgressBar<Stdout>>>);
+/// The bars of a `CliProgress`: files done of those queued, and bytes
+/// received of the file sizes known so far.
+struct Bars {
+    files: ProgressBar<Pipe>,
+    bytes: ProgressBar<Pipe>,
+    received: u64,
+    in_flight: Vec<String>,
+    failed: usize,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl Bars {
+    fn show_files(&mut self) {
+        let current = self.in_flight.last().map(String::as_str).unwrap_or("");
+        let message = match self.failed {
+            0 => format!("Downloading {:<32.32} ", current),
+            failed => format!("Downloading {:<32.32} ({} failed) ", current, failed),
+        };
+        self.files.message(&message);
+        self.files.tick();
+    }
+}
+
+#[derive(Clone)]
+struct CliProgress {
+    bars: Arc<Mutex<Bars>>,
+    file: Option<String>,
+}
 
 impl DownloadProgress for CliProgress {
     fn size(&self, files: usize) {
-        if let Ok(mut inner) = self.0.lock() {
-            inner.total = files as u64;
-            inner.show_speed = false;
-            inner.show_bar = true;
-        }
+        self.with_bars(|bars| {
+            bars.files.total += files as u64;
+            bars.files.tick();
+        });
+    }
+    fn file_size(&self, bytes: usize) {
+        self.with_bars(|bars| {
+            bars.bytes.total += bytes as u64;
+            bars.bytes.tick();
+        });
+    }
+    fn progress(&self, bytes: usize) {
+        self.with_bars(|bars| {
+            bars.received += bytes as u64;
+            if bars.received > bars.bytes.total {
+                bars.bytes.total = bars.received;
+            }
+            bars.bytes.add(bytes as u64);
+        });
+    }
+    fn resumed(&self, bytes: usize) {
+        self.progress(bytes)
+    }
+    fn failed(&self, _: &str) {
+        self.with_bars(|bars| bars.failed += 1);
     }
-    fn progress(&self, _: usize) {}
     fn complete(&self) {
-        if let Ok(mut inner) = self.0.lock() {
-            inner.inc();
+        self.with_bars(|bars| {
+            bars.files.inc();
+        });
+    }
+    fn for_file(&self, file: &str) -> Self {
+        let name = Path::new(file)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file.to_string());
+        self.with_bars(|bars| {
+            bars.in_flight.push(name.clone());
+            bars.show_files();
+        });
+        CliProgress {
+            bars: self.bars.clone(),
+            file: Some(name),
         }
     }
-    fn for_file(&self, _: &str) -> Self {
-        CliProgress(self.0.clone())
+}
+
+impl Drop for CliProgress {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            self.with_bars(|bars| {
+                if let Some(index) = bars.in_flight.iter().position(|other| *other == file) {
+                    bars.in_flight.remove(index);
+                }
+                bars.show_files();
+            });
+        }
     }
 }
 
 impl CliProgress {
     fn new() -> Self {
-        let mut progress = ProgressBar::new(363);
-        progress.show_speed = false;
-        progress.show_time_left = false;
-        progress.format("[#> ]");
-        progress.message("Downloading Packs ");
-        CliProgress(Arc::new(Mutex::new(progress)))
+        let multi = MultiBar::new();
+        let mut files = multi.create_bar(0);
+        files.show_speed = false;
+        files.show_time_left = false;
+        files.format("[#> ]");
+        files.message("Downloading ");
+        let mut bytes = multi.create_bar(0);
+        bytes.set_units(Units::Bytes);
+        bytes.format("[#> ]");
+        bytes.message("Received    ");
+        let listener = thread::spawn(move || multi.listen());
+        CliProgress {
+            bars: Arc::new(Mutex::new(Bars {
+                files,
+                bytes,
+                received: 0,
+                in_flight: Vec::new(),
+                failed: 0,
+                listener: Some(listener),
+            })),
+            file: None,
+        }
+    }
+
+    fn with_bars<F: FnOnce(&mut Bars)>(&self, f: F) {
+        if let Ok(mut bars) = self.bars.lock() {
+            if bars.listener.is_some() {
+                f(&mut bars)
+            }
+        }
+    }
+
+    /// Draw the bars a last time, once the downloads are over.
+    fn finish(&self) {
+        let listener = self.bars.lock().ok().and_then(|mut bars| {
+            let listener = bars.listener.take();
+            if listener.is_some() {
+                bars.files.finish();
+                bars.bytes.finish();
+            }
+            listener
+        });
+        if let Some(listener) = listener {
+            let _ = listener.join();
+        }
+    }
+}
+
+/// Cancel the downloads of `conf` on Ctrl-C.
+fn cancel_on_interrupt(conf: &Config, logger: &Logger) {
+    let cancel = conf.download.cancel.clone();
+    ctrlc::set_handler(move || cancel.cancel()).ok_warn(logger);
+}
+
+fn remove_part_files(dir: &Path, depth: usize, logger: &Logger) {
+    let entries = match dir.read_dir().ok_warn(logger) {
+        Some(entries) => entries,
+        None => return,
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.is_dir() {
+            if depth > 0 {
+                remove_part_files(&path, depth - 1, logger);
+            }
+        } else if path.extension().map(|ext| ext == "part").unwrap_or(false) {
+            remove_file(&path).ok_warn(logger);
+        }
+    }
+}
+
+/// Once the downloads of `conf` are cancelled, remove the `.part` files
+/// they left in `dir`, down to packs in `Vendor/Name/`.
+fn remove_cancelled(conf: &Config, dir: &Path, logger: &Logger) {
+    if conf.download.cancel.is_cancelled() {
+        warn!(logger, "Cancelled; removing partial downloads");
+        remove_part_files(dir, 2, logger);
+    }
+}
+
+/// Print a row for each file that was not already up to date, then a
+/// count of each outcome.
+fn print_report(report: &UpdateReport, logger: &Logger) {
+    let changed: Vec<_> = report
+        .items
+        .iter()
+        .filter(|item| item.outcome != Outcome::Unchanged)
+        .collect();
+    if !changed.is_empty() {
+        println!("{:<12} {:>6} {:>10}  FILE", "OUTCOME", "STATUS", "BYTES");
+    }
+    for item in changed {
+        let status = item.status.map(|status| status.to_string()).unwrap_or_default();
+        let file = item.dest.file_name().unwrap_or_else(|| item.dest.as_os_str());
+        let mut line = format!(
+            "{:<12} {:>6} {:>10}  {}",
+            item.outcome.to_string(),
+            status,
+            item.bytes,
+            file.to_string_lossy()
+        );
+        if let Some(ref error) = item.error {
+            line.push_str(&format!(": {}", error));
+        }
+        println!("{}", line);
     }
+    let unchanged = report.count(Outcome::Unchanged);
+    if unchanged == report.items.len() {
+        info!(logger, "Already up to date");
+        return;
+    }
+    info!(logger, "{} downloaded, {} unchanged, {} failed, {} skipped as deprecated",
+          report.count(Outcome::Downloaded),
+          unchanged,
+          report.count(Outcome::Failed),
+          report.count(Outcome::Deprecated));
 }
 
 pub fn install_args() -> App<'static, 'static> {
@@ -65,9 +254,73 @@ pub fn install_args() -> App<'static, 'static> {
                 .takes_value(true)
                 .index(1)
                 .multiple(true)
+                .help("A PDSC file or an indexed Vendor.Pack, with an optional @version")
         )
 }
 
+/// Split `Vendor.Pack@1.2.3` into the pack and its version. Existing paths
+/// are never split.
+fn split_version(input: &str) -> Result<(&str, Option<Version>), Error> {
+    if Path::new(input).exists() {
+        return Ok((input, None));
+    }
+    match input.rfind('@') {
+        Some(at) => Ok((&input[..at], Some(input[at + 1..].parse()?))),
+        None => Ok((input, None)),
+    }
+}
+
+/// Find the PDSC of `Vendor.Pack` in the pack store, preferring the one
+/// with the newest release when several were downloaded.
+fn find_indexed_pdsc(conf: &Config, pack: &str, l: &Logger) -> Result<Package, Error> {
+    let prefix = format!("{}.", pack);
+    let mut found: Option<Package> = None;
+    for entry in conf.pack_store.read_dir()? {
+        let path = entry?.path();
+        let is_candidate = path.extension().map(|ext| ext == "pdsc").unwrap_or(false)
+            && path.file_name()
+                .map(|name| name.to_string_lossy().starts_with(&prefix))
+                .unwrap_or(false);
+        if !is_candidate {
+            continue;
+        }
+        match Package::from_path(&path, l) {
+            Ok(pdsc) => {
+                if format!("{}.{}", pdsc.vendor, pdsc.name) != pack {
+                    continue;
+                }
+                let newer = match found {
+                    Some(ref prev) => {
+                        pdsc.releases.latest_release().version
+                            > prev.releases.latest_release().version
+                    }
+                    None => true,
+                };
+                if newer {
+                    found = Some(pdsc);
+                }
+            }
+            Err(e) => error!(l, "parsing {:?}: {}", path, e),
+        }
+    }
+    found.ok_or_else(|| err_msg(format!("pack {} is not in the index; try `cmsis update`", pack)))
+}
+
+fn resolve_install(
+    conf: &Config,
+    input: &str,
+    l: &Logger,
+) -> Result<(Package, Option<Version>), Error> {
+    let (pack, version) = split_version(input)?;
+    let path = Path::new(pack);
+    let pdsc = if path.exists() {
+        Package::from_path(path, l).map_err(|e| err_msg(e.to_string()))?
+    } else {
+        find_indexed_pdsc(conf, pack, l)?
+    };
+    Ok((pdsc, version))
+}
+
 pub fn install_command<'a>(
     conf: &Config,
     args: &ArgMatches<'a>,
@@ -75,25 +328,402 @@ pub fn install_command<'a>(
 ) -> Result<(), Error> {
     let pdsc_list: Vec<_> = args.values_of("PDSC")
         .unwrap()
-        .filter_map(|input| Package::from_path(Path::new(input), logger).ok())
+        .filter_map(|input| match resolve_install(conf, input, logger) {
+            Ok(found) => Some(found),
+            Err(e) => {
+                error!(logger, "{}: {}", input, e);
+                None
+            }
+        })
         .collect();
+    let releases = pdsc_list
+        .iter()
+        .map(|(pdsc, version)| match version {
+            Some(version) => PackRelease::new(pdsc, version),
+            None => Ok(PackRelease::latest(pdsc)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
     let progress = CliProgress::new();
-    let updated = install(conf, pdsc_list.iter(), logger, progress)?;
-    let num_updated = updated.iter().map(|_| 1).sum::<u32>();
-    match num_updated {
-        0 => {
-            info!(logger, "Already up to date");
-        }
-        1 => {
-            info!(logger, "Updated 1 package");
-        }
-        _ => {
-            info!(logger, "Updated {} package", num_updated);
+    cancel_on_interrupt(conf, logger);
+    let updated = install(conf, releases, logger, progress.clone());
+    progress.finish();
+    remove_cancelled(conf, &conf.pack_store, logger);
+    let updated = updated?;
+    print_report(&updated, logger);
+    match updated.count(Outcome::Failed) {
+        0 => Ok(()),
+        failed => Err(err_msg(format!("{} of the packs could not be installed", failed))),
+    }
+}
+
+fn lockfile_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("lockfile")
+        .long("lockfile")
+        .takes_value(true)
+        .default_value("cmsis.lock")
+        .help("Path of the lockfile")
+}
+
+pub fn lock_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("lock")
+        .about("Record the installed packs, with their checksums, in a lockfile")
+        .version("0.1.0")
+        .arg(lockfile_arg())
+}
+
+pub fn lock_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let lockfile = Path::new(args.value_of("lockfile").unwrap());
+    let pdscs = read_pdscs(conf, None, logger);
+    let lock = LockFile::from_store(conf, pdscs.iter())?;
+    lock.write(lockfile)?;
+    info!(logger, "Locked {} packs in {}", lock.packs.len(), lockfile.display());
+    Ok(())
+}
+
+pub fn sync_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("sync")
+        .about("Install exactly the packs recorded in a lockfile")
+        .version("0.1.0")
+        .arg(lockfile_arg())
+}
+
+pub fn sync_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let lock = LockFile::read(Path::new(args.value_of("lockfile").unwrap()))?;
+    let progress = CliProgress::new();
+    cancel_on_interrupt(conf, logger);
+    let installed = install(conf, lock.packs.iter(), logger, progress.clone());
+    progress.finish();
+    remove_cancelled(conf, &conf.pack_store, logger);
+    let installed = installed?;
+    for item in installed.failures() {
+        if let Some(ref error) = item.error {
+            error!(logger, "{}: {}", item.source, error);
         }
     }
+    if !installed.is_success() {
+        let failed = installed.count(Outcome::Failed);
+        return Err(err_msg(format!("{} of the locked packs could not be installed", failed)));
+    }
+    lock.verify(conf)?;
+    info!(logger, "{} packs match the lockfile", lock.packs.len());
+    Ok(())
+}
+
+fn dry_run_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("dry-run")
+        .long("dry-run")
+        .help("List what would be removed, without removing it")
+}
+
+/// List `removals` with the bytes they take up, then delete them unless
+/// this is a dry run.
+fn apply_removals<'a>(
+    conf: &Config,
+    removals: &[Removal],
+    args: &ArgMatches<'a>,
+    logger: &Logger,
+) -> Result<(), Error> {
+    if !removals.is_empty() {
+        println!("{:<16} {:>10}  PATH", "REASON", "BYTES");
+    }
+    for removal in removals {
+        println!(
+            "{:<16} {:>10}  {}",
+            removal.reason.to_string(),
+            removal.bytes,
+            removal.path.display()
+        );
+    }
+    if args.is_present("dry-run") {
+        let bytes: u64 = removals.iter().map(|removal| removal.bytes).sum();
+        info!(logger, "Would remove {} paths, freeing {} bytes", removals.len(), bytes);
+        return Ok(());
+    }
+    let freed = delete(conf, removals, logger)?;
+    info!(logger, "Removed {} paths, freeing {} bytes", removals.len(), freed);
+    Ok(())
+}
+
+pub fn remove_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("remove")
+        .about("Remove installed packs from the pack store")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("PACK")
+                .required(true)
+                .index(1)
+                .multiple(true)
+                .help("An installed Vendor.Pack, with an optional @version; all versions by default"),
+        )
+        .arg(dry_run_arg())
+}
+
+pub fn remove_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let mut removals = Vec::new();
+    for input in args.values_of("PACK").unwrap() {
+        let (pack, version) = split_version(input)?;
+        let (vendor, name) = match pack.find('.') {
+            Some(dot) => (&pack[..dot], &pack[dot + 1..]),
+            None => return Err(err_msg(format!("{} is not of the form Vendor.Pack", pack))),
+        };
+        removals.extend(plan_remove(conf, vendor, name, version.as_ref())?);
+    }
+    apply_removals(conf, &removals, args, logger)
+}
+
+pub fn gc_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("gc")
+        .about("Remove old pack versions, partial downloads and unlisted PDSCs from the pack store")
+        .version("0.1.0")
+        .arg(lockfile_arg().help("Path of a lockfile whose versions are kept, if it exists"))
+        .arg(dry_run_arg())
+}
+
+pub fn gc_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let lockfile = Path::new(args.value_of("lockfile").unwrap());
+    let lock = if lockfile.exists() {
+        Some(LockFile::read(lockfile)?)
+    } else {
+        None
+    };
+    let vidx_list = conf.read_vidx_list(logger);
+    let removals = plan_gc(conf, vidx_list, lock.as_ref(), logger)?;
+    if removals.is_empty() {
+        info!(logger, "Nothing to collect");
+        return Ok(());
+    }
+    apply_removals(conf, &removals, args, logger)
+}
+
+pub fn list_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("list")
+        .about("List the packs in the pack store")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("outdated")
+                .long("outdated")
+                .help("Only list installed packs with a newer release, as of the last update"),
+        )
+        .arg(Arg::with_name("json").long("json").help("Print the list as JSON"))
+}
+
+pub fn list_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let registry = Registry::scan(conf, logger)?;
+    let packs = if args.is_present("outdated") {
+        registry.outdated()
+    } else {
+        registry.packs()
+    };
+    if args.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&packs)?);
+        return Ok(());
+    }
+    if packs.is_empty() {
+        info!(logger, "No packs found in {}", conf.pack_store.display());
+        return Ok(());
+    }
+    println!("{:<40} {:<12} {:<10} {:>10}  {:<10}  LATEST", "PACK", "VERSION", "STATE", "BYTES", "DATE");
+    for pack in packs {
+        let state = match pack.state {
+            PackState::Installed => "installed",
+            PackState::PdscOnly => "pdsc",
+        };
+        let latest = if pack.latest > pack.version {
+            pack.latest.to_string()
+        } else {
+            String::new()
+        };
+        println!(
+            "{:<40} {:<12} {:<10} {:>10}  {:<10}  {}",
+            format!("{}.{}", pack.vendor, pack.name),
+            pack.version.to_string(),
+            state,
+            pack.bytes,
+            pack.date(),
+            latest
+        );
+    }
+    Ok(())
+}
+
+pub fn mirror_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("mirror")
+        .about("Mirror the pack index, and selected packs, into a directory")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("DIR")
+                .required(true)
+                .index(1)
+                .help("Directory to mirror into"),
+        )
+        .arg(
+            Arg::with_name("PACK")
+                .index(2)
+                .multiple(true)
+                .help("A Vendor.Pack to mirror, with an optional @version"),
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .takes_value(true)
+                .help("URL the mirror will be served from; defaults to a file:// URL of DIR"),
+        )
+}
+
+pub fn mirror_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let dir = Path::new(args.value_of("DIR").unwrap());
+    create_dir_all(dir)?;
+    let base_url = match args.value_of("url") {
+        Some(url) if url.ends_with('/') => url.to_string(),
+        Some(url) => format!("{}/", url),
+        None => format!("{}/", file_url(&dir.canonicalize()?)),
+    };
+    let packs = args.values_of("PACK")
+        .map(|packs| {
+            packs
+                .map(|pack| split_version(pack).map(|(pack, version)| (pack.to_string(), version)))
+                .collect::<Result<Vec<_>, Error>>()
+        })
+        .unwrap_or_else(|| Ok(Vec::new()))?;
+    let vidx_list = conf.read_vidx_list(logger);
+    let progress = CliProgress::new();
+    cancel_on_interrupt(conf, logger);
+    let mirrored = mirror(conf, vidx_list, &packs, dir, &base_url, logger, progress.clone());
+    progress.finish();
+    remove_cancelled(conf, dir, logger);
+    let mirrored = mirrored?;
+    print_report(&mirrored, logger);
+    if !mirrored.is_success() {
+        let failed = mirrored.count(Outcome::Failed);
+        return Err(err_msg(format!("{} of the files could not be mirrored", failed)));
+    }
+    info!(logger, "Mirrored into {}", dir.display());
     Ok(())
 }
 
+#[cfg(feature = "serve")]
+pub fn serve_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("serve")
+        .about("Serve the pack store as a pack index over HTTP")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("addr")
+                .long("addr")
+                .takes_value(true)
+                .default_value("127.0.0.1:8080")
+                .help("Address to listen on"),
+        )
+}
+
+#[cfg(feature = "serve")]
+pub fn serve_command<'a>(conf: &Config, args: &ArgMatches<'a>, logger: &Logger) -> Result<(), Error> {
+    let addr: SocketAddr = args.value_of("addr").unwrap().parse()?;
+    let server = PackServer::bind(conf, &addr, logger)?;
+    info!(
+        logger,
+        "Serving {} at http://{}/index.pidx",
+        conf.pack_store.display(),
+        server.local_addr()?
+    );
+    server.run()
+}
+
+/// Options of every subcommand that downloads.
+pub fn download_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("jobs")
+            .long("jobs")
+            .takes_value(true)
+            .global(true)
+            .help("Downloads to run at once"),
+        Arg::with_name("per-host")
+            .long("per-host")
+            .takes_value(true)
+            .global(true)
+            .help("Downloads to run at once from a single host"),
+        Arg::with_name("connect-timeout")
+            .long("connect-timeout")
+            .takes_value(true)
+            .global(true)
+            .help("Seconds to wait for a server to respond; 0 waits forever"),
+        Arg::with_name("read-timeout")
+            .long("read-timeout")
+            .takes_value(true)
+            .global(true)
+            .help("Seconds to wait for more of a download; 0 waits forever"),
+        Arg::with_name("retries")
+            .long("retries")
+            .takes_value(true)
+            .global(true)
+            .help("Times to retry a download that failed on the server or network"),
+        Arg::with_name("skip-deprecated")
+            .long("skip-deprecated")
+            .global(true)
+            .help("Do not download the PDSCs that the index marks deprecated"),
+        Arg::with_name("proxy")
+            .long("proxy")
+            .takes_value(true)
+            .global(true)
+            .help("Proxy URL for downloads; defaults to HTTP_PROXY and HTTPS_PROXY by scheme"),
+        Arg::with_name("ca-file")
+            .long("ca-file")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .global(true)
+            .help("PEM file of extra root certificates to trust"),
+    ]
+}
+
+fn parsed_arg<'a, T>(args: &ArgMatches<'a>, name: &str) -> Result<Option<T>, Error>
+    where T: FromStr,
+          T::Err: ::std::error::Error + Send + Sync + 'static,
+{
+    match args.value_of(name) {
+        Some(value) => Ok(Some(value.parse()?)),
+        None => Ok(None),
+    }
+}
+
+fn timeout(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// The configuration, with the download options of `download_args`.
+pub fn config_from_args<'a>(args: &ArgMatches<'a>) -> Result<Config, Error> {
+    let mut download = DownloadOptions::default();
+    if let Some(jobs) = parsed_arg(args, "jobs")? {
+        download.max_concurrent = jobs;
+    }
+    if let Some(per_host) = parsed_arg(args, "per-host")? {
+        download.per_host = per_host;
+    }
+    if let Some(secs) = parsed_arg(args, "connect-timeout")? {
+        download.connect_timeout = timeout(secs);
+    }
+    if let Some(secs) = parsed_arg(args, "read-timeout")? {
+        download.read_timeout = timeout(secs);
+    }
+    if let Some(retries) = parsed_arg(args, "retries")? {
+        download.retries = retries;
+    }
+    download.skip_deprecated = args.is_present("skip-deprecated");
+    let mut builder = ConfigBuilder::new().with_download_options(download);
+    if let Some(url) = args.value_of("proxy") {
+        let mut proxy = Proxy::new(url);
+        proxy.no_proxy = Proxy::from_env().map(|env| env.no_proxy).unwrap_or_default();
+        builder = builder.with_proxy(proxy);
+    }
+    for pem in args.values_of("ca-file").into_iter().flatten() {
+        builder = builder.with_ca_roots(pem);
+    }
+    builder.build()
+}
+
 pub fn update_args<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("update")
         .about("Update CMSIS PDSC files for indexing")
@@ -106,20 +736,16 @@ pub fn update_command<'a>(conf: &Config, _: &ArgMatches<'a>, logger: &Logger) ->
         info!(logger, "Updating registry from `{}`", url);
     }
     let progress = CliProgress::new();
-    let updated = update(conf, vidx_list, logger, progress)?;
-    let num_updated = updated.iter().map(|_| 1).sum::<u32>();
-    match num_updated {
-        0 => {
-            info!(logger, "Already up to date");
-        }
-        1 => {
-            info!(logger, "Updated 1 package");
-        }
-        _ => {
-            info!(logger, "Updated {} package", num_updated);
-        }
+    cancel_on_interrupt(conf, logger);
+    let updated = update(conf, vidx_list, logger, progress.clone());
+    progress.finish();
+    remove_cancelled(conf, &conf.pack_store, logger);
+    let updated = updated?;
+    print_report(&updated, logger);
+    match updated.count(Outcome::Failed) {
+        0 => Ok(()),
+        failed => Err(err_msg(format!("{} of the PDSC files could not be updated", failed))),
     }
-    Ok(())
 }
 
 
@@ -144,23 +770,20 @@ pub fn dump_devices_args<'a, 'b>() -> App<'a, 'b> {
 
 }
 
-pub fn dump_devices_command<'a>(
-    c: &Config,
-    args: &ArgMatches<'a>,
-    l: &Logger,
-) -> Result<(), Error> {
-    let files = args.value_of("INPUT").map(|input| {
+fn read_pdscs(c: &Config, input: Option<&str>, l: &Logger) -> Vec<Package> {
+    let files = input.map(|input| {
         vec![Box::new(Path::new(input)).to_path_buf()]
     });
     let filenames = files
         .or_else(|| {
             c.pack_store.read_dir().ok().map(|rd| {
                 rd.flat_map(|dirent| dirent.into_iter().map(|p| p.path()))
+                    .filter(|p| p.extension().map(|ext| ext == "pdsc").unwrap_or(false))
                     .collect()
             })
         })
         .unwrap();
-    let pdscs = filenames
+    filenames
         .into_iter()
         .flat_map(|filename| match Package::from_path(&filename, &l) {
             Ok(c) => Some(c),
@@ -169,12 +792,103 @@ pub fn dump_devices_command<'a>(
                 None
             }
         })
-        .collect::<Vec<Package>>();
+        .collect()
+}
+
+pub fn dump_devices_command<'a>(
+    c: &Config,
+    args: &ArgMatches<'a>,
+    l: &Logger,
+) -> Result<(), Error> {
+    let pdscs = read_pdscs(c, args.value_of("INPUT"), l);
     let to_ret = dump_devices(&pdscs, args.value_of("devices"), args.value_of("boards"), l);
     debug!(l, "exiting");
     to_ret
 }
 
+pub fn components_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("components")
+        .about("Dump the components that apply to a device as json")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .required(true)
+                .takes_value(true)
+                .help("Name of the device, as the Dname of a pack"),
+        )
+        .arg(
+            Arg::with_name("compiler")
+                .long("compiler")
+                .required(true)
+                .takes_value(true)
+                .help("Toolchain to build with, such as GCC, ARMCC or IAR"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to dump components from")
+                .index(1),
+        )
+}
+
+pub fn components_command<'a>(
+    c: &Config,
+    args: &ArgMatches<'a>,
+    l: &Logger,
+) -> Result<(), Error> {
+    let pdscs = read_pdscs(c, args.value_of("INPUT"), l);
+    let device = args.value_of("device").unwrap();
+    let compiler = args.value_of("compiler").unwrap();
+    println!("{}", dumps_components_for_device(&pdscs, device, compiler, l)?);
+    debug!(l, "exiting");
+    Ok(())
+}
+
+pub fn boards_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("boards")
+        .about("List the boards described by packs")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .takes_value(true)
+                .help("Only list boards with this device mounted or compatible"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to list boards from")
+                .index(1),
+        )
+}
+
+pub fn boards_command<'a>(c: &Config, args: &ArgMatches<'a>, l: &Logger) -> Result<(), Error> {
+    let pdscs = read_pdscs(c, args.value_of("INPUT"), l);
+    let device = args.value_of("device");
+    let mut boards: Vec<&Board> = pdscs
+        .iter()
+        .flat_map(|pdsc| pdsc.boards.iter())
+        .filter(|board| device.map(|dev| board.supports_device(dev)).unwrap_or(true))
+        .collect();
+    boards.sort_by(|a, b| (&a.vendor, &a.name).cmp(&(&b.vendor, &b.name)));
+    for board in boards {
+        let mut line = String::new();
+        if let Some(ref vendor) = board.vendor {
+            line.push_str(vendor);
+            line.push_str("::");
+        }
+        line.push_str(&board.name);
+        if let Some(ref revision) = board.revision {
+            line.push_str(&format!(" ({})", revision));
+        }
+        if !board.mounted_devices.is_empty() {
+            line.push_str(&format!(": {}", board.mounted_devices.join(", ")));
+        }
+        println!("{}", line);
+    }
+    debug!(l, "exiting");
+    Ok(())
+}
+
 pub fn check_args<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("check")
         .about(
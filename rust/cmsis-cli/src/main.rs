@@ -9,34 +9,63 @@ extern crate pdsc;
 extern crate failure;
 extern crate cmsis_cli;
 
-use pack_index::config::Config;
 use cmsis_cli::{
+    config_from_args,
+    download_args,
     update_args,
     update_command,
     install_args,
     install_command,
+    lock_args,
+    lock_command,
+    mirror_args,
+    mirror_command,
+    sync_args,
+    sync_command,
+    remove_args,
+    remove_command,
+    gc_args,
+    gc_command,
+    list_args,
+    list_command,
     check_args,
     check_command,
     dump_devices_args,
-    dump_devices_command
+    dump_devices_command,
+    components_args,
+    components_command,
+    boards_args,
+    boards_command
 };
+#[cfg(feature = "serve")]
+use cmsis_cli::{serve_args, serve_command};
 use clap::{Arg, App};
 use slog::Drain;
-use failure::Error;
 
 fn main() {
     // Note: This argument parser should do nothing more than handle
-    let matches = App::new("CMSIS Pack manager and builder")
+    let app = App::new("CMSIS Pack manager and builder")
         .version("0.1.0")
         .author("Jimmy Brisson")
         .arg(Arg::with_name("verbose").short("v").help(
             "Sets the level of verbosity",
         ))
+        .args(&download_args())
         .subcommand(update_args())
         .subcommand(check_args())
         .subcommand(dump_devices_args())
         .subcommand(install_args())
-        .get_matches();
+        .subcommand(lock_args())
+        .subcommand(sync_args())
+        .subcommand(mirror_args())
+        .subcommand(remove_args())
+        .subcommand(gc_args())
+        .subcommand(list_args())
+        .subcommand(components_args())
+        .subcommand(boards_args());
+    #[cfg(feature = "serve")]
+    let app = app.subcommand(serve_args());
+    let matches = app.get_matches();
 
     let decorator = slog_term::TermDecorator::new().build();
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
@@ -47,29 +76,71 @@ fn main() {
 
     match matches.subcommand() {
         ("update", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
+            config_from_args(sub_m)
                 .and_then(|config| update_command(&config, sub_m, &log))
                 .unwrap();
         }
         ("install", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
+            config_from_args(sub_m)
                 .and_then(|config| install_command(&config, sub_m, &log))
                 .unwrap();
         }
+        ("lock", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| lock_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        ("sync", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| sync_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        ("mirror", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| mirror_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        ("remove", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| remove_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        ("gc", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| gc_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        ("list", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| list_command(&config, sub_m, &log))
+                .unwrap();
+        }
         ("check", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
+            config_from_args(sub_m)
                 .and_then(|config| check_command(&config, sub_m, &log))
                 .unwrap();
         }
         ("dump-devices", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
+            config_from_args(sub_m)
                 .and_then(|config| dump_devices_command(&config, sub_m, &log))
                 .unwrap();
         }
+        ("components", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| components_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        ("boards", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| boards_command(&config, sub_m, &log))
+                .unwrap();
+        }
+        #[cfg(feature = "serve")]
+        ("serve", Some(sub_m)) => {
+            config_from_args(sub_m)
+                .and_then(|config| serve_command(&config, sub_m, &log))
+                .unwrap();
+        }
         (bad_command, Some(_)) => {
             println!("I did not understand the command {}", bad_command);
         }
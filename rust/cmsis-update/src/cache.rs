@@ -0,0 +1,229 @@
+//! HTTP cache metadata, for conditional requests.
+//!
+//! The `ETag` and `Last-Modified` of every downloaded URL are kept in
+//! `.cache/http.json` inside the pack store. Index files are not stored
+//! anywhere else, so their bodies are cached there as well.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use failure::Error;
+use hyper::Headers;
+use hyper::header::{ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, IfRange, LastModified};
+use ring::digest::{digest, SHA256};
+use serde_json;
+use slog::Logger;
+
+use pack_index::config::Config;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CacheEntry {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// The validators of an unfinished download, to resume it by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    partial: Option<Box<CacheEntry>>,
+}
+
+impl CacheEntry {
+    fn from_headers(headers: &Headers) -> Self {
+        CacheEntry {
+            etag: headers.get::<ETag>().map(|etag| etag.to_string()),
+            last_modified: headers.get::<LastModified>().map(|date| date.to_string()),
+            partial: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+pub(crate) struct HttpCache {
+    dir: PathBuf,
+    entries: Mutex<BTreeMap<String, CacheEntry>>,
+}
+
+impl HttpCache {
+    /// Load the cache of a pack store. A missing or unreadable cache is
+    /// treated as empty.
+    pub fn load(config: &Config, l: &Logger) -> Self {
+        let dir = config.pack_store.join(".cache");
+        let entries = File::open(dir.join("http.json"))
+            .map_err(Error::from)
+            .and_then(|fd| Ok(serde_json::from_reader(fd)?))
+            .unwrap_or_else(|e| {
+                debug!(l, "Starting with an empty HTTP cache: {}", e);
+                BTreeMap::new()
+            });
+        HttpCache {
+            dir,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        create_dir_all(&self.dir)?;
+        let mut fd = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.dir.join("http.json"))?;
+        if let Ok(entries) = self.entries.lock() {
+            serde_json::to_writer_pretty(&mut fd, &*entries)?;
+            fd.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Add `If-None-Match` and `If-Modified-Since` for a URL, if it was
+    /// downloaded before.
+    pub fn conditional_headers(&self, url: &str, headers: &mut Headers) {
+        let entry = match self.entries.lock() {
+            Ok(entries) => entries.get(url).cloned(),
+            Err(_) => None,
+        };
+        if let Some(entry) = entry {
+            if let Some(etag) = entry.etag.and_then(|etag| etag.parse().ok()) {
+                headers.set(IfNoneMatch::Items(vec![etag]));
+            }
+            if let Some(date) = entry.last_modified.and_then(|date| date.parse::<HttpDate>().ok()) {
+                headers.set(IfModifiedSince(date));
+            }
+        }
+    }
+
+    /// Remember the validators of a successful response. This forgets
+    /// those of an unfinished download of the same URL.
+    pub fn record(&self, url: &str, headers: &Headers) {
+        let entry = CacheEntry::from_headers(headers);
+        if let Ok(mut entries) = self.entries.lock() {
+            if entry.is_empty() {
+                entries.remove(url);
+            } else {
+                entries.insert(url.to_string(), entry);
+            }
+        }
+    }
+
+    /// Remember the validators of a response whose body is saved as it
+    /// arrives, so that an interrupted download can be resumed.
+    pub fn record_partial(&self, url: &str, headers: &Headers) {
+        let partial = CacheEntry::from_headers(headers);
+        if let Ok(mut entries) = self.entries.lock() {
+            let entry = entries.entry(url.to_string()).or_default();
+            entry.partial = if partial.is_empty() { None } else { Some(Box::new(partial)) };
+        }
+    }
+
+    /// Add `If-Range` for resuming an unfinished download of a URL, so that
+    /// the server sends all of it again if it changed since. Returns false
+    /// when there is no validator to resume by.
+    pub fn if_range(&self, url: &str, headers: &mut Headers) -> bool {
+        let partial = match self.entries.lock() {
+            Ok(entries) => entries.get(url).and_then(|entry| entry.partial.clone()),
+            Err(_) => None,
+        };
+        let partial = match partial {
+            Some(partial) => partial,
+            None => return false,
+        };
+        // Weak tags may not be used in `If-Range`.
+        match partial.etag.and_then(|etag| etag.parse::<EntityTag>().ok()) {
+            Some(ref etag) if !etag.weak => {
+                headers.set(IfRange::EntityTag(etag.clone()));
+                return true;
+            }
+            _ => (),
+        }
+        match partial.last_modified.and_then(|date| date.parse::<HttpDate>().ok()) {
+            Some(date) => {
+                headers.set(IfRange::Date(date));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn body_path(&self, url: &str) -> PathBuf {
+        let name: String = digest(&SHA256, url.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.dir.join(name)
+    }
+
+    /// Headers for fetching a URL whose body is cached here.
+    pub fn body_headers(&self, url: &str) -> Headers {
+        let mut headers = Headers::new();
+        if self.body_path(url).exists() {
+            self.conditional_headers(url, &mut headers);
+        }
+        headers
+    }
+
+    pub fn cached_body(&self, url: &str) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        File::open(self.body_path(url))?.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    pub fn store_body(&self, url: &str, headers: &Headers, body: &[u8]) -> io::Result<()> {
+        create_dir_all(&self.dir)?;
+        File::create(self.body_path(url))?.write_all(body)?;
+        self.record(url, headers);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::Discard;
+
+    use testing::TempStore;
+
+    #[test]
+    fn conditional_requests() {
+        let log = Logger::root(Discard, o!());
+        let store = TempStore::new("cache");
+        let config = &store.config;
+        let url = "http://localhost/index.pidx";
+        let cache = HttpCache::load(config, &log);
+        assert_eq!(cache.body_headers(url).len(), 0);
+
+        let mut response = Headers::new();
+        response.set(ETag(EntityTag::strong("v1".to_string())));
+        response.set(LastModified("Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap()));
+        cache.store_body(url, &response, b"<index/>").unwrap();
+        cache.save().unwrap();
+
+        let cache = HttpCache::load(config, &log);
+        let headers = cache.body_headers(url);
+        assert_eq!(
+            headers.get::<IfNoneMatch>(),
+            Some(&IfNoneMatch::Items(vec![EntityTag::strong("v1".to_string())]))
+        );
+        assert!(headers.has::<IfModifiedSince>());
+        assert_eq!(cache.cached_body(url).unwrap(), b"<index/>".to_vec());
+
+        let mut other = Headers::new();
+        cache.conditional_headers("http://localhost/other.pidx", &mut other);
+        assert_eq!(other.len(), 0);
+
+        let mut resume = Headers::new();
+        assert!(!cache.if_range(url, &mut resume));
+        cache.record_partial(url, &response);
+        assert!(cache.if_range(url, &mut resume));
+        assert_eq!(
+            resume.get::<IfRange>(),
+            Some(&IfRange::EntityTag(EntityTag::strong("v1".to_string())))
+        );
+        cache.record(url, &response);
+        assert!(!cache.if_range(url, &mut Headers::new()));
+    }
+}
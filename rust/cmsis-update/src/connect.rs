@@ -0,0 +1,239 @@
+//! Connections for downloads, through a proxy when one is configured.
+//!
+//! Plain `http` requests are sent to the proxy with their absolute URI;
+//! `https` ones go through a `CONNECT` tunnel.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use failure::{err_msg, Error};
+use futures::future::{err, loop_fn, Loop};
+use futures::prelude::Future;
+use hyper::{Body, Client, Uri};
+use hyper::client::{HttpConnector, Service};
+use hyper_rustls::{HttpsConnector, MaybeHttpsStream};
+use rustls::ClientConfig;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::io::{read, write_all};
+use tokio_rustls::ClientConfigExt;
+use webpki::{DNSName, DNSNameRef};
+use webpki_roots;
+
+use pack_index::config::{Config, DownloadOptions, Proxy};
+
+/// The built-in roots, and those of `DownloadOptions::ca_roots`.
+fn tls_config(options: &DownloadOptions) -> Result<ClientConfig, Error> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    for path in &options.ca_roots {
+        let mut pem = BufReader::new(File::open(path)?);
+        match config.root_store.add_pem_file(&mut pem) {
+            Ok((added, _)) if added > 0 => {}
+            _ => return Err(err_msg(format!("no certificates found in {}", path.display()))),
+        }
+    }
+    Ok(config)
+}
+
+/// Is `uri` reached through `proxy`?
+pub(crate) fn is_proxied(proxy: &Option<Proxy>, uri: &Uri) -> bool {
+    match (proxy.as_ref(), uri.scheme(), uri.host()) {
+        (Some(proxy), Some(scheme), Some(host)) => proxy.url_for(scheme, host).is_some(),
+        _ => false,
+    }
+}
+
+fn parse_proxy(url: &Option<String>) -> Result<Option<Uri>, Error> {
+    match *url {
+        Some(ref url) => Ok(Some(url.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Ask the proxy on `tcp` for a tunnel to `authority`.
+fn tunnel(tcp: TcpStream, authority: String) -> impl Future<Item = TcpStream, Error = io::Error> {
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", authority);
+    write_all(tcp, request)
+        .and_then(|(tcp, _)| {
+            loop_fn((tcp, Vec::new()), |(tcp, mut head)| {
+                read(tcp, vec![0; 1024]).and_then(move |(tcp, buf, len)| {
+                    if len == 0 {
+                        let msg = "proxy closed the connection";
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+                    }
+                    head.extend_from_slice(&buf[..len]);
+                    if head.windows(4).any(|end| end == b"\r\n\r\n") {
+                        Ok(Loop::Break((tcp, head)))
+                    } else if head.len() > 16 * 1024 {
+                        let msg = "proxy response is too long";
+                        Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+                    } else {
+                        Ok(Loop::Continue((tcp, head)))
+                    }
+                })
+            })
+        })
+        .and_then(|(tcp, head)| {
+            let head = String::from_utf8_lossy(&head);
+            let status = head.lines().next().unwrap_or("");
+            if status.split_whitespace().nth(1) == Some("200") {
+                Ok(tcp)
+            } else {
+                let msg = format!("proxy refused the tunnel: {}", status);
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, msg))
+            }
+        })
+}
+
+/// Connects directly, or through the proxy of the `DownloadOptions`.
+pub(crate) struct Connector {
+    http: HttpConnector,
+    https: HttpsConnector,
+    tls: Arc<ClientConfig>,
+    proxy: Option<Proxy>,
+    http_proxy: Option<Uri>,
+    https_proxy: Option<Uri>,
+}
+
+impl Connector {
+    pub(crate) fn new(options: &DownloadOptions, handle: &Handle) -> Result<Self, Error> {
+        let tls = tls_config(options)?;
+        let mut http = HttpConnector::new(4, handle);
+        http.enforce_http(false);
+        let (http_proxy, https_proxy) = match options.proxy {
+            Some(ref proxy) => (parse_proxy(&proxy.http)?, parse_proxy(&proxy.https)?),
+            None => (None, None),
+        };
+        Ok(Connector {
+            https: HttpsConnector::from((http.clone(), tls.clone())),
+            http,
+            tls: Arc::new(tls),
+            proxy: options.proxy.clone(),
+            http_proxy,
+            https_proxy,
+        })
+    }
+}
+
+impl Service for Connector {
+    type Request = Uri;
+    type Response = MaybeHttpsStream;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = MaybeHttpsStream, Error = io::Error>>;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        let proxy_uri = if uri.scheme() == Some("https") {
+            &self.https_proxy
+        } else {
+            &self.http_proxy
+        };
+        let proxy_uri = match *proxy_uri {
+            Some(ref proxy_uri) if is_proxied(&self.proxy, &uri) => proxy_uri.clone(),
+            _ => return Box::new(self.https.call(uri)),
+        };
+        if uri.scheme() != Some("https") {
+            return Box::new(self.http.call(proxy_uri).map(MaybeHttpsStream::Http));
+        }
+        let host = uri.host().unwrap_or("").to_string();
+        let domain: DNSName = match DNSNameRef::try_from_ascii_str(&host) {
+            Ok(domain) => domain.into(),
+            Err(_) => {
+                let msg = format!("invalid host name {}", host);
+                return Box::new(err(io::Error::new(io::ErrorKind::InvalidInput, msg)));
+            }
+        };
+        let authority = format!("{}:{}", host, uri.port().unwrap_or(443));
+        let tls = self.tls.clone();
+        Box::new(
+            self.http
+                .call(proxy_uri)
+                .and_then(move |tcp| tunnel(tcp, authority))
+                .and_then(move |tcp| tls.connect_async(domain.as_ref(), tcp))
+                .map(MaybeHttpsStream::Https),
+        )
+    }
+}
+
+/// A client for the downloads of `config`.
+pub(crate) fn client(config: &Config, handle: &Handle) -> Result<Client<Connector, Body>, Error> {
+    Ok(Client::configure()
+        .keep_alive(true)
+        .connector(Connector::new(&config.download, handle)?)
+        .build(handle))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+    use hyper::{Headers, StatusCode};
+    use slog::{Discard, Logger};
+    use tokio_core::reactor::Core;
+
+    use redirect::ClientRedirExt;
+    use testing::TempStore;
+
+    /// A proxy answering one request with `response`, and reporting the
+    /// request it was sent.
+    fn proxy(response: &'static str) -> (Proxy, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = Proxy::new(format!("http://{}", listener.local_addr().unwrap()));
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let len = stream.read(&mut request).unwrap();
+            sender.send(String::from_utf8_lossy(&request[..len]).into_owned()).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        (proxy, receiver)
+    }
+
+    fn store(proxy: Proxy) -> TempStore {
+        let mut store = TempStore::new("connect");
+        store.config.download.proxy = Some(proxy);
+        store
+    }
+
+    #[test]
+    fn bypassed_hosts() {
+        let mut proxy = Proxy::new("http://proxy:3128");
+        proxy.no_proxy = vec!["localhost".to_string(), ".corp.example".to_string()];
+        assert!(proxy.is_used_for("www.keil.com"));
+        assert!(!proxy.is_used_for("localhost"));
+        assert!(!proxy.is_used_for("packs.corp.example"));
+        assert!(!proxy.is_used_for("corp.example"));
+        assert!(proxy.is_used_for("notcorp.example"));
+    }
+
+    #[test]
+    fn http_through_proxy() {
+        let logger = Logger::root(Discard, o!());
+        let mut core = Core::new().unwrap();
+        let (proxy, requests) = proxy("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let store = store(proxy);
+        let client = client(&store.config, &core.handle()).unwrap();
+        let uri = "http://packs.example/index.pidx".parse().unwrap();
+        let res = core.run(client.redirectable(uri, Headers::new(), &store.config.download, &logger));
+        let res = res.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert!(requests.recv().unwrap().starts_with("GET http://packs.example/index.pidx "));
+    }
+
+    #[test]
+    fn https_tunnels_through_proxy() {
+        let mut core = Core::new().unwrap();
+        let (proxy, requests) = proxy("HTTP/1.1 403 Forbidden\r\n\r\n");
+        let client = client(&store(proxy).config, &core.handle()).unwrap();
+        let uri = "https://packs.example/index.pidx".parse().unwrap();
+        assert!(core.run(client.get(uri)).is_err());
+        assert!(requests.recv().unwrap().starts_with("CONNECT packs.example:443 HTTP/1.1\r\n"));
+    }
+}
@@ -1,51 +1,310 @@
 use std::path::{Path, PathBuf};
 
-use failure::Error;
+use failure::{err_msg, Error};
 use futures::stream::iter_ok;
 use futures::prelude::*;
-use hyper::{Body, Client, Uri};
+use hyper::{Body, Client};
 use hyper::client::Connect;
 use slog::Logger;
 
-use pdsc::Package;
+use pdsc::{Package, Release, Version};
+use pack_index::PdscRef;
 use pack_index::config::Config;
 
-use download::{IntoDownload, DownloadProgress, download_stream};
+use cache::HttpCache;
+use download::{IntoDownload, DownloadProgress, Source, download_stream};
+use report::UpdateReport;
+use vidx::cached_pdscs;
+use extract::verify_pack;
 
-impl<'a> IntoDownload for &'a Package {
-    fn into_uri(&self, _: &Config) -> Result<Uri, Error> {
-        let &Package{ref name, ref vendor, ref url, ref releases, ..} = *self;
-        let version: &str = releases.latest_release().version.as_ref();
-        let uri = if url.ends_with('/') {
-            format!("{}{}.{}.{}.pack", url, vendor, name, version)
-        } else {
-            format!("{}/{}.{}.{}.pack", url, vendor, name, version)
-        }.parse()?;
-        Ok(uri)
+/// One release of a pack: where to download it from and where it is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackRelease {
+    pub vendor: String,
+    pub name: String,
+    pub version: Version,
+    /// The `.pack` file itself, honoring per-release `url` overrides.
+    pub url: String,
+    /// Expected size of the `.pack` in bytes, when known.
+    pub size: Option<u64>,
+    /// Expected hex encoded SHA-256 of the `.pack`, when known.
+    pub sha256: Option<String>,
+}
+
+impl PackRelease {
+    fn from_release(package: &Package, release: &Release) -> Self {
+        let &Package{ref name, ref vendor, ref url, ..} = package;
+        let version = release.version.as_str();
+        let url = match release.url {
+            Some(ref url) => url.clone(),
+            None if url.ends_with('/') => format!("{}{}.{}.{}.pack", url, vendor, name, version),
+            None => format!("{}/{}.{}.{}.pack", url, vendor, name, version),
+        };
+        PackRelease {
+            vendor: vendor.clone(),
+            name: name.clone(),
+            version: release.version.clone(),
+            url,
+            size: None,
+            sha256: None,
+        }
     }
 
-    fn into_fd(&self, config: &Config) -> PathBuf {
-        let &Package{ref name, ref vendor, ref releases, ..} = *self;
-        let version: &str = releases.latest_release().version.as_ref();
+    pub fn latest(package: &Package) -> Self {
+        Self::from_release(package, package.releases.latest_release())
+    }
+
+    /// Fails when `version` is not one of the pack's releases.
+    pub fn new(package: &Package, version: &Version) -> Result<Self, Error> {
+        match package.releases.find(version) {
+            Some(release) => Ok(Self::from_release(package, release)),
+            None => Err(err_msg(format!(
+                "{}.{} has no release {}",
+                package.vendor, package.name, version
+            ))),
+        }
+    }
+
+    /// Take the expected size from an index entry. Sizes with a unit, such
+    /// as `8MB`, are too coarse to check against and are ignored.
+    pub fn with_pdsc_ref(self, pdsc_ref: &PdscRef) -> Self {
+        PackRelease {
+            size: pdsc_ref.size.as_ref().and_then(|size| size.trim().parse().ok()),
+            ..self
+        }
+    }
+
+    /// Where the `.pack` file is kept in the pack store.
+    pub fn pack_path(&self, config: &Config) -> PathBuf {
         let mut filename = config.pack_store.clone();
-        filename.push(Path::new(vendor));
-        filename.push(Path::new(name));
-        filename.push(format!("{}.pack", version));
+        filename.push(Path::new(&self.vendor));
+        filename.push(Path::new(&self.name));
+        filename.push(format!("{}.pack", self.version));
         filename
     }
 }
 
+/// Anything that names a pack release to install: a `&Package` for its
+/// latest release, or a `(&Package, Version)` pair for a specific one.
+pub trait IntoPackRelease {
+    fn into_pack_release(self) -> Result<PackRelease, Error>;
+}
+
+impl IntoPackRelease for PackRelease {
+    fn into_pack_release(self) -> Result<PackRelease, Error> {
+        Ok(self)
+    }
+}
+
+impl IntoPackRelease for &Package {
+    fn into_pack_release(self) -> Result<PackRelease, Error> {
+        Ok(PackRelease::latest(self))
+    }
+}
+
+impl IntoPackRelease for (&Package, &Version) {
+    fn into_pack_release(self) -> Result<PackRelease, Error> {
+        PackRelease::new(self.0, self.1)
+    }
+}
+
+impl IntoPackRelease for (&Package, Version) {
+    fn into_pack_release(self) -> Result<PackRelease, Error> {
+        PackRelease::new(self.0, &self.1)
+    }
+}
+
+impl IntoDownload for PackRelease {
+    fn into_source(&self, _: &Config) -> Result<Source, Error> {
+        Source::parse(&self.url)
+    }
+
+    fn into_fd(&self, config: &Config) -> PathBuf {
+        self.pack_path(config)
+    }
+
+    /// Only asked for when no checksum is known yet, as from a lock file.
+    fn checksum_url(&self) -> Option<String> {
+        match self.sha256 {
+            Some(_) => None,
+            None => Some(format!("{}.sha256", self.url)),
+        }
+    }
+
+    fn verify(&self, path: &Path, sha256: Option<String>, _: &Config, logger: &Logger) -> Result<(), Error> {
+        match sha256 {
+            Some(sha256) if self.sha256.is_none() => {
+                let release = PackRelease {
+                    sha256: Some(sha256),
+                    ..self.clone()
+                };
+                verify_pack(path, &release, logger)
+            }
+            _ => verify_pack(path, self, logger),
+        }
+    }
+}
+
+/// Take the expected sizes of `packs` from the indexes, as cached by the
+/// last `update`. Without them, packs are installed unchecked.
+fn with_index_sizes<I>(config: &Config, packs: I, cache: &HttpCache, logger: &Logger) -> Vec<PackRelease>
+    where I: IntoIterator<Item = PackRelease>
+{
+    let pdscs = match cached_pdscs(config.read_vidx_list(logger), cache, logger) {
+        Ok(pdscs) => pdscs,
+        Err(e) => {
+            debug!(logger, "Not checking pack sizes, as the indexes are unknown: {}", e);
+            Vec::new()
+        }
+    };
+    packs
+        .into_iter()
+        .map(|pack| {
+            let listed = pdscs.iter().find(|pdsc| {
+                pdsc.vendor == pack.vendor && pdsc.name == pack.name
+                    && pdsc.version.parse::<Version>().ok().as_ref() == Some(&pack.version)
+            });
+            match listed {
+                Some(pdsc_ref) => pack.with_pdsc_ref(pdsc_ref),
+                None => pack,
+            }
+        })
+        .collect()
+}
 
 pub fn install_future<'client,'a: 'client,  C, I, P>(
     config: &'a Config,
-    pdscs: I,
+    packs: I,
     client: &'client Client<C, Body>,
+    cache: &'client HttpCache,
     logger: &'a Logger,
     progress: P,
-) -> impl Future<Item = Vec<PathBuf>, Error = Error> + 'client
+) -> impl Future<Item = UpdateReport, Error = Error> + 'client
     where C: Connect,
-          I: IntoIterator<Item = &'a Package> + 'a,
+          I: IntoIterator<Item = PackRelease> + 'a,
           P: DownloadProgress + 'client,
 {
-    download_stream(config, iter_ok(pdscs), client, logger, progress).collect()
+    let packs = with_index_sizes(config, packs, cache, logger);
+    download_stream(config, iter_ok(packs), client, cache, logger, progress)
+        .collect()
+        .map(|items| UpdateReport { items })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::Discard;
+    use utils::parse::FromElem;
+
+    use hyper::Headers;
+
+    use lock::sha256_file;
+    use testing::{config, write, write_pack, TempStore};
+
+    const PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/packs</url>
+      <releases>
+        <release version=\"1.1.0\">latest</release>
+        <release version=\"1.0.0\" url=\"http://mirror/MyPack-1.0.0.pack\">old</release>
+      </releases>
+    </package>";
+
+    #[test]
+    fn pinned_release() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(PDSC, &log).unwrap();
+        let config = config(PathBuf::from("store"));
+        let latest = (&pack).into_pack_release().unwrap();
+        assert_eq!(
+            latest.into_source(&config).unwrap(),
+            Source::parse("http://localhost/packs/MyVendor.MyPack.1.1.0.pack").unwrap()
+        );
+        let old = (&pack, Version::new(1, 0, 0)).into_pack_release().unwrap();
+        assert_eq!(
+            old.into_source(&config).unwrap(),
+            Source::parse("http://mirror/MyPack-1.0.0.pack").unwrap()
+        );
+        assert!(old.into_fd(&config).ends_with("MyVendor/MyPack/1.0.0.pack"));
+        assert!((&pack, Version::new(2, 0, 0)).into_pack_release().is_err());
+    }
+
+    #[test]
+    fn sizes_from_cached_index() {
+        let log = Logger::root(Discard, o!());
+        let store = TempStore::new("sizes");
+        let config = &store.config;
+        let url = "http://localhost/index.pidx";
+        write(&config.vidx_list, url.as_bytes());
+        let cache = HttpCache::load(config, &log);
+        cache.store_body(url, &Headers::new(), b"<index>
+          <vendor>Local</vendor>
+          <url>http://localhost/</url>
+          <pindex>
+            <pdsc url=\"http://localhost/\" vendor=\"MyVendor\" name=\"MyPack\" version=\"1.0.0\"
+                  size=\"1234\"/>
+          </pindex>
+        </index>").unwrap();
+        let pack = Package::from_string(PDSC, &log).unwrap();
+        let releases = vec![
+            (&pack, Version::new(1, 0, 0)).into_pack_release().unwrap(),
+            (&pack).into_pack_release().unwrap(),
+        ];
+        let sized = with_index_sizes(config, releases, &cache, &log);
+        assert_eq!(sized[0].size, Some(1234));
+        assert_eq!(sized[1].size, None);
+    }
+
+    #[test]
+    fn reject_mismatched_packs() {
+        let log = Logger::root(Discard, o!());
+        let store = TempStore::new("dl-pack");
+        let config = &store.config;
+        let path = store.path().join("MyVendor.MyPack.1.0.0.pack.part");
+        let pdsc = PDSC.replace("1.1.0", "1.0.0");
+        write_pack(&path, &[("MyVendor.MyPack.pdsc", pdsc.as_str())]);
+        let size = path.metadata().unwrap().len();
+        let release = PackRelease {
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: Version::new(1, 0, 0),
+            url: "http://localhost/MyVendor.MyPack.1.0.0.pack".to_string(),
+            size: None,
+            sha256: None,
+        };
+        let index = |size: u64| PdscRef {
+            url: "http://localhost/".to_string(),
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: "1.0.0".to_string(),
+            date: None,
+            deprecated: None,
+            replacement: None,
+            size: Some(size.to_string()),
+        };
+        assert_eq!(
+            release.checksum_url(),
+            Some("http://localhost/MyVendor.MyPack.1.0.0.pack.sha256".to_string())
+        );
+        release.verify(&path, None, config, &log).unwrap();
+
+        let sized = release.clone().with_pdsc_ref(&index(size + 1));
+        let error = sized.verify(&path, None, config, &log).unwrap_err();
+        assert!(error.to_string().contains("bytes"));
+        let sized = release.clone().with_pdsc_ref(&index(size));
+        sized.verify(&path, None, config, &log).unwrap();
+
+        let error = sized.verify(&path, Some("0".repeat(64)), config, &log).unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+        let sha256 = sha256_file(&path).unwrap();
+        sized.verify(&path, Some(sha256.clone()), config, &log).unwrap();
+
+        let locked = PackRelease {
+            sha256: Some(sha256),
+            ..release
+        };
+        assert_eq!(locked.checksum_url(), None);
+    }
 }
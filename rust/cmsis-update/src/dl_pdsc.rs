@@ -1,26 +1,34 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use failure::Error;
 use futures::prelude::*;
-use hyper::{Body, Client, Uri};
+use hyper::{Body, Client};
 use hyper::client::Connect;
 use slog::Logger;
 
 use pack_index::{PdscRef};
 use pack_index::config::Config;
+use pdsc::{Package, Version};
+use utils::parse::FromElem;
 
-use download::{IntoDownload, DownloadProgress, download_stream};
+use cache::HttpCache;
+use download::{IntoDownload, DownloadProgress, Source, download_stream};
+use report::UpdateReport;
 use vidx::{download_vidx_list, flatmap_pdscs};
 
+/// The `YYYY-MM-DD` part of a date, which may also carry a time.
+fn day(date: &str) -> &str {
+    date.get(..10).unwrap_or(date)
+}
+
 impl IntoDownload for PdscRef {
-    fn into_uri(&self, _: &Config) -> Result<Uri, Error> {
+    fn into_source(&self, _: &Config) -> Result<Source, Error> {
         let &PdscRef {ref url, ref vendor, ref name, ..} = self;
-        let uri = if url.ends_with('/') {
-            format!("{}{}.{}.pdsc", url, vendor, name)
+        if url.ends_with('/') {
+            Source::parse(&format!("{}{}.{}.pdsc", url, vendor, name))
         } else {
-            format!("{}/{}.{}.pdsc", url, vendor, name)
-        }.parse()?;
-        Ok(uri)
+            Source::parse(&format!("{}/{}.{}.pdsc", url, vendor, name))
+        }
     }
 
     fn into_fd(&self, config: &Config) -> PathBuf {
@@ -30,6 +38,28 @@ impl IntoDownload for PdscRef {
         filename.push(pdscname);
         filename
     }
+
+    fn is_deprecated(&self) -> bool {
+        self.deprecated.is_some()
+    }
+
+    /// The local copy is outdated when the index lists a newer version, or
+    /// a later date for the same version.
+    fn is_current(&self, dest: &Path, logger: &Logger) -> bool {
+        let local = match Package::from_path(dest, logger) {
+            Ok(local) => local,
+            Err(_) => return false,
+        };
+        let latest = local.releases.latest_release();
+        let indexed = Version::parse_lossy(&self.version);
+        if latest.version != indexed {
+            return latest.version > indexed;
+        }
+        match (latest.date.as_ref(), self.date.as_ref()) {
+            (Some(local_date), Some(indexed_date)) => day(local_date) >= day(indexed_date),
+            _ => true,
+        }
+    }
 }
 
 /// Create a future of the update command.
@@ -37,16 +67,61 @@ pub fn update_future<'a, C, I, P>(
     config: &'a Config,
     vidx_list: I,
     client: &'a Client<C, Body>,
+    cache: &'a HttpCache,
     logger: &'a Logger,
     progress: P
-) -> impl Future<Item = Vec<PathBuf>, Error = Error> + 'a
+) -> impl Future<Item = UpdateReport, Error = Error> + 'a
     where C: Connect,
           I: IntoIterator<Item = String> + 'a,
           P: DownloadProgress + 'a,
 {
-    let parsed_vidx = download_vidx_list(vidx_list, client, logger);
+    let options = &config.download;
+    let parsed_vidx = download_vidx_list(vidx_list, client, cache, options, logger);
     let pdsc_list = parsed_vidx
-        .filter_map(move |vidx| vidx.map(|v| flatmap_pdscs(v, client, logger)))
+        .filter_map(move |vidx| vidx.map(|v| flatmap_pdscs(v, client, cache, options, logger)))
         .flatten();
-    download_stream(config, pdsc_list, client, logger, progress).collect()
+    download_stream(config, pdsc_list, client, cache, logger, progress)
+        .collect()
+        .map(|items| UpdateReport { items })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::Discard;
+
+    use testing::{write, TempStore};
+
+    fn pdsc_ref(version: &str, date: Option<&str>) -> PdscRef {
+        PdscRef {
+            url: "http://localhost/".to_string(),
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: version.to_string(),
+            date: date.map(str::to_string),
+            deprecated: None,
+            replacement: None,
+            size: None,
+        }
+    }
+
+    #[test]
+    fn outdated_pdsc() {
+        let log = Logger::root(Discard, o!());
+        let store = TempStore::new("pdsc");
+        let dest = store.path().join("MyVendor.MyPack.1.0.0.pdsc");
+        assert!(!pdsc_ref("1.0.0", None).is_current(&dest, &log));
+        write(&dest, b"<package>
+              <vendor>MyVendor</vendor>
+              <name>MyPack</name>
+              <description>dummy</description>
+              <url>http://localhost/</url>
+              <releases><release version=\"1.0.0\" date=\"2018-05-01\"/></releases>
+            </package>");
+        assert!(pdsc_ref("1.0.0", None).is_current(&dest, &log));
+        assert!(pdsc_ref("1.0.0", Some("2018-05-01")).is_current(&dest, &log));
+        assert!(pdsc_ref("0.9.0", Some("2019-01-01")).is_current(&dest, &log));
+        assert!(!pdsc_ref("1.0.0", Some("2018-06-01T12:00:00")).is_current(&dest, &log));
+        assert!(!pdsc_ref("1.0.1", None).is_current(&dest, &log));
+    }
 }
@@ -1,109 +1,912 @@
-use std::fs::{create_dir_all, rename, OpenOptions};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{copy, create_dir_all, read_to_string, remove_file, rename, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use failure::Error;
-use futures::Stream;
+use failure::{Error, Fail};
+use failure::err_msg;
+use futures::{task, Async, Poll, Stream};
+use futures::task::Task;
 use futures::prelude::Future;
-use futures::future::{ok, result};
+use futures::future::{err, ok, poll_fn, result, Either};
 use futures::stream::iter_ok;
-use hyper::{Body, Client, Uri};
+use hyper::{Body, Client, Headers, StatusCode, Uri};
 use hyper::client::Connect;
+use hyper::header::{ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec, Range};
 use slog::Logger;
-use std::sync::Arc;
+use tokio_timer::Interval;
 
-use pack_index::config::Config;
+use pack_index::config::{CancelToken, Config, DownloadOptions};
 
-use redirect::ClientRedirExt;
+use cache::HttpCache;
+use redirect::{retrying, ClientRedirExt, ReadTimeout, ServerError};
+use report::{Outcome, ReportItem};
+
+/// Where a file is downloaded from: a server, or the local file system for
+/// `file://` URLs and plain paths.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Source {
+    Remote(Uri),
+    Local(PathBuf),
+}
+
+/// Undo the `%XX` escapes of a `file://` URL.
+fn percent_decode(from: &str) -> String {
+    let bytes = from.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = if bytes[index] == b'%' {
+            from.get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape the bytes of a path segment that may not appear in a URL as is.
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for &byte in segment.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The `file://` URL of an absolute path, with forward slashes and
+/// escaped segments. Windows paths become `file:///C:/...`, without the
+/// `\\?\` prefix of canonicalized paths.
+pub fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                    url.push_str(&format!("/{}:", drive as char))
+                }
+                Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+                    url.push_str(&percent_encode(&server.to_string_lossy()));
+                    url.push('/');
+                    url.push_str(&percent_encode(&share.to_string_lossy()));
+                }
+                Prefix::Verbatim(name) | Prefix::DeviceNS(name) => {
+                    url.push('/');
+                    url.push_str(&percent_encode(&name.to_string_lossy()));
+                }
+            },
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir => url.push_str("/.."),
+            Component::Normal(segment) => {
+                url.push('/');
+                url.push_str(&percent_encode(&segment.to_string_lossy()));
+            }
+        }
+    }
+    url
+}
+
+impl Source {
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        match url.find("://") {
+            Some(end) if &url[..end] == "file" => {
+                let path = &url[end + 3..];
+                // Skip an authority, as in `file://localhost/path`.
+                let path = match path.find('/') {
+                    Some(slash) => &path[slash..],
+                    None => path,
+                };
+                // `file:///C:/path` names a Windows drive.
+                let path = match path.get(1..3) {
+                    Some(drive) if drive.ends_with(':') => &path[1..],
+                    _ => path,
+                };
+                Ok(Source::Local(PathBuf::from(percent_decode(path))))
+            }
+            Some(_) => Ok(Source::Remote(url.parse()?)),
+            None => Ok(Source::Local(PathBuf::from(url))),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Source::Remote(ref uri) => write!(f, "{}", uri),
+            Source::Local(ref path) => write!(f, "{}", path.display()),
+        }
+    }
+}
 
 pub(crate) trait IntoDownload {
-    fn into_uri(&self, &Config) -> Result<Uri, Error>;
+    fn into_source(&self, &Config) -> Result<Source, Error>;
     fn into_fd(&self, &Config) -> PathBuf;
+
+    /// Is the file already in the store up to date?
+    fn is_current(&self, dest: &Path, _: &Logger) -> bool {
+        dest.exists()
+    }
+
+    /// Is the file marked deprecated, so that it is not worth downloading?
+    fn is_deprecated(&self) -> bool {
+        false
+    }
+
+    /// Where a `.sha256` file may publish the checksum of this one.
+    fn checksum_url(&self) -> Option<String> {
+        None
+    }
+
+    /// Check a completed download before it is moved into the store,
+    /// against the checksum published at `checksum_url`, if any.
+    fn verify(&self, _: &Path, _: Option<String>, _: &Config, _: &Logger) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub trait DownloadProgress: Send {
+    /// `files` more files are to be downloaded.
     fn size(&self, files: usize);
+    /// The file is `bytes` long, when its server says so.
+    fn file_size(&self, bytes: usize);
     fn progress(&self, bytes: usize);
+    /// A download continues from an earlier attempt, `bytes` into the file.
+    fn resumed(&self, bytes: usize);
+    /// The download failed; `complete` is still called after this.
+    fn failed(&self, error: &str);
     fn complete(&self);
     fn for_file(&self, file: &str) -> Self;
 }
 
 impl DownloadProgress for () {
     fn size(&self, _: usize) {}
+    fn file_size(&self, _: usize) {}
     fn progress(&self, _: usize) {}
+    fn resumed(&self, _: usize) {}
+    fn failed(&self, _: &str) {}
     fn complete(&self) {}
     fn for_file(&self, _: &str) -> Self {
         ()
     }
 }
 
+/// Move a complete download from `temp` into place, if it verifies.
+fn finish<V>(temp: &Path, dest: &Path, verify: V) -> Result<(), Error>
+    where V: FnOnce(&Path) -> Result<(), Error>
+{
+    match verify(temp) {
+        Ok(()) => {
+            rename(temp, dest)?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = remove_file(temp);
+            Err(e)
+        }
+    }
+}
+
+/// The progress of one download, and the response status and bytes it
+/// got so far, for its `ReportItem`.
+struct Transfer<P> {
+    spinner: P,
+    status: Cell<Option<u16>>,
+    bytes: Cell<u64>,
+    /// Whether `spinner` was told the size of the file, which a retry
+    /// must not add again.
+    sized: Cell<bool>,
+    /// The bytes of the file `spinner` was told of, through `resumed` and
+    /// `progress`.
+    reported: Cell<u64>,
+}
+
+impl<P: DownloadProgress> Transfer<P> {
+    fn new(spinner: P) -> Self {
+        Transfer {
+            spinner,
+            status: Cell::new(None),
+            bytes: Cell::new(0),
+            sized: Cell::new(false),
+            reported: Cell::new(0),
+        }
+    }
+
+    fn file_size(&self, bytes: u64) {
+        if !self.sized.replace(true) {
+            self.spinner.file_size(bytes as usize);
+        }
+    }
+
+    /// The download continues `offset` bytes into the file, which may have
+    /// been received by an earlier attempt of this run.
+    fn resumed(&self, offset: u64) {
+        if offset > self.reported.get() {
+            self.spinner.resumed((offset - self.reported.get()) as usize);
+            self.reported.set(offset);
+        }
+    }
+
+    fn received(&self, bytes: usize) {
+        self.bytes.set(self.bytes.get() + bytes as u64);
+        self.reported.set(self.reported.get() + bytes as u64);
+        self.spinner.progress(bytes);
+    }
+}
+
+fn copy_file<P, V>(source: &Path, dest: &Path, transfer: &Transfer<P>, verify: V) -> Result<(), Error>
+    where P: DownloadProgress,
+          V: FnOnce(&Path) -> Result<(), Error>
+{
+    dest.parent().map(create_dir_all);
+    let temp = dest.with_extension("part");
+    if let Ok(meta) = source.metadata() {
+        transfer.file_size(meta.len());
+    }
+    let bytes = copy(source, &temp)?;
+    transfer.received(bytes as usize);
+    finish(&temp, dest, verify)
+}
+
+/// The error of downloads stopped through their `CancelToken`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the downloads were cancelled")
+    }
+}
+
+impl Fail for Cancelled {}
+
+/// Ends a stream with `Cancelled` once its token is cancelled, which drops
+/// the downloads still in flight. The token is checked every tenth of a
+/// second, so that hung downloads are stopped too.
+struct Cancellable<S> {
+    inner: S,
+    cancel: CancelToken,
+    ticks: Interval,
+}
+
+impl<S> Cancellable<S> {
+    fn new(inner: S, cancel: CancelToken) -> Self {
+        let period = Duration::from_millis(100);
+        Cancellable {
+            inner,
+            cancel,
+            ticks: Interval::new(Instant::now() + period, period),
+        }
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for Cancellable<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        if self.cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        while let Ok(Async::Ready(Some(_))) = self.ticks.poll() {}
+        self.inner.poll()
+    }
+}
+
+/// Downloads in flight from each host, so that none gets more than
+/// `DownloadOptions::per_host` of them.
+#[derive(Clone, Default)]
+struct HostSlots(Rc<RefCell<SlotCounts>>);
+
+#[derive(Default)]
+struct SlotCounts {
+    busy: HashMap<String, usize>,
+    waiting: Vec<Task>,
+}
+
+/// A download's place in `HostSlots`, given back when dropped.
+struct HostSlot {
+    host: String,
+    slots: HostSlots,
+}
+
+impl HostSlots {
+    fn acquire(&self, host: String, limit: usize) -> impl Future<Item = HostSlot, Error = Error> {
+        let slots = self.clone();
+        poll_fn(move || {
+            let mut counts = slots.0.borrow_mut();
+            let busy = counts.busy.entry(host.clone()).or_insert(0);
+            if *busy < limit.max(1) {
+                *busy += 1;
+                return Ok(Async::Ready(HostSlot {
+                    host: host.clone(),
+                    slots: slots.clone(),
+                }));
+            }
+            counts.waiting.push(task::current());
+            Ok(Async::NotReady)
+        })
+    }
+}
+
+impl Drop for HostSlot {
+    fn drop(&mut self) {
+        let mut counts = self.slots.0.borrow_mut();
+        if let Some(busy) = counts.busy.get_mut(&self.host) {
+            *busy -= 1;
+        }
+        for task in counts.waiting.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+fn host(source: &Result<Source, Error>) -> Option<String> {
+    match *source {
+        Ok(Source::Remote(ref uri)) => uri.authority().map(String::from),
+        _ => None,
+    }
+}
+
+/// Take downloads from each host in turn, so that those in flight are
+/// spread across hosts instead of waiting on a busy one.
+fn interleave_hosts<DL>(to_dl: Vec<DL>, config: &Config) -> Vec<(Result<Source, Error>, DL)>
+    where DL: IntoDownload
+{
+    let mut hosts: Vec<(Option<String>, VecDeque<_>)> = Vec::new();
+    for from in to_dl {
+        let source = from.into_source(config);
+        let host = host(&source);
+        match hosts.iter().position(|(other, _)| *other == host) {
+            Some(index) => hosts[index].1.push_back((source, from)),
+            None => hosts.push((host, vec![(source, from)].into_iter().collect())),
+        }
+    }
+    let mut interleaved = Vec::new();
+    while !hosts.is_empty() {
+        for (_, queue) in &mut hosts {
+            interleaved.extend(queue.pop_front());
+        }
+        hosts.retain(|(_, queue)| !queue.is_empty());
+    }
+    interleaved
+}
+
+/// Download `source` into the `.part` file next to `dest`, resuming an
+/// earlier attempt where possible. Resolves to the response headers once
+/// the `.part` file is complete, or to `None` when `dest` is not modified.
 fn download_file<'b,  C: Connect, P: DownloadProgress + 'b>(
     source: Uri,
     dest: PathBuf,
     client: &'b Client<C, Body>,
+    cache: &'b HttpCache,
+    options: &'b DownloadOptions,
     logger: &'b Logger,
-    spinner: Arc<P>
-) -> Box<Future<Item=(), Error=Error> + 'b> {
-    if !dest.exists() {
-        dest.parent().map(create_dir_all);
-        Box::new(client.redirectable(source, logger)
-             .from_err()
-             .and_then(move |res| {
-                let temp = dest.with_extension("part");
-                let fdf = result(OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(&temp));
-                fdf.from_err().and_then(move |mut fd| {
-                    res.body().for_each(move |bytes| {
-                        spinner.progress(bytes.len());
-                        fd.write_all(bytes.as_ref())?;
-                        Ok(())
-                    }).then(move |_| {
-                        rename(&temp, &dest)?;
-                        Ok(())
-                    })
-                })
-            })
-        )
-    } else {
-        Box::new(ok(()))
+    transfer: Rc<Transfer<P>>,
+) -> Box<Future<Item=Option<Headers>, Error=Error> + 'b>
+{
+    dest.parent().map(create_dir_all);
+    let url = source.to_string();
+    let temp = dest.with_extension("part");
+    let mut headers = Headers::new();
+    // A `.part` file is only resumed when the server is able to tell
+    // whether the file changed since; it restarts the download if so.
+    let offset = match temp.metadata() {
+        Ok(ref meta) if meta.len() > 0 && cache.if_range(&url, &mut headers) => meta.len(),
+        _ => 0,
+    };
+    if offset > 0 {
+        headers.set(Range::Bytes(vec![ByteRangeSpec::AllFrom(offset)]));
+    } else if dest.exists() {
+        cache.conditional_headers(&url, &mut headers);
+    }
+    Box::new(client.redirectable(source, headers, options, logger)
+        .from_err()
+        .and_then(move |res| {
+            transfer.status.set(Some(res.status().as_u16()));
+            let resume = match res.status() {
+                StatusCode::NotModified if dest.exists() => {
+                    slog_debug!(logger, "{} is not modified", url);
+                    return Either::A(ok(None));
+                }
+                StatusCode::PartialContent => {
+                    match res.headers().get::<ContentRange>() {
+                        Some(&ContentRange(ContentRangeSpec::Bytes {
+                            range: Some((start, _)), ..
+                        })) if start == offset => true,
+                        _ => {
+                            let msg = "server resumed the download at the wrong offset";
+                            return Either::A(err(err_msg(msg)));
+                        }
+                    }
+                }
+                StatusCode::RangeNotSatisfiable => {
+                    let _ = remove_file(&temp);
+                    let msg = "server could not resume the download; restarting it next time";
+                    return Either::A(err(err_msg(msg)));
+                }
+                status if status.is_success() => false,
+                status => return Either::A(err(ServerError(status).into())),
+            };
+            let resumed_at = if resume { offset } else { 0 };
+            if let Some(&ContentLength(len)) = res.headers().get::<ContentLength>() {
+                transfer.file_size(resumed_at + len);
+            }
+            if resume {
+                slog_debug!(logger, "Resuming download of {:?} at byte {}", dest, offset);
+                transfer.resumed(offset);
+            } else {
+                cache.record_partial(&url, res.headers());
+            }
+            let headers = res.headers().clone();
+            let fdf = result(OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(resume)
+                .truncate(!resume)
+                .open(&temp));
+            // An interrupted download keeps its `.part` file, to be resumed
+            // by a retry, or next time.
+            let body = ReadTimeout::new(res.body(), options);
+            Either::B(fdf.from_err().and_then(move |mut fd| {
+                body.from_err::<Error>().for_each(move |bytes| {
+                    transfer.received(bytes.len());
+                    fd.write_all(bytes.as_ref())?;
+                    Ok(())
+                }).map(move |_| Some(headers))
+            }))
+        })
+    )
+}
+
+/// The hex encoded SHA-256 at the start of a checksum file, which may go
+/// on to name the file it is for, as `sha256sum` writes them.
+fn parse_checksum(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+}
+
+/// Fetch the checksum published at `url`. Most servers publish none, so a
+/// missing or unreadable checksum file only means there is nothing to check.
+fn fetch_checksum<'b, C: Connect>(
+    url: Option<String>,
+    client: &'b Client<C, Body>,
+    options: &'b DownloadOptions,
+    logger: &'b Logger,
+) -> impl Future<Item = Option<String>, Error = Error> + 'b {
+    let url = match url {
+        Some(url) => url,
+        None => return Either::B(ok(None)),
+    };
+    let parsed = move |url: &str, text: &str| {
+        let checksum = parse_checksum(text);
+        if checksum.is_none() {
+            slog_warn!(logger, "Ignoring {}: not a SHA-256 checksum", url);
+        }
+        checksum
+    };
+    match Source::parse(&url) {
+        Ok(Source::Remote(uri)) => Either::A(client.redirectable(uri, Headers::new(), options, logger)
+            .from_err()
+            .and_then(move |res| {
+                if !res.status().is_success() {
+                    slog_debug!(logger, "No checksum at {}: {}", url, res.status());
+                    return Either::A(ok(None));
+                }
+                Either::B(ReadTimeout::new(res.body(), options)
+                    .concat2()
+                    .from_err()
+                    .map(move |body| parsed(&url, &String::from_utf8_lossy(&body))))
+            })),
+        Ok(Source::Local(path)) => Either::B(ok(read_to_string(path)
+            .ok()
+            .and_then(|text| parsed(&url, &text)))),
+        Err(e) => Either::B(err(e)),
     }
 }
 
+/// One attempt at downloading `from` into the store, checked against the
+/// checksum published next to it, if any.
+fn fetch_item<'b, C: Connect, P: DownloadProgress + 'b, DL: IntoDownload + 'b>(
+    from: Rc<DL>,
+    source: Source,
+    config: &'b Config,
+    client: &'b Client<C, Body>,
+    cache: &'b HttpCache,
+    logger: &'b Logger,
+    transfer: Rc<Transfer<P>>,
+) -> impl Future<Item = Outcome, Error = Error> + 'b {
+    let options = &config.download;
+    let dest = from.into_fd(config);
+    fetch_checksum(from.checksum_url(), client, options, logger).and_then(move |sha256| {
+        let verify = move |path: &Path| from.verify(path, sha256, config, logger);
+        match source {
+            Source::Remote(uri) => {
+                let url = uri.to_string();
+                Either::A(download_file(
+                    uri, dest.clone(), client, cache, options, logger, transfer
+                ).and_then(move |headers| match headers {
+                    Some(headers) => {
+                        finish(&dest.with_extension("part"), &dest, verify)?;
+                        cache.record(&url, &headers);
+                        Ok(Outcome::Downloaded)
+                    }
+                    None => Ok(Outcome::Unchanged),
+                }))
+            }
+            Source::Local(path) => Either::B(result(
+                copy_file(&path, &dest, &transfer, verify).map(|_| Outcome::Downloaded)
+            )),
+        }
+    })
+}
+
 pub(crate) fn download_stream<'b, 'a: 'b, F, C, P: 'b, DL: 'a>(
     config: &'a Config,
     stream: F,
     client: &'b Client<C, Body>,
+    cache: &'b HttpCache,
     logger: &'b Logger,
     progress: P
-) -> Box<Stream<Item = PathBuf, Error = Error> + 'b>
+) -> Box<Stream<Item = ReportItem, Error = Error> + 'b>
     where F: Stream<Item = DL, Error = Error> + 'b,
           C: Connect,
           DL: IntoDownload,
           P: DownloadProgress
 {
-    let streaming_pathbuffs = 
+    let options = &config.download;
+    let streaming_items =
         stream.collect().map(move |to_dl|{
             let len = to_dl.len();
             progress.size(len);
-            iter_ok(to_dl).map(move |from| {
+            let slots = HostSlots::default();
+            iter_ok(interleave_hosts(to_dl, config)).map(move |(source, from)| {
                 let dest = from.into_fd(config);
-                let source = from.into_uri(config);
-                let new_prog = Arc::new(progress.for_file(&dest.to_string_lossy()));
-                result(source).and_then(move |source| download_file(
-                    source.clone(), dest.clone(), client, logger, new_prog.clone()
-                    ).then(move |res| {
-                        new_prog.complete();
+                let url = source.as_ref().map(ToString::to_string).unwrap_or_default();
+                if from.is_current(&dest, logger) {
+                    progress.complete();
+                    return Either::A(ok(ReportItem::new(url, dest, Outcome::Unchanged)));
+                }
+                if options.skip_deprecated && from.is_deprecated() {
+                    slog_debug!(logger, "Skipping deprecated {}", url);
+                    progress.complete();
+                    return Either::A(ok(ReportItem::new(url, dest, Outcome::Deprecated)));
+                }
+                let slot = match host(&source) {
+                    Some(host) => Either::A(slots.acquire(host, options.per_host).map(Some)),
+                    None => Either::B(ok(None)),
+                };
+                let transfer = Rc::new(Transfer::new(progress.for_file(&dest.to_string_lossy())));
+                let from = Rc::new(from);
+                Either::B(slot.and_then(move |slot| {
+                    let fetch = match source {
+                        Ok(source) => {
+                            let transfer = transfer.clone();
+                            Either::A(retrying(options, logger, url.clone(), move || {
+                                fetch_item(from.clone(), source.clone(), config, client, cache, logger, transfer.clone())
+                            }))
+                        }
+                        Err(e) => Either::B(err(e)),
+                    };
+                    fetch.then(move |res| {
+                        drop(slot);
+                        let mut item = ReportItem::new(url, dest, Outcome::Downloaded);
+                        item.status = transfer.status.get();
+                        item.bytes = transfer.bytes.get();
                         match res {
-                            Ok(_) => Ok(Some(dest)),
+                            Ok(outcome) => item.outcome = outcome,
                             Err(e) => {
-                                slog_error!(logger, "download of {:?} failed: {}", source, e);
-                                Ok(None)
+                                slog_error!(logger, "download of {} failed: {}", item.dest.display(), e);
+                                transfer.spinner.failed(&e.to_string());
+                                item.outcome = Outcome::Failed;
+                                item.error = Some(e.to_string());
                             }
                         }
-                    }))
+                        transfer.spinner.complete();
+                        Ok(item)
+                    })
+                }))
             })
         }).flatten_stream();
-    Box::new(streaming_pathbuffs.buffer_unordered(32).filter_map(|x| x))
+    Box::new(Cancellable::new(
+        streaming_items.buffer_unordered(options.max_concurrent.max(1)),
+        options.cancel.clone(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::read_to_string;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use hyper::client::HttpConnector;
+    use hyper::header::{ETag, EntityTag};
+    use pack_index::PdscRef;
+    use slog::Discard;
+    use tokio_core::reactor::Core;
+
+    use testing::{serve, write, TempStore};
+    use update;
+
+    const SHA256: &'static str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn checksum_files() {
+        assert_eq!(
+            parse_checksum(&format!("{}  MyVendor.MyPack.1.0.0.pack\n", SHA256.to_uppercase())),
+            Some(SHA256.to_string())
+        );
+        assert_eq!(parse_checksum("<html>Not Found</html>"), None);
+        assert_eq!(parse_checksum(""), None);
+
+        let logger = Logger::root(Discard, o!());
+        let options = DownloadOptions::default();
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let mut fetch = |response: &'static str| {
+            let (uri, _) = serve(vec![response], Duration::from_millis(0));
+            core.run(fetch_checksum(Some(uri.to_string()), &client, &options, &logger)).unwrap()
+        };
+        assert_eq!(
+            fetch("HTTP/1.1 200 OK\r\nContent-Length: 65\r\n\r\n\
+                   e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n"),
+            Some(SHA256.to_string())
+        );
+        assert_eq!(fetch("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"), None);
+        assert_eq!(core.run(fetch_checksum(None, &client, &options, &logger)).unwrap(), None);
+    }
+
+    /// Counts the files it is told of, and those completed.
+    #[derive(Clone, Default)]
+    struct Counted(Arc<Mutex<(usize, usize)>>);
+
+    impl DownloadProgress for Counted {
+        fn size(&self, files: usize) {
+            self.0.lock().unwrap().0 += files;
+        }
+        fn file_size(&self, _: usize) {}
+        fn progress(&self, _: usize) {}
+        fn resumed(&self, _: usize) {}
+        fn failed(&self, _: &str) {}
+        fn complete(&self) {
+            self.0.lock().unwrap().1 += 1;
+        }
+        fn for_file(&self, _: &str) -> Self {
+            self.clone()
+        }
+    }
+
+    /// Records the byte counts it is given.
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<(usize, usize)>>);
+
+    impl DownloadProgress for Recorded {
+        fn size(&self, _: usize) {}
+        fn file_size(&self, bytes: usize) {
+            self.0.lock().unwrap().0 += bytes;
+        }
+        fn progress(&self, bytes: usize) {
+            self.0.lock().unwrap().1 += bytes;
+        }
+        fn resumed(&self, _: usize) {}
+        fn failed(&self, _: &str) {}
+        fn complete(&self) {}
+        fn for_file(&self, _: &str) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn current_files_complete() {
+        let logger = Logger::root(Discard, o!());
+        let store = TempStore::new("complete");
+        let pdsc = PdscRef {
+            url: "http://localhost/".to_string(),
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: "1.0.0".to_string(),
+            date: None,
+            deprecated: None,
+            replacement: None,
+            size: None,
+        };
+        write(&pdsc.into_fd(&store.config), b"<package>
+          <vendor>MyVendor</vendor>
+          <name>MyPack</name>
+          <description>A pack</description>
+          <url>http://localhost/</url>
+          <releases><release version=\"1.0.0\">Release</release></releases>
+        </package>");
+        let cache = HttpCache::load(&store.config, &logger);
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let progress = Counted::default();
+        let stream = download_stream(&store.config, iter_ok(vec![pdsc]), &client, &cache, &logger, progress.clone());
+        assert_eq!(core.run(stream.collect()).unwrap().len(), 1);
+        assert_eq!(*progress.0.lock().unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn every_file_completes() {
+        let logger = Logger::root(Discard, o!());
+        let mut store = TempStore::new("complete");
+        store.config.download.skip_deprecated = true;
+        let upstream = store.path().join("upstream");
+        write(&upstream.join("MyVendor.MyPack.pdsc"), b"<package>
+          <vendor>MyVendor</vendor>
+          <name>MyPack</name>
+          <description>A pack</description>
+          <url>http://example.com/</url>
+          <releases><release version=\"1.0.0\">Release</release></releases>
+        </package>");
+        let index = store.path().join("index.pidx");
+        write(&index, format!("<index>
+          <vendor>Local</vendor>
+          <url>file://{0}/</url>
+          <pindex>
+            <pdsc url=\"file://{0}/\" vendor=\"MyVendor\" name=\"MyPack\" version=\"1.0.0\"/>
+            <pdsc url=\"file://{0}/\" vendor=\"MyVendor\" name=\"OldPack\" version=\"1.0.0\"
+                  deprecated=\"2019-01-01\"/>
+          </pindex>
+        </index>", upstream.display()).as_bytes());
+        let vidx_list = vec![index.to_string_lossy().into_owned()];
+
+        for _ in 0..2 {
+            let progress = Counted::default();
+            let report = update(&store.config, vidx_list.clone(), &logger, progress.clone()).unwrap();
+            assert_eq!(*progress.0.lock().unwrap(), (2, 2));
+            assert_eq!(report.count(Outcome::Deprecated), 1);
+        }
+    }
+
+    #[test]
+    fn cancel_hung_update() {
+        let logger = Logger::root(Discard, o!());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://localhost:{}/index.pidx", listener.local_addr().unwrap().port());
+        thread::spawn(move || {
+            let _hung = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(30));
+        });
+        let store = TempStore::new("cancel");
+        let cancel = store.config.download.cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            cancel.cancel();
+        });
+        let started = Instant::now();
+        let res = update(&store.config, vec![url], &logger, ());
+        assert!(res.unwrap_err().downcast_ref::<Cancelled>().is_some());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn resume_downloads() {
+        let logger = Logger::root(Discard, o!());
+        let store = TempStore::new("resume");
+        let options = DownloadOptions::default();
+        let cache = HttpCache::load(&store.config, &logger);
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let dest = store.path().join("MyVendor.MyPack.1.0.0.pack");
+        let temp = dest.with_extension("part");
+        let mut started = Headers::new();
+        started.set(ETag(EntityTag::strong("v1".to_string())));
+        let mut run = |response: &'static str| {
+            let (uri, requests) = serve(vec![response], Duration::from_millis(0));
+            let url = uri.to_string();
+            cache.record_partial(&url, &started);
+            write(&temp, b"pa");
+            let transfer = Rc::new(Transfer::new(()));
+            let res = core.run(download_file(uri, dest.clone(), &client, &cache, &options, &logger, transfer));
+            (res, requests.recv().unwrap(), read_to_string(&temp).ok())
+        };
+
+        let (res, request, part) = run(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 2-3/4\r\nContent-Length: 2\r\n\r\nrt"
+        );
+        assert!(res.unwrap().is_some());
+        assert!(request.contains("Range: bytes=2-\r\n"));
+        assert!(request.contains("If-Range: \"v1\"\r\n"));
+        assert_eq!(part.unwrap(), "part");
+
+        let (res, _, part) = run("HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nContent-Length: 4\r\n\r\nnew!");
+        assert!(res.unwrap().is_some());
+        assert_eq!(part.unwrap(), "new!");
+
+        let (res, _, part) = run("HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n\r\n");
+        assert!(res.is_err());
+        assert!(part.is_none());
+    }
+
+    /// A file at `url`, saved to `dest`.
+    struct Plain(String, PathBuf);
+
+    impl IntoDownload for Plain {
+        fn into_source(&self, _: &Config) -> Result<Source, Error> {
+            Source::parse(&self.0)
+        }
+
+        fn into_fd(&self, _: &Config) -> PathBuf {
+            self.1.clone()
+        }
+    }
+
+    #[test]
+    fn retry_interrupted_bodies() {
+        let logger = Logger::root(Discard, o!());
+        let mut store = TempStore::new("retry");
+        store.config.download.retries = 1;
+        store.config.download.backoff = Duration::from_millis(10);
+        let config = &store.config;
+        let cache = HttpCache::load(config, &logger);
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let (uri, requests) = serve(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 4\r\n\r\npa",
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 2-3/4\r\nContent-Length: 2\r\n\r\nrt",
+        ], Duration::from_millis(0));
+        let dest = store.path().join("MyVendor.MyPack.pdsc");
+        let progress = Recorded::default();
+        let items = core.run(download_stream(
+            config, iter_ok(vec![Plain(uri.to_string(), dest.clone())]), &client, &cache, &logger,
+            progress.clone()
+        ).collect()).unwrap();
+        assert_eq!(items[0].outcome, Outcome::Downloaded);
+        assert_eq!(read_to_string(&dest).unwrap(), "part");
+        assert!(requests.iter().nth(1).unwrap().contains("Range: bytes=2-\r\n"));
+        assert_eq!(*progress.0.lock().unwrap(), (4, 4));
+    }
+
+    #[test]
+    fn local_copies_report_bytes() {
+        let store = TempStore::new("download");
+        let source = store.path().join("source.pdsc");
+        write(&source, b"<package/>");
+        let progress = Recorded::default();
+        let transfer = Transfer::new(progress.clone());
+        copy_file(&source, &store.path().join("dest.pdsc"), &transfer, |_| Ok(())).unwrap();
+        assert_eq!(*progress.0.lock().unwrap(), (10, 10));
+        assert_eq!(transfer.bytes.get(), 10);
+    }
+
+    #[test]
+    fn local_sources() {
+        let local = |path: &str| Source::Local(PathBuf::from(path));
+        assert_eq!(Source::parse("file:///srv/index.pidx").unwrap(), local("/srv/index.pidx"));
+        assert_eq!(Source::parse("file://localhost/srv/a%20b.pack").unwrap(), local("/srv/a b.pack"));
+        assert_eq!(Source::parse("file:///C:/packs/index.pidx").unwrap(), local("C:/packs/index.pidx"));
+        assert_eq!(Source::parse("mirror/index.pidx").unwrap(), local("mirror/index.pidx"));
+        let url = file_url(Path::new("/srv/100% a b/index.pidx"));
+        assert_eq!(url, "file:///srv/100%25%20a%20b/index.pidx");
+        assert_eq!(Source::parse(&url).unwrap(), local("/srv/100% a b/index.pidx"));
+        match Source::parse("http://localhost:8001/index.pidx").unwrap() {
+            Source::Remote(uri) => assert_eq!(uri.port(), Some(8001)),
+            other => panic!("expected a remote source, got {}", other),
+        }
+    }
 }
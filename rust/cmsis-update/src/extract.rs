@@ -0,0 +1,330 @@
+//! Unpacking downloaded packs.
+//!
+//! A pack release is extracted next to its `.pack` file, into
+//! `Vendor/Name/version/`, the layout of Keil's `PACK_ROOT`. The directory is
+//! only complete once it holds an `.installed` marker.
+
+use std::fs::{create_dir_all, remove_dir_all, rename, File};
+use std::io::{copy, Read, Write};
+use std::path::{Path, PathBuf};
+
+use failure::{err_msg, Error};
+use slog::Logger;
+use zip::ZipArchive;
+
+use pack_index::config::Config;
+use pdsc::{FileRef, Package};
+use utils::parse::FromElem;
+
+use dl_pack::PackRelease;
+use lock::sha256_file;
+
+pub(crate) const INSTALLED_MARKER: &str = ".installed";
+
+/// Join a path from a pack, with either separator, onto `root`. Absolute
+/// paths and `..` leading outside of `root` are rejected.
+fn join_inside(root: &Path, relative: &str) -> Result<PathBuf, Error> {
+    if relative.starts_with('/') || relative.starts_with('\\') || relative.contains(':') {
+        return Err(err_msg(format!("{} is not a relative path", relative)));
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    for part in relative.split(&['/', '\\'][..]) {
+        match part {
+            "" | "." => (),
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(err_msg(format!("{} leads outside of the pack", relative)));
+                }
+            }
+            _ => parts.push(part),
+        }
+    }
+    let mut path = root.to_path_buf();
+    path.extend(parts);
+    Ok(path)
+}
+
+/// Unpack `pack` into `dest`, which must not exist yet.
+fn unpack(pack: &Path, dest: &Path) -> Result<(), Error> {
+    let mut archive = ZipArchive::new(File::open(pack)?)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let path = join_inside(dest, entry.name())?;
+        if entry.name().ends_with('/') || entry.name().ends_with('\\') {
+            create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        copy(&mut entry, &mut File::create(&path)?)?;
+    }
+    Ok(())
+}
+
+/// Check that `pack` is a complete archive of `release`: it matches the
+/// expected size and checksum, if any, and holds a PDSC for the same
+/// vendor, name and version.
+pub(crate) fn verify_pack(pack: &Path, release: &PackRelease, l: &Logger) -> Result<(), Error> {
+    let file = File::open(pack)?;
+    if let Some(size) = release.size {
+        let actual = file.metadata()?.len();
+        if actual != size {
+            return Err(err_msg(format!("pack is {} bytes, expected {}", actual, size)));
+        }
+    }
+    if let Some(ref expected) = release.sha256 {
+        let actual = sha256_file(pack)?;
+        if &actual != expected {
+            return Err(err_msg(format!("pack has checksum {}, expected {}", actual, expected)));
+        }
+    }
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| err_msg(format!("not a valid pack archive: {}", e)))?;
+    let pdsc_index = (0..archive.len()).find(|&index| {
+        archive
+            .by_index(index)
+            .map(|entry| {
+                let name = entry.name();
+                name.ends_with(".pdsc") && !name.contains('/') && !name.contains('\\')
+            })
+            .unwrap_or(false)
+    });
+    let mut content = String::new();
+    match pdsc_index {
+        Some(index) => archive.by_index(index)?.read_to_string(&mut content)?,
+        None => return Err(err_msg("pack does not contain a PDSC")),
+    };
+    let pdsc = Package::from_string(&content, l)
+        .map_err(|e| err_msg(format!("parsing the PDSC in the pack: {}", e)))?;
+    let version = &pdsc.releases.latest_release().version;
+    if pdsc.vendor != release.vendor || pdsc.name != release.name || *version != release.version {
+        return Err(err_msg(format!(
+            "pack contains {}.{} {}, expected {}.{} {}",
+            pdsc.vendor, pdsc.name, version, release.vendor, release.name, release.version
+        )));
+    }
+    Ok(())
+}
+
+impl PackRelease {
+    /// Where the pack is extracted to in the pack store.
+    pub fn install_dir(&self, config: &Config) -> PathBuf {
+        let mut dir = config.pack_store.clone();
+        dir.push(Path::new(&self.vendor));
+        dir.push(Path::new(&self.name));
+        dir.push(self.version.as_str());
+        dir
+    }
+
+    /// Has this release been downloaded and completely extracted?
+    pub fn is_installed(&self, config: &Config) -> bool {
+        self.install_dir(config).join(INSTALLED_MARKER).exists()
+    }
+}
+
+/// A pack release that has been extracted into the pack store.
+#[derive(Debug, Clone)]
+pub struct InstalledPack {
+    root: PathBuf,
+}
+
+impl InstalledPack {
+    /// Extract the downloaded `.pack` of `release`, unless that has
+    /// already been done. Extraction goes through a temporary directory, so
+    /// a failure never leaves a half populated install behind.
+    pub fn extract(config: &Config, release: &PackRelease) -> Result<Self, Error> {
+        let root = release.install_dir(config);
+        if release.is_installed(config) {
+            return Ok(InstalledPack { root });
+        }
+        let pack = release.pack_path(config);
+        let temp = root.with_file_name(format!("{}.extracting", release.version));
+        if temp.exists() {
+            remove_dir_all(&temp)?;
+        }
+        create_dir_all(&temp)?;
+        let extracted = unpack(&pack, &temp).and_then(|_| {
+            let mut marker = File::create(temp.join(INSTALLED_MARKER))?;
+            writeln!(marker, "{}", sha256_file(&pack)?)?;
+            if root.exists() {
+                remove_dir_all(&root)?;
+            }
+            rename(&temp, &root)?;
+            Ok(())
+        });
+        if let Err(e) = extracted {
+            let _ = remove_dir_all(&temp);
+            return Err(err_msg(format!("extracting {}: {}", pack.display(), e)));
+        }
+        Ok(InstalledPack { root })
+    }
+
+    /// Open an already extracted pack release.
+    pub fn open(config: &Config, release: &PackRelease) -> Result<Self, Error> {
+        if release.is_installed(config) {
+            Ok(InstalledPack {
+                root: release.install_dir(config),
+            })
+        } else {
+            Err(err_msg(format!(
+                "{}.{} {} is not installed",
+                release.vendor, release.name, release.version
+            )))
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The on-disk location of a file the pack's PDSC refers to.
+    pub fn resolve(&self, file: &FileRef) -> Result<PathBuf, Error> {
+        self.resolve_path(&file.path)
+    }
+
+    pub fn resolve_path(&self, path: &Path) -> Result<PathBuf, Error> {
+        join_inside(&self.root, &path.to_string_lossy())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use hyper::{Body, Client};
+    use hyper::client::HttpConnector;
+    use pdsc::Version;
+    use slog::{Discard, Logger};
+    use tokio_core::reactor::Core;
+
+    use report::Outcome;
+    use testing::{serve, write_pack, TempStore};
+
+    #[test]
+    fn paths_stay_inside() {
+        let root = Path::new("root");
+        assert_eq!(
+            join_inside(root, "Device\\Include\\dev.h").unwrap(),
+            root.join("Device/Include/dev.h")
+        );
+        assert_eq!(join_inside(root, "a/../b/./c.h").unwrap(), root.join("b/c.h"));
+        assert!(join_inside(root, "../escape.h").is_err());
+        assert!(join_inside(root, "a/../../escape.h").is_err());
+        assert!(join_inside(root, "/etc/passwd").is_err());
+        assert!(join_inside(root, "C:\\Windows").is_err());
+    }
+
+    #[test]
+    fn extract_pack() {
+        let store = TempStore::new("extract");
+        let config = &store.config;
+        let release = PackRelease {
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: Version::new(1, 0, 0),
+            url: "http://localhost/MyVendor.MyPack.1.0.0.pack".to_string(),
+            size: None,
+            sha256: None,
+        };
+        write_pack(
+            &release.pack_path(config),
+            &[("MyVendor.MyPack.pdsc", "<package/>"), ("Device/Include/dev.h", "#define DEV")],
+        );
+        assert!(InstalledPack::open(config, &release).is_err());
+        let installed = InstalledPack::extract(config, &release).unwrap();
+        assert!(release.is_installed(config));
+        assert_eq!(installed.root(), store.path().join("MyVendor/MyPack/1.0.0").as_path());
+        let header = installed.resolve_path(Path::new("Device\\Include\\dev.h")).unwrap();
+        assert!(header.is_file());
+        assert!(installed.resolve_path(Path::new("../../escape")).is_err());
+
+        let evil = PackRelease {
+            version: Version::new(2, 0, 0),
+            ..release.clone()
+        };
+        write_pack(&evil.pack_path(config), &[("../../evil.h", "")]);
+        assert!(InstalledPack::extract(config, &evil).is_err());
+        assert!(!evil.install_dir(config).exists());
+        assert!(!store.path().join("MyVendor/evil.h").exists());
+    }
+
+    #[test]
+    fn corrupt_download_is_not_installed() {
+        let log = Logger::root(Discard, o!());
+        let store = TempStore::new("corrupt");
+        let config = &store.config;
+        let (uri, _) = serve(
+            vec![
+                "HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 22\r\n\r\n<html>Not Found</html>",
+            ],
+            Duration::from_millis(0),
+        );
+        let release = PackRelease {
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: Version::new(1, 0, 0),
+            url: format!("http://{}/MyVendor.MyPack.1.0.0.pack", uri.authority().unwrap()),
+            size: None,
+            sha256: None,
+        };
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let downloaded = ::install_inner(config, vec![release.clone()], &mut core, &client, &log, ());
+        assert_eq!(downloaded.unwrap().count(Outcome::Failed), 1);
+        assert!(!release.pack_path(config).exists());
+        assert!(!release.install_dir(config).exists());
+    }
+
+    const PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"1.0.0\"/></releases>
+    </package>";
+
+    #[test]
+    fn verify_downloaded_pack() {
+        let log = Logger::root(Discard, o!());
+        let store = TempStore::new("verify");
+        let pack = store.path().join("MyVendor.MyPack.1.0.0.pack");
+        let mut release = PackRelease {
+            vendor: "MyVendor".to_string(),
+            name: "MyPack".to_string(),
+            version: Version::new(1, 0, 0),
+            url: "http://localhost/MyVendor.MyPack.1.0.0.pack".to_string(),
+            size: None,
+            sha256: None,
+        };
+        write_pack(&pack, &[("MyVendor.MyPack.pdsc", PDSC)]);
+        verify_pack(&pack, &release, &log).unwrap();
+
+        release.sha256 = Some(sha256_file(&pack).unwrap());
+        release.size = Some(pack.metadata().unwrap().len());
+        verify_pack(&pack, &release, &log).unwrap();
+        release.size = Some(1);
+        assert!(verify_pack(&pack, &release, &log).is_err());
+        release.size = None;
+        release.sha256 = Some("00".to_string());
+        assert!(verify_pack(&pack, &release, &log).is_err());
+        release.sha256 = None;
+
+        let other = PackRelease {
+            version: Version::new(1, 1, 0),
+            ..release.clone()
+        };
+        assert!(verify_pack(&pack, &other, &log).is_err());
+
+        let len = pack.metadata().unwrap().len();
+        let mut truncated = Vec::new();
+        File::open(&pack).unwrap().read_to_end(&mut truncated).unwrap();
+        truncated.truncate(len as usize - 10);
+        File::create(&pack).unwrap().write_all(&truncated).unwrap();
+        assert!(verify_pack(&pack, &release, &log).is_err());
+
+        File::create(&pack).unwrap().write_all(b"<html>Not Found</html>").unwrap();
+        assert!(verify_pack(&pack, &release, &log).is_err());
+    }
+}
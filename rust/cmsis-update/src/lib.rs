@@ -2,10 +2,22 @@
 
 extern crate futures;
 extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_rustls;
+extern crate tokio_timer;
 extern crate hyper;
 extern crate hyper_rustls;
+extern crate rustls;
 extern crate minidom;
 extern crate failure;
+extern crate ring;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate webpki;
+extern crate webpki_roots;
+extern crate zip;
 
 #[macro_use]
 extern crate slog;
@@ -16,25 +28,45 @@ extern crate pdsc;
 
 use hyper::{Body, Client};
 use hyper::client::Connect;
-use hyper_rustls::HttpsConnector;
 use tokio_core::reactor::Core;
-use std::path::PathBuf;
 use slog::Logger;
 use failure::Error;
 
 use pack_index::config::Config;
-use pdsc::Package;
+use utils::ResultLogExt;
 
 pub mod upgrade;
+mod cache;
+mod connect;
 mod redirect;
 mod vidx;
 mod download;
 mod dl_pdsc;
 mod dl_pack;
+mod extract;
+mod lock;
+mod mirror;
+mod registry;
+mod remove;
+mod report;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(test)]
+mod testing;
 
+use cache::HttpCache;
 use dl_pdsc::{update_future};
 use dl_pack::{install_future};
-pub use download::DownloadProgress;
+pub use dl_pack::{IntoPackRelease, PackRelease};
+pub use extract::InstalledPack;
+pub use download::{file_url, Cancelled, DownloadProgress};
+pub use lock::{sha256_file, LockFile, LockedPack};
+pub use mirror::mirror;
+pub use registry::{PackState, Registry, RegistryEntry};
+pub use remove::{delete, plan_gc, plan_remove, Reason, Removal};
+pub use report::{Outcome, ReportItem, UpdateReport};
+#[cfg(feature = "serve")]
+pub use serve::PackServer;
 
 // This will "trick" the borrow checker into thinking that the lifetimes for
 // client and core are at least as big as the lifetime for pdscs, which they actually are
@@ -45,27 +77,27 @@ fn update_inner<'a, C, I, P>(
     client: &'a Client<C, Body>,
     logger: &'a Logger,
     progress: P,
-) -> Result<Vec<PathBuf>, Error>
+) -> Result<UpdateReport, Error>
 where
     C: Connect,
     I: IntoIterator<Item = String>,
     P: DownloadProgress + 'a,
 {
-    core.run(update_future(config, vidx_list, client, logger, progress))
+    let cache = HttpCache::load(config, logger);
+    let updated = core.run(update_future(config, vidx_list, client, &cache, logger, progress));
+    cache.save().ok_warn(logger);
+    updated
 }
 
-/// Flatten a list of Vidx Urls into a list of updated CMSIS packs
-pub fn update<I, P>(config: &Config, vidx_list: I, logger: &Logger, progress: P) -> Result<Vec<PathBuf>, Error>
+/// Flatten a list of Vidx Urls into the PDSC files of their CMSIS packs,
+/// reporting what became of each of them.
+pub fn update<I, P>(config: &Config, vidx_list: I, logger: &Logger, progress: P) -> Result<UpdateReport, Error>
 where
     I: IntoIterator<Item = String>,
     P: DownloadProgress,
 {
     let mut core = Core::new().unwrap();
-    let handle = core.handle();
-    let client: Client<HttpsConnector, _> = Client::configure()
-        .keep_alive(true)
-        .connector(HttpsConnector::new(4, &handle))
-        .build(&handle);
+    let client = connect::client(config, &core.handle())?;
     update_inner(config, vidx_list, &mut core, &client, logger, progress)
 }
 
@@ -78,31 +110,53 @@ fn install_inner<'client, 'a: 'client, C, I: 'a, P: 'client>(
     client: &'client Client<C, Body>,
     logger: &'a Logger,
     progress: P
-) -> Result<Vec<PathBuf>, Error>
+) -> Result<UpdateReport, Error>
     where
     C: Connect,
-    I: IntoIterator<Item = &'a Package>,
+    I: IntoIterator<Item = PackRelease>,
     P: DownloadProgress + 'a
 {
-    core.run(install_future(config, pdsc_list, client, logger, progress))
+    let cache = HttpCache::load(config, logger);
+    let installed = core.run(install_future(config, pdsc_list, client, &cache, logger, progress));
+    cache.save().ok_warn(logger);
+    installed
 }
 
-/// Flatten a list of Vidx Urls into a list of updated CMSIS packs
-pub fn install<'a, I: 'a, P>(
+/// Download and extract the packs in a list of pack releases, reporting
+/// what became of each `.pack` file. A pack that fails to extract is
+/// reported as failed.
+///
+/// Each item is either a `&Package`, for its latest release, or a
+/// `(&Package, Version)` pair. Every version is checked against the pack's
+/// `<releases>` before anything is downloaded.
+pub fn install<'a, I, T, P>(
     config: &'a Config,
     pdsc_list: I,
     logger: &'a Logger,
     progress: P
-) -> Result<Vec<PathBuf>, Error>
+) -> Result<UpdateReport, Error>
     where
-    I: IntoIterator<Item = &'a Package>,
+    I: IntoIterator<Item = T>,
+    T: IntoPackRelease,
     P: DownloadProgress + 'a,
 {
+    let pdsc_list = pdsc_list
+        .into_iter()
+        .map(IntoPackRelease::into_pack_release)
+        .collect::<Result<Vec<_>, _>>()?;
     let mut core = Core::new().unwrap();
-    let handle = core.handle();
-    let client: Client<HttpsConnector, _> = Client::configure()
-        .keep_alive(true)
-        .connector(HttpsConnector::new(4, &handle))
-        .build(&handle);
-    install_inner(config, pdsc_list, &mut core, &client, logger, progress)
+    let client = connect::client(config, &core.handle())?;
+    let mut report = install_inner(config, pdsc_list.clone(), &mut core, &client, logger, progress)?;
+    let downloaded = report.paths();
+    for release in &pdsc_list {
+        let path = release.pack_path(config);
+        if !downloaded.contains(&path) {
+            continue;
+        }
+        if let Err(e) = InstalledPack::extract(config, release) {
+            slog_error!(logger, "{}", e);
+            report.fail(&path, e.to_string());
+        }
+    }
+    Ok(report)
 }
@@ -0,0 +1,205 @@
+//! Lockfiles pin the exact set of installed packs.
+//!
+//! A lockfile is JSON, listing the vendor, name, version, download URL and
+//! SHA-256 of every locked `.pack`, so that a later `sync` reproduces the
+//! same pack store byte for byte.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use failure::{err_msg, Error};
+use ring::digest::{Context, SHA256};
+use serde_json;
+
+use pack_index::config::Config;
+use pdsc::{Package, Version};
+
+use dl_pack::{IntoPackRelease, PackRelease};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPack {
+    pub vendor: String,
+    pub name: String,
+    pub version: Version,
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockFile {
+    pub packs: Vec<LockedPack>,
+}
+
+/// The hex encoded SHA-256 of a file.
+pub fn sha256_file(path: &Path) -> Result<String, Error> {
+    let mut fd = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = fd.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+impl LockedPack {
+    fn release(&self) -> PackRelease {
+        PackRelease {
+            vendor: self.vendor.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            url: self.url.clone(),
+            size: None,
+            sha256: Some(self.sha256.clone()),
+        }
+    }
+
+    /// Check the stored `.pack` against the locked checksum.
+    pub fn verify(&self, config: &Config) -> Result<(), Error> {
+        let path = self.release().pack_path(config);
+        if !path.exists() {
+            return Err(err_msg(format!(
+                "{}.{} {} is not installed",
+                self.vendor, self.name, self.version
+            )));
+        }
+        let sha256 = sha256_file(&path)?;
+        if sha256 != self.sha256 {
+            return Err(err_msg(format!(
+                "{}.{} {} has checksum {}, but the lockfile expects {}",
+                self.vendor, self.name, self.version, sha256, self.sha256
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl IntoPackRelease for &LockedPack {
+    fn into_pack_release(self) -> Result<PackRelease, Error> {
+        Ok(self.release())
+    }
+}
+
+impl LockFile {
+    /// Lock the newest installed release of each pack in `pdscs`.
+    pub fn from_store<'a, I>(config: &Config, pdscs: I) -> Result<Self, Error>
+        where I: IntoIterator<Item = &'a Package>,
+    {
+        let mut packs: Vec<LockedPack> = Vec::new();
+        for pdsc in pdscs {
+            let installed = pdsc.releases
+                .iter()
+                .map(|release| PackRelease::new(pdsc, &release.version))
+                .filter_map(Result::ok)
+                .find(|release| release.pack_path(config).exists());
+            if let Some(release) = installed {
+                let duplicate = packs.iter().any(|locked| {
+                    locked.vendor == release.vendor && locked.name == release.name
+                        && locked.version >= release.version
+                });
+                if duplicate {
+                    continue;
+                }
+                packs.retain(|locked| locked.vendor != release.vendor || locked.name != release.name);
+                packs.push(LockedPack {
+                    sha256: sha256_file(&release.pack_path(config))?,
+                    vendor: release.vendor,
+                    name: release.name,
+                    version: release.version,
+                    url: release.url,
+                });
+            }
+        }
+        packs.sort_by(|a, b| (&a.vendor, &a.name).cmp(&(&b.vendor, &b.name)));
+        Ok(LockFile { packs })
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let fd = File::open(path)?;
+        Ok(serde_json::from_reader(fd)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut fd = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        serde_json::to_writer_pretty(&mut fd, self)?;
+        fd.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Check every locked pack, reporting all mismatches at once.
+    pub fn verify(&self, config: &Config) -> Result<(), Error> {
+        let errors: Vec<String> = self.packs
+            .iter()
+            .filter_map(|locked| locked.verify(config).err())
+            .map(|e| e.to_string())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(err_msg(errors.join("\n")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::create_dir_all;
+    use slog::{Discard, Logger};
+    use utils::parse::FromElem;
+
+    use testing::TempStore;
+
+    const PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases>
+        <release version=\"1.1.0\">latest</release>
+        <release version=\"1.0.0\">old</release>
+      </releases>
+    </package>";
+
+    #[test]
+    fn lock_installed_packs() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(PDSC, &log).unwrap();
+        let store = TempStore::new("lock");
+        let config = &store.config;
+        let installed = PackRelease::new(&pack, &Version::new(1, 0, 0)).unwrap();
+        let path = installed.pack_path(config);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap().write_all(b"abc").unwrap();
+
+        let lock = LockFile::from_store(config, Some(&pack)).unwrap();
+        assert_eq!(lock.packs.len(), 1);
+        assert_eq!(lock.packs[0].version, Version::new(1, 0, 0));
+        assert_eq!(lock.packs[0].url, "http://localhost/MyVendor.MyPack.1.0.0.pack");
+        assert_eq!(
+            lock.packs[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        lock.verify(config).unwrap();
+
+        let lockfile = store.path().join("cmsis.lock");
+        lock.write(&lockfile).unwrap();
+        assert_eq!(LockFile::read(&lockfile).unwrap(), lock);
+
+        File::create(&path).unwrap().write_all(b"abd").unwrap();
+        assert!(lock.verify(config).is_err());
+    }
+}
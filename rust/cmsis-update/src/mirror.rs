@@ -0,0 +1,343 @@
+//! Offline mirrors of the pack index.
+//!
+//! A mirror is a directory laid out like `tests/test-pack-index`: an
+//! `index.pidx`, one `Vendor.Name.pdsc` per pack and the mirrored
+//! `Vendor.Name.version.pack` files. The PDSC files point back at the
+//! mirror, so it can be used through a `file://` URL or served over HTTP.
+//! The PDSC files as published upstream are kept in `.upstream`.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use failure::{err_msg, Error};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use minidom::{Element, Node};
+use slog::Logger;
+use tokio_core::reactor::Core;
+
+use pack_index::PdscRef;
+use pack_index::config::Config;
+use pdsc::{Package, Version};
+use utils::ResultLogExt;
+use utils::parse::FromElem;
+
+use cache::HttpCache;
+use connect;
+use dl_pack::PackRelease;
+use download::{download_stream, DownloadProgress, IntoDownload, Source};
+use report::UpdateReport;
+use vidx::{download_vidx_list, flatmap_pdscs};
+
+/// A download saved under a fixed name in the mirror.
+struct Mirrored<T> {
+    inner: T,
+    dest: PathBuf,
+}
+
+impl<T: IntoDownload> IntoDownload for Mirrored<T> {
+    fn into_source(&self, config: &Config) -> Result<Source, Error> {
+        self.inner.into_source(config)
+    }
+
+    fn into_fd(&self, _: &Config) -> PathBuf {
+        self.dest.clone()
+    }
+
+    fn is_current(&self, dest: &Path, logger: &Logger) -> bool {
+        self.inner.is_current(dest, logger)
+    }
+
+    fn checksum_url(&self) -> Option<String> {
+        self.inner.checksum_url()
+    }
+
+    fn verify(&self, path: &Path, sha256: Option<String>, config: &Config, logger: &Logger) -> Result<(), Error> {
+        self.inner.verify(path, sha256, config, logger)
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write `element` back out as XML. minidom keeps attribute values
+/// escaped but text unescaped, and takes the URI of an `xmlns:prefix`
+/// declaration for the element's namespace, dropping the prefix, so its
+/// own writer does not round trip a PDSC.
+fn write_element(element: &Element, parent_ns: Option<&str>, out: &mut String) {
+    out.push('<');
+    out.push_str(element.name());
+    match element.ns() {
+        Some(ns) if element.ns() != parent_ns => {
+            let prefix = element.attrs().filter_map(|(key, _)| key.find(':').map(|colon| &key[..colon])).next();
+            match prefix {
+                Some(prefix) => out.push_str(&format!(" xmlns:{}=\"{}\"", prefix, ns)),
+                None => out.push_str(&format!(" xmlns=\"{}\"", ns)),
+            }
+        }
+        _ => (),
+    }
+    for (key, value) in element.attrs() {
+        out.push_str(&format!(" {}=\"{}\"", key, value));
+    }
+    if element.nodes().next().is_none() {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+    for node in element.nodes() {
+        match *node {
+            Node::Element(ref child) => write_element(child, element.ns(), out),
+            Node::Text(ref text) => out.push_str(&escape(text)),
+        }
+    }
+    out.push_str(&format!("</{}>", element.name()));
+}
+
+/// Point a PDSC at the mirror: replace the package `<url>`, and the `url`
+/// of its releases, which would lead back upstream, with the mirror's.
+pub(crate) fn rewrite_pdsc(pdsc: &str, base_url: &str) -> Result<String, Error> {
+    let mut package: Element = pdsc.parse().map_err(|e| err_msg(format!("parsing PDSC: {}", e)))?;
+    let child_text = |name: &str| {
+        package
+            .children()
+            .find(|child| child.name() == name)
+            .map(|child| child.text().trim().to_string())
+            .unwrap_or_default()
+    };
+    let (vendor, name) = (child_text("vendor"), child_text("name"));
+    for child in package.children_mut() {
+        match child.name() {
+            "url" => {
+                for node in child.nodes_mut() {
+                    *node = Node::Text(String::new());
+                }
+                child.append_text_node(base_url);
+            }
+            "releases" => {
+                for release in child.children_mut().filter(|release| release.name() == "release") {
+                    let version = match (release.attr("url"), release.attr("version")) {
+                        (Some(_), Some(version)) => version.to_string(),
+                        _ => continue,
+                    };
+                    let url = format!("{}{}.{}.{}.pack", base_url, vendor, name, version);
+                    release.set_attr("url", escape(&url));
+                }
+            }
+            _ => (),
+        }
+    }
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n");
+    write_element(&package, None, &mut out);
+    out.push('\n');
+    Ok(out)
+}
+
+/// An index of `vendor` listing the latest release of each pack, with the
+/// PDSC files found at `base_url`.
+pub(crate) fn pdsc_index<'a, I>(vendor: &str, base_url: &str, packs: I) -> String
+    where I: IntoIterator<Item = &'a Package>,
+{
+    let mut index = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n");
+    index.push_str("<index schemaVersion=\"1.0.0\">\n");
+    index.push_str(&format!("  <vendor>{}</vendor>\n", escape(vendor)));
+    index.push_str(&format!("  <url>{}</url>\n", escape(base_url)));
+    index.push_str("  <pindex>\n");
+    for pack in packs {
+        let latest = pack.releases.latest_release();
+        let date = match latest.date {
+            Some(ref date) => format!(" date=\"{}\"", escape(date)),
+            None => String::new(),
+        };
+        index.push_str(&format!(
+            "    <pdsc url=\"{}\" vendor=\"{}\" name=\"{}\" version=\"{}\"{}/>\n",
+            escape(base_url),
+            escape(&pack.vendor),
+            escape(&pack.name),
+            escape(latest.version.as_str()),
+            date
+        ));
+    }
+    index.push_str("  </pindex>\n</index>\n");
+    index
+}
+
+/// Mirror the whole index, and the listed packs, into `dir`. The mirror
+/// will be reachable at `base_url`, which is written into its index and
+/// PDSC files. Each pack is a `Vendor.Name` with an optional version; the
+/// latest release is mirrored when the version is `None`. The report lists
+/// the upstream PDSC files and the packs.
+pub fn mirror<I, P>(
+    config: &Config,
+    vidx_list: I,
+    packs: &[(String, Option<Version>)],
+    dir: &Path,
+    base_url: &str,
+    logger: &Logger,
+    progress: P,
+) -> Result<UpdateReport, Error>
+where
+    I: IntoIterator<Item = String>,
+    P: DownloadProgress,
+{
+    let mut core = Core::new()?;
+    let client = connect::client(config, &core.handle())?;
+    let cache = HttpCache::load(config, logger);
+    let upstream = dir.join(".upstream");
+    create_dir_all(&upstream)?;
+    let refs: Vec<PdscRef> = core.run(
+        download_vidx_list(vidx_list, &client, &cache, &config.download, logger)
+            .filter_map(|vidx| vidx.map(|v| flatmap_pdscs(v, &client, &cache, &config.download, logger)))
+            .flatten()
+            .collect(),
+    )?;
+    let mut newest: BTreeMap<(String, String), PdscRef> = BTreeMap::new();
+    for pdsc_ref in refs {
+        let key = (pdsc_ref.vendor.clone(), pdsc_ref.name.clone());
+        let replace = match newest.get(&key) {
+            Some(prev) => {
+                Version::parse_lossy(&pdsc_ref.version) > Version::parse_lossy(&prev.version)
+            }
+            None => true,
+        };
+        if replace {
+            newest.insert(key, pdsc_ref);
+        }
+    }
+    let pdsc_downloads: Vec<_> = newest
+        .into_iter()
+        .map(|((vendor, name), pdsc_ref)| Mirrored {
+            dest: upstream.join(format!("{}.{}.pdsc", vendor, name)),
+            inner: pdsc_ref,
+        })
+        .collect();
+    let index_progress = progress.for_file("index");
+    let mut report = UpdateReport::default();
+    report.extend(core.run(
+        download_stream(config, iter_ok(pdsc_downloads), &client, &cache, logger, index_progress)
+            .collect(),
+    )?);
+
+    let mut pdscs: Vec<(PathBuf, String, Package)> = Vec::new();
+    for path in report.paths() {
+        let mut content = String::new();
+        File::open(&path)?.read_to_string(&mut content)?;
+        if let Some(pack) = Package::from_string(&content, logger).ok_warn(logger) {
+            pdscs.push((path, content, pack));
+        }
+    }
+
+    let mut pack_downloads = Vec::new();
+    for (wanted, version) in packs {
+        let pack = pdscs
+            .iter()
+            .map(|(_, _, pack)| pack)
+            .find(|pack| &format!("{}.{}", pack.vendor, pack.name) == wanted)
+            .ok_or_else(|| err_msg(format!("pack {} is not in the index", wanted)))?;
+        let release = match version {
+            Some(version) => PackRelease::new(pack, version)?,
+            None => PackRelease::latest(pack),
+        };
+        pack_downloads.push(Mirrored {
+            dest: dir.join(format!("{}.{}.{}.pack", release.vendor, release.name, release.version)),
+            inner: release,
+        });
+    }
+    report.extend(core.run(
+        download_stream(config, iter_ok(pack_downloads), &client, &cache, logger, progress)
+            .collect(),
+    )?);
+    cache.save().ok_warn(logger);
+
+    for (path, content, _) in &pdscs {
+        if let Some(name) = path.file_name() {
+            File::create(dir.join(name))?.write_all(rewrite_pdsc(content, base_url)?.as_bytes())?;
+        }
+    }
+    let index = pdsc_index("Mirror", base_url, pdscs.iter().map(|(_, _, pack)| pack));
+    File::create(dir.join("index.pidx"))?.write_all(index.as_bytes())?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{copy, read_to_string};
+    use slog::Discard;
+
+    use download::file_url;
+    use report::Outcome;
+    use testing::{write, TempStore};
+    use {install, update};
+
+    #[test]
+    fn rewrite_for_mirror() {
+        let pdsc = "<package xmlns:xs=\"http://www.w3.org/2001/XMLSchema-instance\" xs:noNamespaceSchemaLocation=\"PACK.xsd\">
+          <vendor>MyVendor</vendor>
+          <name>MyPack</name>
+          <description>Drivers &amp; examples</description>
+          <url>http://upstream/packs/</url>
+          <releases>
+            <release version=\"1.1.0\">new</release>
+            <release url=\"http://elsewhere/old.pack\" version=\"1.0.0\">old</release>
+          </releases>
+          <examples>
+            <example name=\"blinky\"><url>http://upstream/blinky/</url></example>
+          </examples>
+        </package>";
+        let rewritten = rewrite_pdsc(pdsc, "file:///mirror/").unwrap();
+        assert!(rewritten.contains("xmlns:xs=\"http://www.w3.org/2001/XMLSchema-instance\""));
+        assert!(rewritten.contains("<url>file:///mirror/</url>"));
+        assert!(rewritten.contains("<release version=\"1.1.0\">new</release>"));
+        assert!(rewritten.contains(
+            "<release url=\"file:///mirror/MyVendor.MyPack.1.0.0.pack\" version=\"1.0.0\">old</release>"
+        ));
+        assert!(rewritten.contains("<description>Drivers &amp; examples</description>"));
+        assert!(rewritten.contains("<url>http://upstream/blinky/</url>"));
+        assert!(!rewritten.contains("elsewhere"));
+    }
+
+    #[test]
+    fn mirror_test_index() {
+        let logger = Logger::root(Discard, o!());
+        let store = TempStore::new("mirror");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/test-pack-index");
+        let upstream = store.path().join("up stream");
+        let upstream_url = format!("{}/", file_url(&upstream));
+        let localize = |name: &str| {
+            let content = read_to_string(fixture.join(name)).unwrap();
+            let content = content
+                .replace("http://localhost:8001/tests/test-pack-index/", &upstream_url)
+                .replace("http://localhost:8001/", &upstream_url);
+            write(&upstream.join(name), content.as_bytes());
+        };
+        localize("index.pidx");
+        localize("MyVendor.MyPack.pdsc");
+        copy(fixture.join("MyVendor.MyPack.1.1.0.pack"), upstream.join("MyVendor.MyPack.1.1.0.pack")).unwrap();
+
+        let dir = store.path().join("mirror 100%");
+        let base_url = format!("{}/", file_url(&dir));
+        let packs = vec![("MyVendor.MyPack".to_string(), None)];
+        let index = upstream.join("index.pidx").to_string_lossy().into_owned();
+        let report = mirror(&store.config, vec![index], &packs, &dir, &base_url, &logger, ()).unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.count(Outcome::Downloaded), 2);
+        assert!(dir.join("MyVendor.MyPack.1.1.0.pack").is_file());
+        let pdsc = Package::from_path(&dir.join("MyVendor.MyPack.pdsc"), &logger).unwrap();
+        assert_eq!(pdsc.url, base_url);
+
+        let client = TempStore::new("mirror-client");
+        let updated = update(&client.config, vec![format!("{}index.pidx", base_url)], &logger, ()).unwrap();
+        assert_eq!(updated.paths(), vec![client.path().join("MyVendor.MyPack.1.1.0.pdsc")]);
+        let pack = Package::from_path(&updated.paths()[0], &logger).unwrap();
+        let installed = install(&client.config, vec![&pack], &logger, ()).unwrap();
+        assert_eq!(installed.paths(), vec![PackRelease::latest(&pack).pack_path(&client.config)]);
+        assert!(PackRelease::latest(&pack).is_installed(&client.config));
+    }
+}
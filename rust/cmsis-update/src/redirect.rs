@@ -1,52 +1,189 @@
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use failure::{self, Fail};
 use futures::prelude::Future;
-use futures::{Async, Poll};
-use hyper::{Error, Body, Client, Response, StatusCode, Uri};
+use futures::{Async, Poll, Stream};
+use futures::future::{err, loop_fn, ok, Either, Loop};
+use hyper::{Error, Body, Chunk, Client, Headers, Method, Request, Response, StatusCode, Uri};
 use hyper::client::{Connect, FutureResponse};
 use hyper::header::Location;
 use slog::Logger;
+use tokio_timer::Delay;
+
+use pack_index::config::DownloadOptions;
+
+use connect::is_proxied;
 
 pub(crate) struct RedirectingFuture<'a, C: Connect> {
     client: &'a Client<C, Body>,
     uri: Uri,
+    headers: Headers,
+    options: &'a DownloadOptions,
     logger: &'a Logger,
     //history: Vec<Uri>,
     cur_get: FutureResponse,
+    deadline: Option<Delay>,
 }
 
 pub(crate) trait ClientRedirExt<C>
 where
     C: Connect,
 {
+    /// GET `uri`, following redirects and sending `headers` with every
+    /// request. Failures are left to `retrying`, which also covers those of
+    /// the response body.
     fn redirectable<'a>(
         &'a self,
         uri: Uri,
+        headers: Headers,
+        options: &'a DownloadOptions,
         logger: &'a Logger,
     ) -> Box<RedirectingFuture<'a, C>>;
 }
 
+fn get<C: Connect>(
+    client: &Client<C, Body>,
+    uri: Uri,
+    headers: &Headers,
+    options: &DownloadOptions,
+) -> FutureResponse {
+    // A proxy is sent the absolute URI of plain `http` requests.
+    let through_proxy = uri.scheme() == Some("http") && is_proxied(&options.proxy, &uri);
+    let mut request = Request::new(Method::Get, uri);
+    request.set_proxy(through_proxy);
+    *request.headers_mut() = headers.clone();
+    client.request(request)
+}
+
 impl<C: Connect> ClientRedirExt<C> for Client<C, Body> {
     fn redirectable<'a>(
         &'a self,
         uri: Uri,
+        headers: Headers,
+        options: &'a DownloadOptions,
         logger: &'a Logger,
     ) -> Box<RedirectingFuture<'a, C>> {
         Box::new(RedirectingFuture{
             client: self,
             uri: uri.clone(),
+            cur_get: get(self, uri, &headers, options),
+            deadline: options.connect_timeout.map(delay),
+            headers,
+            options,
             logger,
             //history: Vec::new(),
-            cur_get: self.get(uri),
         })
     }
 }
 
+fn delay(timeout: Duration) -> Delay {
+    Delay::new(Instant::now() + timeout)
+}
+
+/// Poll a timeout, failing once it has passed.
+fn poll_deadline(deadline: &mut Option<Delay>, why: &str) -> Result<(), Error> {
+    match deadline.as_mut().map(Future::poll) {
+        Some(Ok(Async::Ready(()))) => Err(io::Error::new(io::ErrorKind::TimedOut, why).into()),
+        // Without a timer, wait as long as it takes.
+        Some(Err(_)) => {
+            *deadline = None;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Errors likely to go away when the request is sent again.
+const TRANSIENT: &[io::ErrorKind] = &[
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::ConnectionRefused,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::UnexpectedEof,
+];
+
+/// A response status that the server may not respond with next time.
+#[derive(Debug)]
+pub(crate) struct ServerError(pub StatusCode);
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "server responded with {}", self.0)
+    }
+}
+
+impl Fail for ServerError {}
+
+/// Is a failed download likely to succeed when tried again?
+pub(crate) fn is_transient(error: &failure::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<Error>() {
+        return match *error {
+            Error::Io(ref e) => TRANSIENT.contains(&e.kind()),
+            Error::Incomplete | Error::Timeout => true,
+            _ => false,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<io::Error>() {
+        return TRANSIENT.contains(&error.kind());
+    }
+    match error.downcast_ref::<ServerError>() {
+        Some(&ServerError(status)) => status.is_server_error(),
+        None => false,
+    }
+}
+
+/// Run the future made by `attempt` again while it fails with a transient
+/// error, as `options` allow, waiting twice as long after each failure.
+pub(crate) fn retrying<'a, A, F>(
+    options: &'a DownloadOptions,
+    logger: &'a Logger,
+    url: String,
+    attempt: A,
+) -> impl Future<Item = F::Item, Error = failure::Error> + 'a
+where
+    A: FnMut() -> F + 'a,
+    F: Future<Error = failure::Error> + 'a,
+{
+    loop_fn((attempt, 0), move |(mut attempt, retries): (A, u32)| {
+        let url = url.clone();
+        attempt().then(move |res| match res {
+            Ok(item) => Either::A(ok(Loop::Break(item))),
+            Err(e) => {
+                if retries >= options.retries || !is_transient(&e) {
+                    return Either::A(err(e));
+                }
+                let wait = options.backoff * 2u32.pow(retries.min(16));
+                warn!(
+                    logger,
+                    "GET of {} failed: {}; retrying in {:?} ({} of {})",
+                    url, e, wait, retries + 1, options.retries
+                );
+                // Without a timer, try again right away.
+                Either::B(delay(wait).then(move |_| Ok(Loop::Continue((attempt, retries + 1)))))
+            }
+        })
+    })
+}
+
+impl<'a, C: Connect> RedirectingFuture<'a, C> {
+    fn send(&mut self) {
+        self.cur_get = get(self.client, self.uri.clone(), &self.headers, self.options);
+        self.deadline = self.options.connect_timeout.map(delay);
+    }
+}
+
 impl<'a, C: Connect> Future for RedirectingFuture<'a, C> {
     type Item=Response;
     type Error=Error;
     fn poll(&mut self) -> Poll<Response, Error>{
         loop {
             debug!(self.logger, "Starting GET of {}", self.uri);
-            match self.cur_get.poll()? {
+            let polled = poll_deadline(&mut self.deadline, "no response in time")
+                .and_then(|()| self.cur_get.poll());
+            match polled? {
                 Async::NotReady => return Ok(Async::NotReady),
                 Async::Ready(res) =>
                 match res.status() {
@@ -67,7 +204,7 @@ impl<'a, C: Connect> Future for RedirectingFuture<'a, C> {
                         debug!(self.logger, "Redirecting from {} to {}", self.uri, new_uri);
                         self.uri = new_uri;
                         //self.history.push(new_uri.clone());
-                        self.cur_get = self.client.get(self.uri.clone());
+                        self.send();
                     }
                     _ => {
                         return Ok(Async::Ready(res));
@@ -77,3 +214,113 @@ impl<'a, C: Connect> Future for RedirectingFuture<'a, C> {
         }
     }
 }
+
+/// A response body that fails once it goes too long without any data.
+pub(crate) struct ReadTimeout {
+    body: Body,
+    timeout: Option<Duration>,
+    deadline: Option<Delay>,
+}
+
+impl ReadTimeout {
+    pub(crate) fn new(body: Body, options: &DownloadOptions) -> Self {
+        ReadTimeout {
+            body,
+            timeout: options.read_timeout,
+            deadline: None,
+        }
+    }
+}
+
+impl Stream for ReadTimeout {
+    type Item = Chunk;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        match self.body.poll()? {
+            Async::NotReady => {}
+            ready => {
+                self.deadline = None;
+                return Ok(ready);
+            }
+        }
+        if self.deadline.is_none() {
+            self.deadline = self.timeout.map(delay);
+        }
+        poll_deadline(&mut self.deadline, "response body stalled")?;
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::client::HttpConnector;
+    use slog::Discard;
+    use tokio_core::reactor::Core;
+
+    use testing::serve;
+
+    fn options(retries: u32) -> DownloadOptions {
+        DownloadOptions {
+            connect_timeout: Some(Duration::from_millis(200)),
+            read_timeout: Some(Duration::from_millis(200)),
+            retries,
+            backoff: Duration::from_millis(10),
+            ..DownloadOptions::default()
+        }
+    }
+
+    const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+    /// GET the body of `uri`, retrying server errors.
+    fn fetch<'a>(
+        client: &'a Client<HttpConnector, Body>,
+        uri: Uri,
+        options: &'a DownloadOptions,
+        logger: &'a Logger,
+    ) -> impl Future<Item = Chunk, Error = failure::Error> + 'a {
+        retrying(options, logger, uri.to_string(), move || {
+            client.redirectable(uri.clone(), Headers::new(), options, logger)
+                .from_err()
+                .and_then(move |res| match res.status() {
+                    status if status.is_server_error() => Either::A(err(ServerError(status).into())),
+                    _ => Either::B(ReadTimeout::new(res.body(), options).concat2().from_err()),
+                })
+        })
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let logger = Logger::root(Discard, o!());
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let retried = options(1);
+        let (uri, _) = serve(vec![UNAVAILABLE, OK], Duration::from_millis(0));
+        let body = core.run(fetch(&client, uri, &retried, &logger)).unwrap();
+        assert_eq!(body.as_ref(), b"ok");
+
+        let once = options(0);
+        let (uri, _) = serve(vec![UNAVAILABLE, OK], Duration::from_millis(0));
+        let error = core.run(fetch(&client, uri, &once, &logger)).unwrap_err();
+        assert!(error.downcast_ref::<ServerError>().is_some());
+    }
+
+    #[test]
+    fn stalled_servers_time_out() {
+        let logger = Logger::root(Discard, o!());
+        let mut core = Core::new().unwrap();
+        let client: Client<HttpConnector, Body> = Client::new(&core.handle());
+        let options = options(0);
+        let (uri, _) = serve(vec![""], Duration::from_secs(2));
+        assert!(core.run(client.redirectable(uri, Headers::new(), &options, &logger)).is_err());
+
+        let (uri, _) = serve(vec!["HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\npart"], Duration::from_secs(2));
+        let body = core.run(
+            client
+                .redirectable(uri, Headers::new(), &options, &logger)
+                .and_then(|res| ReadTimeout::new(res.body(), &options).concat2()),
+        );
+        assert!(body.is_err());
+    }
+}
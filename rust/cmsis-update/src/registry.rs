@@ -0,0 +1,315 @@
+//! What the pack store holds.
+//!
+//! Scanning the store means parsing every PDSC and sizing every extracted
+//! pack, so the result is kept in `.cache/registry.json`. A rescan only
+//! redoes that work for the files whose modification time changed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, metadata, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use failure::Error;
+use serde_json;
+use slog::Logger;
+
+use pack_index::config::Config;
+use pdsc::{Package, Version};
+use utils::parse::FromElem;
+
+use extract::INSTALLED_MARKER;
+use remove::{entries, size_of};
+
+/// How much of a pack release the store holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackState {
+    /// Only its PDSC, as downloaded by `update`.
+    PdscOnly,
+    /// Its `.pack`, downloaded and extracted by `install`.
+    Installed,
+}
+
+/// A pack release in the store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub vendor: String,
+    pub name: String,
+    pub version: Version,
+    pub state: PackState,
+    /// The PDSC, or the directory the pack is extracted to.
+    pub path: PathBuf,
+    /// Bytes taken up in the store, counting the `.pack` of an installed
+    /// release.
+    pub bytes: u64,
+    /// When the release was downloaded or installed, in seconds since the
+    /// Unix epoch.
+    pub modified: u64,
+    /// The date of the release, as its PDSC gives it.
+    pub released: Option<String>,
+    /// The newest release of the same pack in the store.
+    pub latest: Version,
+}
+
+impl RegistryEntry {
+    /// The release date from the PDSC, or an empty string when it has
+    /// none.
+    pub fn date(&self) -> &str {
+        match self.released {
+            Some(ref date) => date,
+            None => "",
+        }
+    }
+
+    /// Is this the newest release installed of its pack, while a newer
+    /// one is known?
+    fn is_outdated(&self, newest_installed: &HashMap<(&str, &str), &Version>) -> bool {
+        self.state == PackState::Installed
+            && newest_installed.get(&(self.vendor.as_str(), self.name.as_str())) == Some(&&self.version)
+            && self.latest > self.version
+    }
+}
+
+/// The pack releases in a store, as of its last scan.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Registry {
+    /// Every PDSC and installed release found, sorted by pack and version.
+    /// A PDSC of an installed release has an entry of its own.
+    entries: Vec<RegistryEntry>,
+}
+
+fn registry_path(config: &Config) -> PathBuf {
+    config.pack_store.join(".cache").join("registry.json")
+}
+
+/// Seconds since the Unix epoch at which `path` was last modified.
+fn modified(path: &Path) -> Option<u64> {
+    let time = metadata(path).and_then(|meta| meta.modified()).ok()?;
+    time.duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+impl Registry {
+    /// Load the registry of a pack store as last saved, without scanning.
+    /// A missing or unreadable registry is treated as empty.
+    pub fn load(config: &Config, l: &Logger) -> Self {
+        File::open(registry_path(config))
+            .map_err(Error::from)
+            .and_then(|fd| Ok(serde_json::from_reader(fd)?))
+            .unwrap_or_else(|e| {
+                debug!(l, "Starting with an empty pack registry: {}", e);
+                Registry::default()
+            })
+    }
+
+    pub fn save(&self, config: &Config) -> Result<(), Error> {
+        let path = registry_path(config);
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        let mut fd = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        serde_json::to_writer_pretty(&mut fd, self)?;
+        fd.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Bring the saved registry of a pack store up to date, and save it.
+    pub fn scan(config: &Config, l: &Logger) -> Result<Self, Error> {
+        let previous = Registry::load(config, l);
+        let mut known: HashMap<(PathBuf, u64), RegistryEntry> = previous
+            .entries
+            .into_iter()
+            .map(|entry| ((entry.path.clone(), entry.modified), entry))
+            .collect();
+        let mut scanned = Vec::new();
+        for path in entries(&config.pack_store) {
+            if path.is_file() && path.extension().map(|ext| ext == "pdsc").unwrap_or(false) {
+                let stamp = match modified(&path) {
+                    Some(stamp) => stamp,
+                    None => continue,
+                };
+                if let Some(entry) = known.remove(&(path.clone(), stamp)) {
+                    scanned.push(entry);
+                    continue;
+                }
+                match Package::from_path(&path, l) {
+                    Ok(pdsc) => {
+                        let release = pdsc.releases.latest_release();
+                        let version = release.version.clone();
+                        let released = release.date.clone();
+                        scanned.push(RegistryEntry {
+                            vendor: pdsc.vendor,
+                            name: pdsc.name,
+                            latest: version.clone(),
+                            version,
+                            state: PackState::PdscOnly,
+                            bytes: size_of(&path),
+                            path,
+                            modified: stamp,
+                            released,
+                        });
+                    }
+                    Err(e) => error!(l, "parsing {:?}: {}", path, e),
+                }
+            } else if path.is_dir() {
+                let vendor = file_name(&path);
+                for pack_dir in entries(&path).into_iter().filter(|path| path.is_dir()) {
+                    let name = file_name(&pack_dir);
+                    for dir in entries(&pack_dir).into_iter().filter(|path| path.is_dir()) {
+                        let stamp = match modified(&dir.join(INSTALLED_MARKER)) {
+                            Some(stamp) => stamp,
+                            None => continue,
+                        };
+                        if let Some(entry) = known.remove(&(dir.clone(), stamp)) {
+                            scanned.push(entry);
+                            continue;
+                        }
+                        let version = match file_name(&dir).parse::<Version>() {
+                            Ok(version) => version,
+                            Err(_) => continue,
+                        };
+                        let pack = pack_dir.join(format!("{}.pack", version));
+                        let pdsc = dir.join(format!("{}.{}.pdsc", vendor, name));
+                        let released = Package::from_path(&pdsc, l).ok().and_then(|pdsc| {
+                            pdsc.releases.find(&version).and_then(|rel| rel.date.clone())
+                        });
+                        scanned.push(RegistryEntry {
+                            vendor: vendor.clone(),
+                            name: name.clone(),
+                            latest: version.clone(),
+                            version,
+                            state: PackState::Installed,
+                            bytes: size_of(&dir) + size_of(&pack),
+                            path: dir,
+                            modified: stamp,
+                            released,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut latest: BTreeMap<(String, String), Version> = BTreeMap::new();
+        for entry in &scanned {
+            let newest = latest
+                .entry((entry.vendor.clone(), entry.name.clone()))
+                .or_insert_with(|| entry.version.clone());
+            if entry.version > *newest {
+                *newest = entry.version.clone();
+            }
+        }
+        for entry in &mut scanned {
+            entry.latest = latest[&(entry.vendor.clone(), entry.name.clone())].clone();
+        }
+        scanned.sort_by(|a, b| {
+            (&a.vendor, &a.name, &a.version, a.state == PackState::Installed)
+                .cmp(&(&b.vendor, &b.name, &b.version, b.state == PackState::Installed))
+        });
+
+        let registry = Registry { entries: scanned };
+        registry.save(config)?;
+        Ok(registry)
+    }
+
+    /// Every release in the store, once: installed releases, and the
+    /// PDSCs of releases that are not installed.
+    pub fn packs(&self) -> Vec<&RegistryEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.state == PackState::Installed
+                    || !self.entries.iter().any(|other| {
+                        other.state == PackState::Installed && other.vendor == entry.vendor
+                            && other.name == entry.name
+                            && other.version == entry.version
+                    })
+            })
+            .collect()
+    }
+
+    /// The newest installed release of each pack whose PDSC lists a newer
+    /// one.
+    pub fn outdated(&self) -> Vec<&RegistryEntry> {
+        let mut newest_installed: HashMap<(&str, &str), &Version> = HashMap::new();
+        for entry in self.entries.iter().filter(|entry| entry.state == PackState::Installed) {
+            let newest = newest_installed
+                .entry((entry.vendor.as_str(), entry.name.as_str()))
+                .or_insert(&entry.version);
+            if entry.version > **newest {
+                *newest = &entry.version;
+            }
+        }
+        self.entries
+            .iter()
+            .filter(|entry| entry.is_outdated(&newest_installed))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::Discard;
+
+    use testing::{write, TempStore};
+
+    fn pdsc(version: &str, date: u8) -> String {
+        format!(
+            "<package>
+              <vendor>MyVendor</vendor>
+              <name>MyPack</name>
+              <description>A pack</description>
+              <url>http://example.com/</url>
+              <releases>
+                <release version=\"{}\" date=\"2019-0{}-01\">Release</release>
+              </releases>
+            </package>",
+            version, date
+        )
+    }
+
+    #[test]
+    fn scan_store() {
+        let logger = Logger::root(Discard, o!());
+        let store = TempStore::new("registry");
+        let config = &store.config;
+        let root = store.path();
+        let pack = root.join("MyVendor/MyPack");
+        write(&pack.join("1.0.0.pack"), b"pack");
+        write(&pack.join("1.0.0/.installed"), b"sha");
+        write(&pack.join("1.0.0/MyVendor.MyPack.pdsc"), pdsc("1.0.0", 1).as_bytes());
+        write(&pack.join("1.1.0.part"), b"part");
+        write(&root.join("MyVendor.MyPack.1.1.0.pdsc"), pdsc("1.1.0", 2).as_bytes());
+
+        let registry = Registry::scan(config, &logger).unwrap();
+        let packs: Vec<(String, PackState)> = registry
+            .packs()
+            .iter()
+            .map(|entry| (entry.version.to_string(), entry.state))
+            .collect();
+        assert_eq!(packs, vec![
+            ("1.0.0".to_string(), PackState::Installed),
+            ("1.1.0".to_string(), PackState::PdscOnly),
+        ]);
+        let installed = registry.packs()[0];
+        assert_eq!(installed.bytes, 4 + 3 + pdsc("1.0.0", 1).len() as u64);
+        assert_eq!(installed.latest, Version::new(1, 1, 0));
+        assert_eq!(installed.date(), "2019-01-01");
+        assert_eq!(registry.packs()[1].date(), "2019-02-01");
+        assert_eq!(registry.outdated(), vec![installed]);
+        assert_eq!(Registry::load(config, &logger), registry);
+
+        write(&pack.join("1.1.0/.installed"), b"sha");
+        let registry = Registry::scan(config, &logger).unwrap();
+        assert_eq!(registry.packs().len(), 2);
+        assert!(registry.outdated().is_empty());
+    }
+}
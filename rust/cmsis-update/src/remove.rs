@@ -0,0 +1,305 @@
+//! Removing packs from the pack store, and collecting what it no longer
+//! needs.
+//!
+//! The store holds the `Vendor.Name.version.pdsc` files of `update` and,
+//! for each installed release, a `Vendor/Name/version.pack` next to its
+//! extracted `Vendor/Name/version/` directory. Interrupted downloads leave
+//! `.part` files in either place.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{read_dir, remove_dir, remove_dir_all, remove_file, symlink_metadata};
+use std::path::{Path, PathBuf};
+
+use failure::{err_msg, Error};
+use slog::Logger;
+
+use pack_index::config::Config;
+use pdsc::Version;
+
+use cache::HttpCache;
+use lock::LockFile;
+use vidx::cached_pdscs;
+
+/// Why a file or directory is removed from the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// A release named by `plan_remove`.
+    Removed,
+    /// A release older than the newest installed one, and not locked.
+    OldVersion,
+    PartialDownload,
+    /// A PDSC that none of the indexes lists anymore.
+    UnlistedPdsc,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Reason::Removed => "removed",
+            Reason::OldVersion => "old version",
+            Reason::PartialDownload => "partial download",
+            Reason::UnlistedPdsc => "unlisted PDSC",
+        })
+    }
+}
+
+/// A file or directory to remove from the store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removal {
+    pub path: PathBuf,
+    /// The bytes it takes up, counting everything inside a directory.
+    pub bytes: u64,
+    pub reason: Reason,
+}
+
+pub(crate) fn size_of(path: &Path) -> u64 {
+    match symlink_metadata(path) {
+        Ok(ref meta) if meta.is_dir() => read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| size_of(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+impl Removal {
+    fn new(path: PathBuf, reason: Reason) -> Self {
+        Removal {
+            bytes: size_of(&path),
+            path,
+            reason,
+        }
+    }
+}
+
+/// The entries of `dir`, skipping hidden ones such as `.cache`.
+pub(crate) fn entries(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .map(|name| !name.to_string_lossy().starts_with('.'))
+                    .unwrap_or(false)
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.is_file() && path.extension().map(|ext| ext == extension).unwrap_or(false)
+}
+
+/// The releases installed in a `Vendor/Name/` directory, with their
+/// `.pack` files and extracted directories.
+fn installed_releases(dir: &Path) -> BTreeMap<Version, Vec<PathBuf>> {
+    let mut releases: BTreeMap<Version, Vec<PathBuf>> = BTreeMap::new();
+    for path in entries(dir) {
+        let version = if path.is_dir() {
+            path.file_name()
+        } else if has_extension(&path, "pack") {
+            path.file_stem()
+        } else {
+            continue;
+        };
+        if let Some(version) = version.and_then(|version| version.to_str()?.parse().ok()) {
+            releases.entry(version).or_default().push(path);
+        }
+    }
+    releases
+}
+
+/// What removing the pack `vendor.name` deletes: every installed release,
+/// or only `version`, along with their partial downloads.
+pub fn plan_remove(
+    config: &Config,
+    vendor: &str,
+    name: &str,
+    version: Option<&Version>,
+) -> Result<Vec<Removal>, Error> {
+    let dir = config.pack_store.join(vendor).join(name);
+    let mut removals: Vec<Removal> = installed_releases(&dir)
+        .into_iter()
+        .filter(|(installed, _)| version.map(|version| version == installed).unwrap_or(true))
+        .flat_map(|(_, paths)| paths)
+        .map(|path| Removal::new(path, Reason::Removed))
+        .collect();
+    if removals.is_empty() {
+        return Err(err_msg(match version {
+            Some(version) => format!("{}.{}@{} is not installed", vendor, name, version),
+            None => format!("{}.{} is not installed", vendor, name),
+        }));
+    }
+    removals.extend(
+        entries(&dir)
+            .into_iter()
+            .filter(|path| has_extension(path, "part"))
+            .filter(|path| match version {
+                Some(version) => path.file_stem()
+                    .map(|stem| stem.to_string_lossy() == version.to_string())
+                    .unwrap_or(false),
+                None => true,
+            })
+            .map(|path| Removal::new(path, Reason::PartialDownload)),
+    );
+    Ok(removals)
+}
+
+/// What the store no longer needs: releases older than the newest one
+/// installed of each pack, unless `lock` pins them; `.part` files; and
+/// PDSC files that the indexes of `vidx_list` stopped listing.
+///
+/// The indexes are read as cached by the last `update`. When one of them
+/// is missing, no PDSC file is collected.
+pub fn plan_gc<I>(
+    config: &Config,
+    vidx_list: I,
+    lock: Option<&LockFile>,
+    logger: &Logger,
+) -> Result<Vec<Removal>, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let cache = HttpCache::load(config, logger);
+    let listed: Option<HashSet<String>> = match cached_pdscs(vidx_list, &cache, logger) {
+        Ok(pdscs) => Some(
+            pdscs
+                .iter()
+                .map(|pdsc| format!("{}.{}.{}.pdsc", pdsc.vendor, pdsc.name, pdsc.version))
+                .collect(),
+        ),
+        Err(e) => {
+            warn!(logger, "Keeping all PDSC files, as the indexes are unknown: {}", e);
+            None
+        }
+    };
+    let locked = |vendor: &str, name: &str, version: &Version| {
+        lock.map(|lock| {
+            lock.packs.iter().any(|pack| {
+                pack.vendor == vendor && pack.name == name && &pack.version == version
+            })
+        }).unwrap_or(false)
+    };
+
+    let mut removals = Vec::new();
+    for path in entries(&config.pack_store) {
+        if has_extension(&path, "part") {
+            removals.push(Removal::new(path, Reason::PartialDownload));
+        } else if has_extension(&path, "pdsc") {
+            let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+            match (&listed, file_name) {
+                (Some(listed), Some(file_name)) if !listed.contains(&file_name) => {
+                    removals.push(Removal::new(path, Reason::UnlistedPdsc));
+                }
+                _ => (),
+            }
+        } else if path.is_dir() {
+            let vendor = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            for pack_dir in entries(&path).into_iter().filter(|path| path.is_dir()) {
+                let name = pack_dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                let releases = installed_releases(&pack_dir);
+                let newest = releases.keys().next_back().cloned();
+                for (version, paths) in releases {
+                    if Some(&version) == newest.as_ref() || locked(&vendor, &name, &version) {
+                        continue;
+                    }
+                    removals.extend(paths.into_iter().map(|path| Removal::new(path, Reason::OldVersion)));
+                }
+                removals.extend(
+                    entries(&pack_dir)
+                        .into_iter()
+                        .filter(|path| has_extension(path, "part"))
+                        .map(|path| Removal::new(path, Reason::PartialDownload)),
+                );
+            }
+        }
+    }
+    Ok(removals)
+}
+
+/// Delete `removals`, along with the `Vendor/Name/` and `Vendor/`
+/// directories they leave empty, and return the bytes freed.
+pub fn delete(config: &Config, removals: &[Removal], logger: &Logger) -> Result<u64, Error> {
+    let mut freed = 0;
+    for removal in removals {
+        if removal.path.is_dir() {
+            remove_dir_all(&removal.path)?;
+        } else {
+            remove_file(&removal.path)?;
+        }
+        debug!(logger, "Removed {}", removal.path.display());
+        freed += removal.bytes;
+        let mut parent = removal.path.parent();
+        while let Some(dir) = parent {
+            if dir == config.pack_store || remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::Discard;
+
+    use testing::{write, TempStore};
+
+    #[test]
+    fn collect_and_remove() {
+        let logger = Logger::root(Discard, o!());
+        let store = TempStore::new("remove");
+        let config = &store.config;
+        let root = store.path();
+        let pack = root.join("MyVendor/MyPack");
+        write(&pack.join("1.0.0.pack"), b"old pack");
+        write(&pack.join("1.0.0/MyVendor.MyPack.pdsc"), b"old");
+        write(&pack.join("1.1.0.pack"), b"new pack");
+        write(&pack.join("1.1.0/MyVendor.MyPack.pdsc"), b"new");
+        write(&pack.join("1.2.0.part"), b"pa");
+        write(&root.join("MyVendor.MyPack.1.0.0.pdsc"), b"unlisted");
+        write(&root.join("MyVendor.MyPack.1.1.0.pdsc"), b"listed");
+        let vidx = root.join("index.vidx");
+        write(&vidx, b"<index>
+          <vendor>Local</vendor>
+          <url>file:///packs/</url>
+          <pindex>
+            <pdsc url=\"file:///packs/\" vendor=\"MyVendor\" name=\"MyPack\" version=\"1.1.0\"/>
+          </pindex>
+        </index>");
+        let vidx_list = vec![vidx.to_string_lossy().into_owned()];
+
+        let garbage = plan_gc(config, vidx_list.clone(), None, &logger).unwrap();
+        let reasons: Vec<(PathBuf, Reason)> = garbage
+            .iter()
+            .map(|removal| (removal.path.strip_prefix(root).unwrap().to_path_buf(), removal.reason))
+            .collect();
+        assert_eq!(reasons, vec![
+            (PathBuf::from("MyVendor/MyPack/1.0.0"), Reason::OldVersion),
+            (PathBuf::from("MyVendor/MyPack/1.0.0.pack"), Reason::OldVersion),
+            (PathBuf::from("MyVendor/MyPack/1.2.0.part"), Reason::PartialDownload),
+            (PathBuf::from("MyVendor.MyPack.1.0.0.pdsc"), Reason::UnlistedPdsc),
+        ]);
+        assert_eq!(delete(config, &garbage, &logger).unwrap(), 8 + 3 + 8 + 2);
+        assert!(plan_gc(config, vidx_list, None, &logger).unwrap().is_empty());
+
+        assert!(plan_remove(config, "MyVendor", "MyPack", Some(&Version::new(1, 0, 0))).is_err());
+        let removals = plan_remove(config, "MyVendor", "MyPack", None).unwrap();
+        assert_eq!(removals.len(), 2);
+        delete(config, &removals, &logger).unwrap();
+        assert!(!root.join("MyVendor").exists());
+        assert!(root.join("MyVendor.MyPack.1.1.0.pdsc").exists());
+    }
+}
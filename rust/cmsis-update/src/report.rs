@@ -0,0 +1,97 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// What became of one file of an update or install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Downloaded,
+    /// Already in the store, or not modified on the server.
+    Unchanged,
+    Failed,
+    /// Not downloaded, as the index marks it deprecated and
+    /// `DownloadOptions::skip_deprecated` is set.
+    Deprecated,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Outcome::Downloaded => "downloaded",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Failed => "failed",
+            Outcome::Deprecated => "deprecated",
+        })
+    }
+}
+
+/// One file of an `UpdateReport`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportItem {
+    /// The URL or path the file is fetched from; empty when it has none.
+    pub source: String,
+    pub dest: PathBuf,
+    pub outcome: Outcome,
+    /// The status of the last response, for remote files.
+    pub status: Option<u16>,
+    /// Bytes received by this run.
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+impl ReportItem {
+    pub(crate) fn new(source: String, dest: PathBuf, outcome: Outcome) -> Self {
+        ReportItem {
+            source,
+            dest,
+            outcome,
+            status: None,
+            bytes: 0,
+            error: None,
+        }
+    }
+}
+
+/// The files of an `update` or `install`, and what became of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UpdateReport {
+    pub items: Vec<ReportItem>,
+}
+
+impl UpdateReport {
+    /// The files that are in the store after this run.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.items
+            .iter()
+            .filter(|item| item.outcome == Outcome::Downloaded || item.outcome == Outcome::Unchanged)
+            .map(|item| item.dest.clone())
+            .collect()
+    }
+
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.items.iter().filter(|item| item.outcome == outcome).count()
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &ReportItem> {
+        self.items.iter().filter(|item| item.outcome == Outcome::Failed)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.count(Outcome::Failed) == 0
+    }
+
+    /// Mark the item for `dest` as failed, as when it is downloaded but
+    /// then fails to install.
+    pub(crate) fn fail(&mut self, dest: &Path, error: String) {
+        for item in self.items.iter_mut().filter(|item| item.dest == dest) {
+            item.outcome = Outcome::Failed;
+            item.error = Some(error.clone());
+        }
+    }
+}
+
+impl Extend<ReportItem> for UpdateReport {
+    fn extend<T: IntoIterator<Item = ReportItem>>(&mut self, items: T) {
+        self.items.extend(items)
+    }
+}
@@ -0,0 +1,340 @@
+//! Serve a pack store as a pack index.
+//!
+//! The generated `index.pidx` points at one `Vendor.pidx` per vendor, which
+//! lists the newest PDSC of each of its packs. PDSC files are served with
+//! their `<url>` pointing back at the server, so their packs are fetched
+//! from the store as `Vendor.Name.version.pack`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{metadata, read_dir, File};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+use std::time::SystemTime;
+
+use failure::Error;
+use futures::{Future, Sink};
+use futures::future::{ok, FutureResult};
+use hyper::{self, Body, Chunk, Method, StatusCode};
+use hyper::header::{ContentLength, ContentType, Host};
+use hyper::server::{Http, NewService, Request, Response, Service};
+use slog::Logger;
+
+use pack_index::config::Config;
+use pdsc::Package;
+use utils::ResultLogExt;
+use utils::parse::FromElem;
+
+use mirror::{escape, pdsc_index, rewrite_pdsc};
+
+/// The newest PDSC of each pack in a store, by vendor and name.
+type StorePdscs = BTreeMap<(String, String), (PathBuf, Package)>;
+
+/// The PDSCs of a store, with its modification time when they were listed.
+type Listing = Option<(SystemTime, Rc<StorePdscs>)>;
+
+fn store_pdscs(store: &Path, logger: &Logger) -> StorePdscs {
+    let mut pdscs = BTreeMap::new();
+    let entries = match read_dir(store).ok_warn(logger) {
+        Some(entries) => entries,
+        None => return pdscs,
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().map(|ext| ext != "pdsc").unwrap_or(true) {
+            continue;
+        }
+        let pack = match Package::from_path(&path, logger).ok_warn(logger) {
+            Some(pack) => pack,
+            None => continue,
+        };
+        let key = (pack.vendor.clone(), pack.name.clone());
+        let newer = match pdscs.get(&key) {
+            Some((_, prev)) => {
+                pack.releases.latest_release().version > prev.releases.latest_release().version
+            }
+            None => true,
+        };
+        if newer {
+            pdscs.insert(key, (path, pack));
+        }
+    }
+    pdscs
+}
+
+fn xml(body: String) -> Response {
+    Response::new()
+        .with_header(ContentType::xml())
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+/// Stream a file from a thread of its own, as packs may be large.
+fn stream_file(mut file: File) -> Body {
+    let (mut sender, body) = Body::pair();
+    thread::spawn(move || loop {
+        let mut buf = vec![0; 64 * 1024];
+        let chunk = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => {
+                buf.truncate(len);
+                Ok(Chunk::from(buf))
+            }
+            Err(e) => Err(hyper::Error::from(e)),
+        };
+        let failed = chunk.is_err();
+        sender = match sender.send(chunk).wait() {
+            Ok(sender) => sender,
+            Err(_) => break,
+        };
+        if failed {
+            break;
+        }
+    });
+    body
+}
+
+/// Answers requests for the index, PDSC and pack files of a store.
+#[derive(Clone)]
+struct StoreService {
+    store: PathBuf,
+    logger: Logger,
+    /// Adding, removing or renaming a PDSC changes the modification time
+    /// of the store, so the listing is redone then.
+    listed: Rc<RefCell<Listing>>,
+}
+
+impl StoreService {
+    fn new(store: PathBuf, logger: Logger) -> Self {
+        StoreService {
+            store,
+            logger,
+            listed: Rc::default(),
+        }
+    }
+
+    /// The PDSCs of the store, listed again only once it changed.
+    fn pdscs(&self) -> Rc<StorePdscs> {
+        let modified = metadata(&self.store).and_then(|meta| meta.modified()).ok();
+        let mut listed = self.listed.borrow_mut();
+        match (listed.as_ref(), modified) {
+            (Some(&(at, ref pdscs)), Some(modified)) if at == modified => return pdscs.clone(),
+            _ => (),
+        }
+        let pdscs = Rc::new(store_pdscs(&self.store, &self.logger));
+        *listed = modified.map(|modified| (modified, pdscs.clone()));
+        pdscs
+    }
+
+    fn index(&self, base_url: &str) -> String {
+        let pdscs = self.pdscs();
+        let mut vendors: Vec<&String> = pdscs.keys().map(|(vendor, _)| vendor).collect();
+        vendors.dedup();
+        let mut index = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n");
+        index.push_str("<index schemaVersion=\"1.0.0\">\n");
+        index.push_str("  <vendor>Mirror</vendor>\n");
+        index.push_str(&format!("  <url>{}</url>\n", escape(base_url)));
+        index.push_str("  <vindex>\n");
+        for vendor in vendors {
+            index.push_str(&format!(
+                "    <pidx vendor=\"{}\" url=\"{}\"/>\n",
+                escape(vendor),
+                escape(base_url)
+            ));
+        }
+        index.push_str("  </vindex>\n</index>\n");
+        index
+    }
+
+    fn respond(&self, req: &Request) -> Result<Response, StatusCode> {
+        if req.method() != &Method::Get {
+            return Err(StatusCode::MethodNotAllowed);
+        }
+        let base_url = match req.headers().get::<Host>() {
+            Some(host) => match host.port() {
+                Some(port) => format!("http://{}:{}/", host.hostname(), port),
+                None => format!("http://{}/", host.hostname()),
+            },
+            None => return Err(StatusCode::BadRequest),
+        };
+        let file = req.path().trim_start_matches('/');
+        if file.contains('/') || file.contains('\\') || file.contains(':') {
+            return Err(StatusCode::NotFound);
+        }
+        if file == "index.pidx" {
+            return Ok(xml(self.index(&base_url)));
+        }
+        let (stem, extension) = match file.rfind('.') {
+            Some(dot) => (&file[..dot], &file[dot + 1..]),
+            None => return Err(StatusCode::NotFound),
+        };
+        if extension == "pidx" {
+            let vendor = stem;
+            let pdscs = self.pdscs();
+            let packs: Vec<&Package> = pdscs
+                .iter()
+                .filter(|((pack_vendor, _), _)| pack_vendor == vendor)
+                .map(|(_, (_, pack))| pack)
+                .collect();
+            if packs.is_empty() {
+                return Err(StatusCode::NotFound);
+            }
+            return Ok(xml(pdsc_index(vendor, &base_url, packs)));
+        }
+        if extension == "pdsc" {
+            let pdscs = self.pdscs();
+            let path = pdscs
+                .iter()
+                .find(|((vendor, name), _)| format!("{}.{}.pdsc", vendor, name) == file)
+                .map(|(_, (path, _))| path)
+                .ok_or(StatusCode::NotFound)?;
+            let mut content = String::new();
+            File::open(path)
+                .and_then(|mut fd| fd.read_to_string(&mut content))
+                .map_err(|_| StatusCode::NotFound)?;
+            let rewritten = rewrite_pdsc(&content, &base_url).map_err(|e| {
+                warn!(self.logger, "Serving {}: {}", path.display(), e);
+                StatusCode::InternalServerError
+            })?;
+            return Ok(xml(rewritten));
+        }
+        if extension == "pack" {
+            let mut parts = stem.splitn(3, '.');
+            let path = match (parts.next(), parts.next(), parts.next()) {
+                (Some(vendor), Some(name), Some(version))
+                    if !vendor.is_empty() && !name.is_empty() && !version.is_empty() =>
+                {
+                    self.store.join(vendor).join(name).join(format!("{}.pack", version))
+                }
+                _ => return Err(StatusCode::NotFound),
+            };
+            let fd = File::open(&path).map_err(|_| StatusCode::NotFound)?;
+            let len = fd.metadata().map_err(|_| StatusCode::NotFound)?.len();
+            return Ok(Response::new()
+                .with_header(ContentType("application/zip".parse().unwrap()))
+                .with_header(ContentLength(len))
+                .with_body(stream_file(fd)));
+        }
+        Err(StatusCode::NotFound)
+    }
+}
+
+impl Service for StoreService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = FutureResult<Response, hyper::Error>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let res = self.respond(&req)
+            .unwrap_or_else(|status| Response::new().with_status(status));
+        info!(self.logger, "{} {}", req.method(), req.path(); "status" => res.status().as_u16());
+        ok(res)
+    }
+}
+
+impl NewService for StoreService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Instance = Self;
+
+    fn new_service(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+/// An HTTP server for the pack store of a `Config`, usable as a vidx
+/// source by `update` and `install`.
+pub struct PackServer(hyper::Server<StoreService, Body>);
+
+impl PackServer {
+    /// Listen on `addr`; port 0 picks a free port.
+    pub fn bind(config: &Config, addr: &SocketAddr, logger: &Logger) -> Result<Self, Error> {
+        let service = StoreService::new(config.pack_store.clone(), logger.clone());
+        Ok(PackServer(Http::new().bind(addr, service)?))
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.0.local_addr()?)
+    }
+
+    /// Answer requests until the process exits.
+    pub fn run(self) -> Result<(), Error> {
+        Ok(self.0.run()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{copy, create_dir_all};
+    use std::sync::mpsc::channel;
+    use slog::Discard;
+
+    use testing::{config, write, TempStore};
+    use {install, update, Outcome, PackRelease};
+
+    fn pdsc(name: &str) -> String {
+        format!(
+            "<package>
+              <vendor>MyVendor</vendor>
+              <name>{}</name>
+              <description>A pack</description>
+              <url>http://example.com/</url>
+              <releases><release version=\"1.0.0\">Release</release></releases>
+            </package>",
+            name
+        )
+    }
+
+    #[test]
+    fn list_store_once() {
+        let logger = Logger::root(Discard, o!());
+        let store = TempStore::new("serve-listing");
+        write(&store.path().join("MyVendor.MyPack.1.0.0.pdsc"), pdsc("MyPack").as_bytes());
+        let service = StoreService::new(store.path().to_path_buf(), logger);
+        let listed = service.pdscs();
+        assert_eq!(listed.len(), 1);
+        assert!(Rc::ptr_eq(&listed, &service.clone().pdscs()));
+
+        write(&store.path().join("MyVendor.Other.1.0.0.pdsc"), pdsc("Other").as_bytes());
+        assert_eq!(service.pdscs().len(), 2);
+    }
+
+    #[test]
+    fn update_and_install_from_store() {
+        let logger = Logger::root(Discard, o!());
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/test-pack-index");
+        let served_store = TempStore::new("served");
+        let served = served_store.path();
+        create_dir_all(served.join("MyVendor/MyPack")).unwrap();
+        copy(fixtures.join("MyVendor.MyPack.pdsc"), served.join("MyVendor.MyPack.1.1.0.pdsc")).unwrap();
+        copy(fixtures.join("MyVendor.MyPack.1.1.0.pack"), served.join("MyVendor/MyPack/1.1.0.pack"))
+            .unwrap();
+        let served = served.to_path_buf();
+
+        let (sender, receiver) = channel();
+        let server_logger = logger.clone();
+        thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let server = PackServer::bind(&config(served), &addr, &server_logger).unwrap();
+            sender.send(server.local_addr().unwrap()).unwrap();
+            server.run().unwrap();
+        });
+        let addr = receiver.recv().unwrap();
+
+        let client_store = TempStore::new("serve-client");
+        let client = &client_store.config;
+        let vidx = format!("http://localhost:{}/index.pidx", addr.port());
+        let pdscs = update(client, vec![vidx], &logger, ()).unwrap().paths();
+        assert_eq!(pdscs.len(), 1);
+        let pack = Package::from_path(&pdscs[0], &logger).unwrap();
+        let report = install(client, vec![&pack], &logger, ()).unwrap();
+        assert_eq!(report.count(Outcome::Downloaded), 1);
+        assert!(report.items[0].bytes > 0);
+        assert!(PackRelease::latest(&pack).is_installed(client));
+    }
+}
@@ -0,0 +1,89 @@
+//! Fixtures shared by the tests of this crate.
+
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use hyper::Uri;
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+use pack_index::config::Config;
+
+/// A pack store in a temporary directory of its own, removed on drop,
+/// even when the test fails.
+pub(crate) struct TempStore {
+    pub config: Config,
+}
+
+/// A configuration for the pack store at `pack_store`.
+pub(crate) fn config(pack_store: PathBuf) -> Config {
+    Config {
+        vidx_list: pack_store.join("vendors.list"),
+        pack_store,
+        download: Default::default(),
+    }
+}
+
+impl TempStore {
+    /// `name` keeps the stores of tests running in parallel apart.
+    pub fn new(name: &str) -> Self {
+        let root = ::std::env::temp_dir().join(format!("cmsis-{}-{}", name, process::id()));
+        let _ = remove_dir_all(&root);
+        TempStore {
+            config: config(root),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.config.pack_store
+    }
+}
+
+impl Drop for TempStore {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.config.pack_store);
+    }
+}
+
+/// Write `content` to `path`, creating its parent directories.
+pub(crate) fn write(path: &Path, content: &[u8]) {
+    create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).unwrap().write_all(content).unwrap();
+}
+
+/// Write a `.pack` archive of `entries` to `path`.
+pub(crate) fn write_pack(path: &Path, entries: &[(&str, &str)]) {
+    create_dir_all(path.parent().unwrap()).unwrap();
+    let mut zip = ZipWriter::new(File::create(path).unwrap());
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for &(name, content) in entries {
+        zip.start_file(name, options).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+/// Answer each connection with the next response, holding it open for
+/// `hold` afterwards. Each request received is sent to the receiver.
+pub(crate) fn serve(responses: Vec<&'static str>, hold: Duration) -> (Uri, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("http://{}/index.pidx", listener.local_addr().unwrap());
+    let (requests, received) = channel();
+    thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let len = stream.read(&mut request).unwrap_or(0);
+            let _ = requests.send(String::from_utf8_lossy(&request[..len]).into_owned());
+            stream.write_all(response.as_bytes()).unwrap();
+            thread::sleep(hold);
+        }
+    });
+    (uri.parse().unwrap(), received)
+}
@@ -1,43 +1,79 @@
 use std::borrow::Borrow;
+use std::fs::File;
+use std::io::Read;
 
-use failure::Error;
+use failure::{err_msg, Error};
 use futures::prelude::Future;
 use futures::Stream;
 use futures::stream::{futures_unordered, iter_ok};
-use futures::future::result;
-use hyper::{self, Body, Chunk, Client, Response};
+use futures::future::{err, result, Either};
+use hyper::{Body, Chunk, Client, StatusCode, Uri};
 use hyper::client::Connect;
 use minidom;
 use slog::Logger;
 
 use pack_index::{PdscRef, Pidx, Vidx};
+use pack_index::config::DownloadOptions;
 use utils::parse::FromElem;
 
-use redirect::ClientRedirExt;
+use cache::HttpCache;
+use download::Source;
+use redirect::{retrying, ClientRedirExt, ReadTimeout, ServerError};
 
 fn download_vidx<'a, C: Connect, I: Into<String>>(
     client: &'a Client<C, Body>,
+    cache: &'a HttpCache,
+    options: &'a DownloadOptions,
     vidx_ref: I,
     logger: &'a Logger,
-) -> impl Future<Item = Result<Vidx, minidom::Error>, Error = hyper::Error> + 'a {
+) -> impl Future<Item = Result<Vidx, minidom::Error>, Error = Error> + 'a {
     let vidx = vidx_ref.into();
-    result(vidx.parse())
+    if let Ok(Source::Local(path)) = Source::parse(&vidx) {
+        let body = File::open(path).and_then(|mut fd| {
+            let mut body = Vec::new();
+            fd.read_to_end(&mut body)?;
+            Ok(Chunk::from(body))
+        });
+        return Either::A(result(body).from_err().map(move |body| parse_vidx(body, logger)));
+    }
+    let headers = cache.body_headers(&vidx);
+    Either::B(result(vidx.parse::<Uri>())
         .from_err()
-        .and_then(move |uri| {
-            client.redirectable(uri, logger)
-            .map(Response::body)
-            .flatten_stream()
-            .concat2()
-        })
-        .from_err()
-        .map(move |body| parse_vidx(body, logger))
+        .and_then(move |uri| retrying(options, logger, vidx.clone(), move || {
+            let vidx = vidx.clone();
+            client.redirectable(uri.clone(), headers.clone(), options, logger)
+                .from_err()
+                .and_then(move |res| {
+                    let status = res.status();
+                    if status == StatusCode::NotModified {
+                        debug!(logger, "{} is not modified", vidx);
+                        return Either::A(result(cache.cached_body(&vidx).map(Chunk::from).map_err(Error::from)));
+                    }
+                    if status.is_server_error() {
+                        return Either::A(err(ServerError(status).into()));
+                    }
+                    let headers = res.headers().clone();
+                    let body = ReadTimeout::new(res.body(), options);
+                    Either::B(body.concat2().from_err().map(move |body| {
+                        if status.is_success() {
+                            if let Err(e) = cache.store_body(&vidx, &headers, body.as_ref()) {
+                                warn!(logger, "Could not cache {}: {}", vidx, e);
+                            }
+                        }
+                        body
+                    }))
+                })
+        }))
+        .map(move |body| parse_vidx(body, logger)))
 }
 
 pub(crate) fn download_vidx_list<'a, C, I>(
     list: I,
     client: &'a Client<C, Body>,
+    cache: &'a HttpCache,
+    options: &'a DownloadOptions,
     logger: &'a Logger,
-) -> impl Stream<Item = Option<Vidx>, Error = hyper::Error> + 'a
+) -> impl Stream<Item = Option<Vidx>, Error = Error> + 'a
 where
     C: Connect,
     I: IntoIterator + 'a,
@@ -47,7 +83,7 @@ where
         list.into_iter()
             .map(|vidx_ref| {
                 let string = vidx_ref.into();
-                download_vidx(client, string.clone(), logger).then(move |r| {
+                download_vidx(client, cache, options, string.clone(), logger).then(move |r| {
                     let logger = logger.new(o!("uri" => string));
                     match r {
                         Ok(Ok(r)) => Ok(Some(r)),
@@ -81,14 +117,46 @@ pub(crate) fn flatmap_pdscs<'a, C>(
         ..
     }: Vidx,
     client: &'a Client<C, Body>,
+    cache: &'a HttpCache,
+    options: &'a DownloadOptions,
     logger: &'a Logger,
 ) -> impl Stream<Item = PdscRef, Error = Error> + 'a
     where
     C: Connect,
 {
     let pidx_urls = vendor_index.into_iter().map(into_uri);
-    let job = download_vidx_list(pidx_urls, client, logger)
+    let job = download_vidx_list(pidx_urls, client, cache, options, logger)
         .filter_map(|vidx| vidx.map(|v| iter_ok(v.pdsc_index.into_iter())))
         .flatten();
     iter_ok(pdsc_index.into_iter()).chain(job)
 }
+
+/// The PDSC files listed by the indexes of `list`, as of the last update:
+/// remote indexes are read from `cache` rather than downloaded.
+pub(crate) fn cached_pdscs<I>(list: I, cache: &HttpCache, logger: &Logger) -> Result<Vec<PdscRef>, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let read = |url: &str| -> Result<Vidx, Error> {
+        let body = match Source::parse(url)? {
+            Source::Local(path) => {
+                let mut body = Vec::new();
+                File::open(path)?.read_to_end(&mut body)?;
+                body
+            }
+            Source::Remote(_) => cache
+                .cached_body(url)
+                .map_err(|e| err_msg(format!("{} is not cached: {}", url, e)))?,
+        };
+        parse_vidx(Chunk::from(body), logger).map_err(|e| err_msg(format!("{}: {}", url, e)))
+    };
+    let mut pdscs = Vec::new();
+    for url in list {
+        let Vidx { vendor_index, pdsc_index, .. } = read(&url)?;
+        pdscs.extend(pdsc_index);
+        for pidx in vendor_index {
+            pdscs.extend(read(&into_uri(pidx))?.pdsc_index);
+        }
+    }
+    Ok(pdscs)
+}
@@ -1,19 +1,167 @@
-use std::path::PathBuf;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::io::{BufRead, BufReader, Write};
 use std::fs::{create_dir_all, OpenOptions};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use app_dirs::{app_root, AppDataType, AppInfo};
 use slog::Logger;
 use failure::Error;
 
+/// The HTTP proxies for downloads, and the hosts reached without them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proxy {
+    /// The `http://host:port` URL of the proxy for plain `http` URLs.
+    pub http: Option<String>,
+    /// The proxy for `https` URLs, which tunnels them with `CONNECT`.
+    pub https: Option<String>,
+    /// Host names, or domains such as `.example.com`, as in `NO_PROXY`.
+    pub no_proxy: Vec<String>,
+}
+
+impl Proxy {
+    /// The proxy `url` for both `http` and `https` URLs.
+    pub fn new<T: Into<String>>(url: T) -> Self {
+        let url = url.into();
+        Proxy {
+            http: Some(url.clone()),
+            https: Some(url),
+            no_proxy: Vec::new(),
+        }
+    }
+
+    /// The proxies of `HTTP_PROXY` and `HTTPS_PROXY`, bypassed for the
+    /// hosts of `NO_PROXY`. Lower case names take precedence, as they do
+    /// for curl and wget.
+    pub fn from_env() -> Option<Self> {
+        Proxy::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// As `from_env`, with the variables looked up by `lookup`.
+    pub fn from_lookup<F>(lookup: F) -> Option<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let var = |name: &str| {
+            lookup(&name.to_lowercase())
+                .or_else(|| lookup(name))
+                .filter(|value| !value.is_empty())
+        };
+        let (http, https) = (var("HTTP_PROXY"), var("HTTPS_PROXY"));
+        if http.is_none() && https.is_none() {
+            return None;
+        }
+        let no_proxy = var("NO_PROXY")
+            .map(|hosts| {
+                hosts
+                    .split(',')
+                    .map(|host| host.trim().to_string())
+                    .filter(|host| !host.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Proxy { http, https, no_proxy })
+    }
+
+    /// The proxy to reach `host` through, for a URL of `scheme`.
+    pub fn url_for(&self, scheme: &str, host: &str) -> Option<&str> {
+        let url = match scheme {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        };
+        url.filter(|_| self.is_used_for(host)).map(String::as_str)
+    }
+
+    /// Do requests to `host` go through this proxy?
+    pub fn is_used_for(&self, host: &str) -> bool {
+        !self.no_proxy.iter().any(|pattern| {
+            let domain = pattern.trim_start_matches('.');
+            pattern == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    }
+}
+
+/// Cancels the downloads of the `DownloadOptions` it is in, from any
+/// thread. Tokens are equal when they are clones of each other.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// How downloads are scheduled, and how long to wait on a server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadOptions {
+    /// Downloads in flight at once.
+    pub max_concurrent: usize,
+    /// Downloads in flight at once from a single host.
+    pub per_host: usize,
+    /// Time allowed to connect and receive the response headers.
+    pub connect_timeout: Option<Duration>,
+    /// Time allowed between two reads of a response body.
+    pub read_timeout: Option<Duration>,
+    /// Retries of a download after a server error, a timeout or a dropped
+    /// connection, resuming what was received.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+    /// Leave out the PDSCs that the index marks deprecated.
+    pub skip_deprecated: bool,
+    /// The proxy to download through; `ConfigBuilder` defaults it to
+    /// `Proxy::from_env`.
+    pub proxy: Option<Proxy>,
+    /// PEM files of root certificates to trust besides the built-in ones.
+    pub ca_roots: Vec<PathBuf>,
+    pub cancel: CancelToken,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            max_concurrent: 32,
+            per_host: 8,
+            connect_timeout: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(60)),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            skip_deprecated: false,
+            proxy: None,
+            ca_roots: Vec::new(),
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
 pub struct Config {
     pub pack_store: PathBuf,
     pub vidx_list: PathBuf,
+    pub download: DownloadOptions,
 }
 
 pub struct ConfigBuilder {
     pack_store: Option<PathBuf>,
     vidx_list: Option<PathBuf>,
+    download: DownloadOptions,
 }
 
 impl ConfigBuilder {
@@ -21,6 +169,7 @@ impl ConfigBuilder {
         Self {
             pack_store: None,
             vidx_list: None,
+            download: DownloadOptions::default(),
         }
     }
 
@@ -38,6 +187,25 @@ impl ConfigBuilder {
         }
     }
 
+    pub fn with_download_options(self, download: DownloadOptions) -> Self {
+        Self { download, ..self }
+    }
+
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.download.proxy = Some(proxy);
+        self
+    }
+
+    pub fn with_ca_roots<T: Into<PathBuf>>(mut self, pem: T) -> Self {
+        self.download.ca_roots.push(pem.into());
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.download.cancel = cancel;
+        self
+    }
+
     pub fn build(self) -> Result<Config, Error> {
         let app_info = AppInfo {
             name: "cmsis",
@@ -63,9 +231,14 @@ impl ConfigBuilder {
                 vl
             }
         };
+        let mut download = self.download;
+        if download.proxy.is_none() {
+            download.proxy = Proxy::from_env();
+        }
         Ok(Config {
             pack_store,
             vidx_list,
+            download,
         })
     }
 }
@@ -75,6 +248,18 @@ impl Config {
         ConfigBuilder::new().build()
     }
 
+    /// Entries of the vendor index list are URLs, `file://` URLs or paths.
+    /// Relative paths are relative to the list itself.
+    fn resolve_vidx(&self, entry: String) -> String {
+        if entry.contains("://") || Path::new(&entry).is_absolute() {
+            return entry;
+        }
+        match self.vidx_list.parent() {
+            Some(dir) => dir.join(&entry).to_string_lossy().into_owned(),
+            None => entry,
+        }
+    }
+
     pub fn read_vidx_list(&self, l: &Logger) -> Vec<String> {
         let fd = OpenOptions::new().read(true).open(&self.vidx_list);
         match fd.map_err(Error::from) {
@@ -85,6 +270,9 @@ impl Config {
                     line.map_err(|e| error!(l, "Could not parse line #{}: {}", linenum, e))
                         .into_iter()
                 })
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .map(|line| self.resolve_vidx(line))
                 .collect(),
             Err(_) => {
                 warn!(l, "Failed to open vendor index list read only. Recreating.");
@@ -127,3 +315,27 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn proxies_by_scheme() {
+        let vars: HashMap<&str, &str> = [
+            ("HTTP_PROXY", "http://plain:3128"),
+            ("https_proxy", "http://tunnel:3128"),
+            ("HTTPS_PROXY", "http://ignored:3128"),
+            ("NO_PROXY", "localhost"),
+        ].iter()
+            .cloned()
+            .collect();
+        let proxy = Proxy::from_lookup(|name| vars.get(name).map(|value| value.to_string())).unwrap();
+        assert_eq!(proxy.url_for("http", "packs.example"), Some("http://plain:3128"));
+        assert_eq!(proxy.url_for("https", "packs.example"), Some("http://tunnel:3128"));
+        assert_eq!(proxy.url_for("https", "localhost"), None);
+        assert_eq!(proxy.url_for("ftp", "packs.example"), None);
+        assert_eq!(Proxy::from_lookup(|_| None), None);
+    }
+}
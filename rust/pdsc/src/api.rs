@@ -0,0 +1,78 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use minidom::{Element, Error};
+use slog::Logger;
+
+use utils::parse::{assert_root_name, attr_map, attr_parse, child_text, get_child_no_ns, FromElem};
+
+use component::FileRef;
+use condition::compare_versions;
+use device::NumberBool;
+use Component;
+
+/// An interface that components may implement, from the `apis` section.
+#[derive(Debug, Clone, Serialize)]
+pub struct Api {
+    pub class: String,
+    pub group: String,
+    pub version: String,
+    /// Only one component may implement an exclusive API.
+    pub exclusive: bool,
+    pub condition: Option<String>,
+    pub description: String,
+    pub files: Vec<FileRef>,
+}
+
+impl Api {
+    /// Does `comp` implement this API at a compatible version?
+    pub fn implemented_by(&self, comp: &Component) -> bool {
+        comp.class == self.class && comp.group == self.group
+            && comp.api_version
+                .as_ref()
+                .map(|ver| compare_versions(ver, &self.version) != Ordering::Greater)
+                .unwrap_or(false)
+    }
+}
+
+impl FromElem for Api {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "api")?;
+        Ok(Api {
+            class: attr_map(e, "Cclass", "api")?,
+            group: attr_map(e, "Cgroup", "api")?,
+            version: attr_map(e, "Capiversion", "api")?,
+            exclusive: attr_parse(e, "exclusive", "api")
+                .map(|nb: NumberBool| nb.into())
+                .unwrap_or(true),
+            condition: attr_map(e, "condition", "api").ok(),
+            description: child_text(e, "description", "api").unwrap_or_default(),
+            files: get_child_no_ns(e, "files")
+                .map(|files| FileRef::vec_from_children(files.children(), l))
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// The description of a component class, or of a group within one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Taxonomy {
+    pub class: String,
+    pub group: Option<String>,
+    pub doc: Option<PathBuf>,
+    pub generator: Option<String>,
+    pub description: String,
+}
+
+impl FromElem for Taxonomy {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "description")?;
+        Ok(Taxonomy {
+            class: attr_map(e, "Cclass", "description")?,
+            group: attr_map(e, "Cgroup", "description").ok(),
+            doc: attr_map(e, "doc", "description").ok(),
+            generator: attr_map(e, "generator", "description").ok(),
+            description: e.text().trim().to_string(),
+        })
+    }
+}
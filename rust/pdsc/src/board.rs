@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use minidom::{Element, Error, ErrorKind};
+use slog::Logger;
+
+use utils::parse::{assert_root_name, attr_map, attr_parse, FromElem};
+use utils::ResultLogExt;
+
+use device::{Algorithm, Feature};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardImage {
+    pub small: Option<PathBuf>,
+    pub large: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Book {
+    pub category: Option<String>,
+    pub name: String,
+    pub title: Option<String>,
+}
+
+impl FromElem for Book {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        Ok(Book {
+            category: attr_map(e, "category", "book").ok(),
+            name: attr_map(e, "name", "book")?,
+            title: attr_map(e, "title", "book").ok(),
+        })
+    }
+}
+
+/// A `mountedDevice` or `compatibleDevice` of a board.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardDevice {
+    pub index: Option<u32>,
+    pub vendor: Option<String>,
+    pub family: Option<String>,
+    pub sub_family: Option<String>,
+    pub name: Option<String>,
+}
+
+impl FromElem for BoardDevice {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        let elemname = match e.name() {
+            "mountedDevice" => "mountedDevice",
+            "compatibleDevice" => "compatibleDevice",
+            other => return Err(err_msg!("{} is not a board device", other)),
+        };
+        Ok(BoardDevice {
+            index: attr_parse(e, "deviceIndex", elemname).ok(),
+            vendor: attr_map(e, "Dvendor", elemname).ok(),
+            family: attr_map(e, "Dfamily", elemname).ok(),
+            sub_family: attr_map(e, "DsubFamily", elemname).ok(),
+            name: attr_map(e, "Dname", elemname).ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugInterface {
+    pub adapter: String,
+    pub connector: Option<String>,
+}
+
+impl FromElem for DebugInterface {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        Ok(DebugInterface {
+            adapter: attr_map(e, "adapter", "debugInterface")?,
+            connector: attr_map(e, "connector", "debugInterface").ok(),
+        })
+    }
+}
+
+/// A development board. `name` and `mounted_devices` keep the layout of
+/// earlier board dumps; everything else is optional there.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Board {
+    pub name: String,
+    pub mounted_devices: Vec<String>,
+    #[serde(default)]
+    pub vendor: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub image: Option<BoardImage>,
+    #[serde(default)]
+    pub books: Vec<Book>,
+    #[serde(default)]
+    pub mounted: Vec<BoardDevice>,
+    #[serde(default)]
+    pub compatible: Vec<BoardDevice>,
+    #[serde(default)]
+    pub debug_interfaces: Vec<DebugInterface>,
+    #[serde(default)]
+    pub features: Vec<Feature>,
+    #[serde(default)]
+    pub algorithms: Vec<Algorithm>,
+}
+
+impl Board {
+    /// Is `device` mounted on, or compatible with, this board?
+    pub fn supports_device(&self, device: &str) -> bool {
+        self.mounted
+            .iter()
+            .chain(self.compatible.iter())
+            .any(|dev| dev.name.as_ref().map(|name| name == device).unwrap_or(false))
+    }
+}
+
+impl FromElem for Board {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "board")?;
+        let mut board = Board {
+            name: attr_map(e, "name", "board")?,
+            mounted_devices: Vec::new(),
+            vendor: attr_map(e, "vendor", "board").ok(),
+            revision: attr_map(e, "revision", "board").ok(),
+            description: None,
+            image: None,
+            books: Vec::new(),
+            mounted: Vec::new(),
+            compatible: Vec::new(),
+            debug_interfaces: Vec::new(),
+            features: Vec::new(),
+            algorithms: Vec::new(),
+        };
+        for child in e.children() {
+            match child.name() {
+                "description" => board.description = Some(child.text().trim().to_string()),
+                "image" => {
+                    board.image = Some(BoardImage {
+                        small: attr_map(child, "small", "image").ok(),
+                        large: attr_map(child, "large", "image").ok(),
+                    })
+                }
+                "book" => board.books.extend(Book::from_elem(child, l).ok_warn(l)),
+                "mountedDevice" => {
+                    if let Some(dev) = BoardDevice::from_elem(child, l).ok_warn(l) {
+                        board.mounted_devices.extend(dev.name.clone());
+                        board.mounted.push(dev);
+                    }
+                }
+                "compatibleDevice" => {
+                    board.compatible.extend(BoardDevice::from_elem(child, l).ok_warn(l))
+                }
+                "debugInterface" => board
+                    .debug_interfaces
+                    .extend(DebugInterface::from_elem(child, l).ok_warn(l)),
+                "feature" => board.features.extend(Feature::from_elem(child, l).ok_warn(l)),
+                "algorithm" => board
+                    .algorithms
+                    .extend(Algorithm::from_elem(child, l).ok_warn(l)),
+                _ => (),
+            }
+        }
+        Ok(board)
+    }
+}
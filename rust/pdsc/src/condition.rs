@@ -1,29 +1,336 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
 use minidom::{Element, Error};
 use slog::Logger;
 
 use utils::parse::{FromElem, assert_root_name, attr_map};
 use utils::ResultLogExt;
 
+use version::Version;
+
+/// A single `accept`, `deny` or `require` expression within a condition.
+///
+/// Every attribute that is present must match for the expression to be true.
+#[derive(Debug, Clone, Default)]
 pub struct ConditionComponent {
     pub device_family: Option<String>,
     pub device_sub_family: Option<String>,
     pub device_variant: Option<String>,
     pub device_vendor: Option<String>,
     pub device_name: Option<String>,
+    pub device_core: Option<String>,
+    pub device_fpu: Option<String>,
+    pub device_mpu: Option<String>,
+    pub device_endian: Option<String>,
+    pub device_secure: Option<String>,
+    pub device_trustzone: Option<String>,
+    pub device_dsp: Option<String>,
+    pub device_mve: Option<String>,
+    pub processor_name: Option<String>,
+    pub component_class: Option<String>,
+    pub component_group: Option<String>,
+    pub component_sub_group: Option<String>,
+    pub component_vendor: Option<String>,
+    pub component_variant: Option<String>,
+    pub component_version: Option<String>,
+    pub component_api_version: Option<String>,
+    pub compiler: Option<String>,
+    pub compiler_options: Option<String>,
+    pub board_name: Option<String>,
+    pub board_vendor: Option<String>,
+    pub board_revision: Option<String>,
+    pub condition: Option<String>,
 }
 
 impl FromElem for ConditionComponent {
     fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        let name: &'static str = match e.name() {
+            "accept" => "accept",
+            "deny" => "deny",
+            _ => "require",
+        };
         Ok(ConditionComponent {
-            device_family: attr_map(e, "Dfamily", "condition").ok(),
-            device_sub_family: attr_map(e, "Dsubfamily", "condition").ok(),
-            device_variant: attr_map(e, "Dvariant", "condition").ok(),
-            device_vendor: attr_map(e, "Dvendor", "condition").ok(),
-            device_name: attr_map(e, "Dname", "condition").ok(),
+            device_family: attr_map(e, "Dfamily", name).ok(),
+            device_sub_family: attr_map(e, "DsubFamily", name)
+                .or_else(|_| attr_map(e, "Dsubfamily", name))
+                .ok(),
+            device_variant: attr_map(e, "Dvariant", name).ok(),
+            device_vendor: attr_map(e, "Dvendor", name).ok(),
+            device_name: attr_map(e, "Dname", name).ok(),
+            device_core: attr_map(e, "Dcore", name).ok(),
+            device_fpu: attr_map(e, "Dfpu", name).ok(),
+            device_mpu: attr_map(e, "Dmpu", name).ok(),
+            device_endian: attr_map(e, "Dendian", name).ok(),
+            device_secure: attr_map(e, "Dsecure", name).ok(),
+            device_trustzone: attr_map(e, "Dtz", name).ok(),
+            device_dsp: attr_map(e, "Ddsp", name).ok(),
+            device_mve: attr_map(e, "Dmve", name).ok(),
+            processor_name: attr_map(e, "Pname", name).ok(),
+            component_class: attr_map(e, "Cclass", name).ok(),
+            component_group: attr_map(e, "Cgroup", name).ok(),
+            component_sub_group: attr_map(e, "Csub", name).ok(),
+            component_vendor: attr_map(e, "Cvendor", name).ok(),
+            component_variant: attr_map(e, "Cvariant", name).ok(),
+            component_version: attr_map(e, "Cversion", name).ok(),
+            component_api_version: attr_map(e, "Capiversion", name).ok(),
+            compiler: attr_map(e, "Tcompiler", name).ok(),
+            compiler_options: attr_map(e, "Toptions", name).ok(),
+            board_name: attr_map(e, "Bname", name).ok(),
+            board_vendor: attr_map(e, "Bvendor", name).ok(),
+            board_revision: attr_map(e, "Brevision", name).ok(),
+            condition: attr_map(e, "condition", name).ok(),
         })
     }
 }
 
+/// A component that has been selected for the target, used to resolve the
+/// `Cclass`, `Cgroup`, etc. attributes of a condition.
+#[derive(Debug, Clone, Default)]
+pub struct SelectedComponent {
+    pub vendor: String,
+    pub class: String,
+    pub group: String,
+    pub sub_group: Option<String>,
+    pub variant: Option<String>,
+    pub version: String,
+    pub api_version: Option<String>,
+}
+
+/// Everything a condition may be evaluated against: the device, the
+/// toolchain, the board and the components already selected.
+#[derive(Debug, Clone, Default)]
+pub struct TargetContext {
+    pub device_name: Option<String>,
+    pub device_vendor: Option<String>,
+    pub device_family: Option<String>,
+    pub device_sub_family: Option<String>,
+    pub device_variant: Option<String>,
+    pub processor_name: Option<String>,
+    pub core: Option<String>,
+    pub fpu: Option<String>,
+    pub mpu: Option<String>,
+    pub endian: Option<String>,
+    pub secure: Option<String>,
+    pub trustzone: Option<String>,
+    pub dsp: Option<String>,
+    pub mve: Option<String>,
+    pub compiler: Option<String>,
+    pub compiler_options: Option<String>,
+    pub board_name: Option<String>,
+    pub board_vendor: Option<String>,
+    pub board_revision: Option<String>,
+    pub components: Vec<SelectedComponent>,
+    /// Treat component dependencies as satisfiable, so that conditions are
+    /// judged by the device, toolchain and board alone.
+    pub assume_components: bool,
+}
+
+impl TargetContext {
+    pub fn with_compiler<S: Into<String>>(self, compiler: S) -> Self {
+        Self {
+            compiler: Some(compiler.into()),
+            ..self
+        }
+    }
+}
+
+/// Match `text` against a pattern that may contain the `*` and `?` wildcards.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Vendors are written as `Name:id`; only the name takes part in matching.
+fn vendor_name(vendor: &str) -> &str {
+    vendor.split(':').next().unwrap_or(vendor)
+}
+
+pub(crate) fn compare_versions(lhs: &str, rhs: &str) -> Ordering {
+    Version::parse_lossy(lhs).cmp(&Version::parse_lossy(rhs))
+}
+
+/// Check a version against a condition version of the form `min` or `min:max`.
+pub(crate) fn version_in_range(range: &str, version: &str) -> bool {
+    let mut bounds = range.splitn(2, ':');
+    let min = bounds.next().unwrap_or("");
+    let above_min = min.is_empty() || compare_versions(version, min) != Ordering::Less;
+    let below_max = match bounds.next() {
+        Some(max) => compare_versions(version, max) != Ordering::Greater,
+        None => true,
+    };
+    above_min && below_max
+}
+
+fn fpu_level(fpu: &Option<String>) -> u8 {
+    match fpu.as_ref().map(String::as_ref) {
+        Some("FPU") | Some("SP_FPU") | Some("1") => 1,
+        Some("DP_FPU") | Some("2") => 2,
+        _ => 0,
+    }
+}
+
+fn mve_level(mve: &Option<String>) -> u8 {
+    match mve.as_ref().map(String::as_ref) {
+        Some("MVE") => 1,
+        Some("FP_MVE") => 2,
+        _ => 0,
+    }
+}
+
+/// Flags such as `MPU`/`NO_MPU` or `DSP`/`NO_DSP` are absent unless the
+/// target says otherwise. The deprecated `1` and `0` stand for the flag and
+/// its absence.
+fn flag_matches(expected: &str, present: &str, actual: &Option<String>) -> bool {
+    let is_present = match *actual {
+        Some(ref act) => act == present || act == "1",
+        None => false,
+    };
+    if expected == present || expected == "1" {
+        is_present
+    } else {
+        !is_present
+    }
+}
+
+fn attr_matches<F>(expected: &Option<String>, actual: &Option<String>, matcher: F) -> bool
+where
+    F: Fn(&str, &str) -> bool,
+{
+    match (expected.as_ref(), actual.as_ref()) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(exp), Some(act)) => matcher(exp, act),
+    }
+}
+
+fn optional_matches<F>(expected: &Option<String>, matcher: F) -> bool
+where
+    F: Fn(&str) -> bool,
+{
+    match *expected {
+        Some(ref exp) => matcher(exp),
+        None => true,
+    }
+}
+
+impl ConditionComponent {
+    fn device_matches(&self, ctx: &TargetContext) -> bool {
+        attr_matches(&self.device_name, &ctx.device_name, wildcard_match)
+            && attr_matches(&self.device_family, &ctx.device_family, wildcard_match)
+            && attr_matches(&self.device_sub_family, &ctx.device_sub_family, wildcard_match)
+            && attr_matches(&self.device_variant, &ctx.device_variant, wildcard_match)
+            && attr_matches(&self.device_vendor, &ctx.device_vendor, |exp, act| {
+                vendor_name(exp) == vendor_name(act)
+            })
+            && attr_matches(&self.processor_name, &ctx.processor_name, wildcard_match)
+            && attr_matches(&self.device_core, &ctx.core, |exp, act| exp == act)
+            && attr_matches(&self.device_endian, &ctx.endian, |exp, act| {
+                exp == act || act == "Configurable"
+            })
+            && attr_matches(&self.device_secure, &ctx.secure, |exp, act| exp == act)
+            && optional_matches(&self.device_fpu, |exp| match exp {
+                "FPU" | "1" => fpu_level(&ctx.fpu) >= 1,
+                "SP_FPU" => fpu_level(&ctx.fpu) == 1,
+                "DP_FPU" => fpu_level(&ctx.fpu) == 2,
+                "NO_FPU" | "0" => fpu_level(&ctx.fpu) == 0,
+                _ => false,
+            })
+            && optional_matches(&self.device_mpu, |exp| flag_matches(exp, "MPU", &ctx.mpu))
+            && optional_matches(&self.device_trustzone, |exp| {
+                flag_matches(exp, "TZ", &ctx.trustzone)
+            })
+            && optional_matches(&self.device_dsp, |exp| flag_matches(exp, "DSP", &ctx.dsp))
+            && optional_matches(&self.device_mve, |exp| match exp {
+                "MVE" => mve_level(&ctx.mve) >= 1,
+                "FP_MVE" => mve_level(&ctx.mve) == 2,
+                _ => mve_level(&ctx.mve) == 0,
+            })
+    }
+
+    fn toolchain_matches(&self, ctx: &TargetContext) -> bool {
+        attr_matches(&self.compiler, &ctx.compiler, |exp, act| exp == act)
+            && attr_matches(&self.compiler_options, &ctx.compiler_options, |exp, act| exp == act)
+    }
+
+    fn board_matches(&self, ctx: &TargetContext) -> bool {
+        attr_matches(&self.board_name, &ctx.board_name, wildcard_match)
+            && attr_matches(&self.board_vendor, &ctx.board_vendor, |exp, act| {
+                vendor_name(exp) == vendor_name(act)
+            })
+            && attr_matches(&self.board_revision, &ctx.board_revision, |exp, act| exp == act)
+    }
+
+    /// Does this expression reference a software component?
+    pub fn references_component(&self) -> bool {
+        self.component_class.is_some() || self.component_group.is_some()
+            || self.component_sub_group.is_some()
+            || self.component_vendor.is_some()
+            || self.component_variant.is_some()
+            || self.component_version.is_some()
+            || self.component_api_version.is_some()
+    }
+
+    /// Does the component satisfy every `C*` attribute of this expression?
+    pub fn component_matches(&self, comp: &SelectedComponent) -> bool {
+        let some = |s: &String| Some(s.clone());
+        attr_matches(&self.component_class, &some(&comp.class), |exp, act| exp == act)
+            && attr_matches(&self.component_group, &some(&comp.group), |exp, act| exp == act)
+            && attr_matches(&self.component_sub_group, &comp.sub_group, |exp, act| exp == act)
+            && attr_matches(&self.component_vendor, &some(&comp.vendor), |exp, act| {
+                vendor_name(exp) == vendor_name(act)
+            })
+            && attr_matches(&self.component_variant, &comp.variant, |exp, act| exp == act)
+            && attr_matches(&self.component_version, &some(&comp.version), version_in_range)
+            && attr_matches(&self.component_api_version, &comp.api_version, version_in_range)
+    }
+
+    /// `negated` is true within an odd number of `deny` expressions; it lets
+    /// assumed component dependencies count in favour of the condition.
+    fn evaluate<'a>(
+        &'a self,
+        ctx: &TargetContext,
+        lookup: &HashMap<&'a str, &'a Condition>,
+        stack: &mut Vec<&'a str>,
+        negated: bool,
+    ) -> bool {
+        let components_ok = if !self.references_component() {
+            true
+        } else if ctx.assume_components {
+            !negated
+        } else {
+            ctx.components.iter().any(|c| self.component_matches(c))
+        };
+        let condition_ok = match self.condition {
+            Some(ref id) => match lookup.get(id.as_str()) {
+                Some(cond) => cond.evaluate_inner(ctx, lookup, stack, negated),
+                None => false,
+            },
+            None => true,
+        };
+        self.device_matches(ctx) && self.toolchain_matches(ctx) && self.board_matches(ctx)
+            && components_ok && condition_ok
+    }
+}
+
 pub struct Condition {
     pub id: String,
     pub accept: Vec<ConditionComponent>,
@@ -31,6 +338,122 @@ pub struct Condition {
     pub require: Vec<ConditionComponent>,
 }
 
+impl Condition {
+    /// Evaluate this condition for a target.
+    ///
+    /// A condition holds when all of its `require` expressions hold, at least
+    /// one of its `accept` expressions (if any) holds and none of its `deny`
+    /// expressions hold. Conditions referenced with `condition=` are looked up
+    /// in `lookup`; a condition with an unknown or cyclic reference anywhere
+    /// within it never holds, even when the reference is in a `deny`.
+    pub fn evaluate<'a>(&'a self, ctx: &TargetContext, lookup: &HashMap<&'a str, &'a Condition>) -> bool {
+        self.bad_reference(lookup).is_none()
+            && self.evaluate_inner(ctx, lookup, &mut Vec::new(), false)
+    }
+
+    /// The first `condition=` reference within this condition, or within
+    /// the conditions it references, that is unknown or cyclic.
+    pub fn bad_reference<'a>(&'a self, lookup: &HashMap<&'a str, &'a Condition>) -> Option<&'a str> {
+        self.bad_reference_inner(lookup, &mut Vec::new())
+    }
+
+    fn bad_reference_inner<'a>(
+        &'a self,
+        lookup: &HashMap<&'a str, &'a Condition>,
+        stack: &mut Vec<&'a str>,
+    ) -> Option<&'a str> {
+        if stack.contains(&self.id.as_str()) {
+            return Some(&self.id);
+        }
+        stack.push(&self.id);
+        let found = self.require
+            .iter()
+            .chain(self.accept.iter())
+            .chain(self.deny.iter())
+            .filter_map(|expr| expr.condition.as_ref())
+            .filter_map(|id| match lookup.get(id.as_str()) {
+                Some(cond) => cond.bad_reference_inner(lookup, stack),
+                None => Some(id.as_str()),
+            })
+            .next();
+        stack.pop();
+        found
+    }
+
+    fn evaluate_inner<'a>(
+        &'a self,
+        ctx: &TargetContext,
+        lookup: &HashMap<&'a str, &'a Condition>,
+        stack: &mut Vec<&'a str>,
+        negated: bool,
+    ) -> bool {
+        if stack.contains(&self.id.as_str()) {
+            return false;
+        }
+        stack.push(&self.id);
+        let result = self.require.iter().all(|r| r.evaluate(ctx, lookup, stack, negated))
+            && (self.accept.is_empty()
+                || self.accept.iter().any(|a| a.evaluate(ctx, lookup, stack, negated)))
+            && !self.deny.iter().any(|d| d.evaluate(ctx, lookup, stack, !negated));
+        stack.pop();
+        result
+    }
+}
+
+impl Condition {
+    /// The component expressions that keep this condition from holding.
+    ///
+    /// Each entry is a set of alternatives, any one of which would satisfy
+    /// it: a `require` yields a single alternative, while the `accept`
+    /// expressions of a condition are offered together. Requirements within
+    /// conditions referenced by `condition=` are included.
+    pub fn unmet_requirements<'a>(
+        &'a self,
+        ctx: &TargetContext,
+        lookup: &HashMap<&'a str, &'a Condition>,
+    ) -> Vec<Vec<&'a ConditionComponent>> {
+        let mut unmet = Vec::new();
+        self.unmet_inner(ctx, lookup, &mut Vec::new(), &mut unmet);
+        unmet
+    }
+
+    fn unmet_inner<'a>(
+        &'a self,
+        ctx: &TargetContext,
+        lookup: &HashMap<&'a str, &'a Condition>,
+        stack: &mut Vec<&'a str>,
+        unmet: &mut Vec<Vec<&'a ConditionComponent>>,
+    ) {
+        if stack.contains(&self.id.as_str()) {
+            return;
+        }
+        stack.push(&self.id);
+        for req in self.require.iter() {
+            if req.evaluate(ctx, lookup, stack, false) {
+                continue;
+            }
+            if req.references_component() {
+                unmet.push(vec![req]);
+            }
+            if let Some(cond) = req.condition.as_ref().and_then(|id| lookup.get(id.as_str())) {
+                cond.unmet_inner(ctx, lookup, stack, unmet);
+            }
+        }
+        if !self.accept.is_empty()
+            && !self.accept.iter().any(|a| a.evaluate(ctx, lookup, stack, false))
+        {
+            let alternatives: Vec<_> = self.accept
+                .iter()
+                .filter(|a| a.references_component())
+                .collect();
+            if !alternatives.is_empty() {
+                unmet.push(alternatives);
+            }
+        }
+        stack.pop();
+    }
+}
+
 impl FromElem for Condition {
     fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
         assert_root_name(e, "condition")?;
@@ -40,13 +463,13 @@ impl FromElem for Condition {
         for elem in e.children() {
             match elem.name() {
                 "accept" => {
-                    accept.push(ConditionComponent::from_elem(e, l)?);
+                    accept.push(ConditionComponent::from_elem(elem, l)?);
                 }
                 "deny" => {
-                    deny.push(ConditionComponent::from_elem(e, l)?);
+                    deny.push(ConditionComponent::from_elem(elem, l)?);
                 }
                 "require" => {
-                    require.push(ConditionComponent::from_elem(e, l)?);
+                    require.push(ConditionComponent::from_elem(elem, l)?);
                 }
                 "description" => {}
                 _ => {
@@ -76,3 +499,204 @@ impl FromElem for Conditions {
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{Discard, Logger};
+
+    fn parse(src: &str) -> Conditions {
+        let log = Logger::root(Discard, o!());
+        Conditions::from_string(src, &log).unwrap()
+    }
+
+    fn lookup(conds: &Conditions) -> HashMap<&str, &Condition> {
+        conds.0.iter().map(|c| (c.id.as_str(), c)).collect()
+    }
+
+    fn stm32_gcc() -> TargetContext {
+        TargetContext {
+            device_name: Some(String::from("STM32F407VG")),
+            device_vendor: Some(String::from("STMicroelectronics:13")),
+            device_family: Some(String::from("STM32F4 Series")),
+            core: Some(String::from("Cortex-M4")),
+            fpu: Some(String::from("SP_FPU")),
+            compiler: Some(String::from("GCC")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("STM32F4*", "STM32F407VG"));
+        assert!(wildcard_match("STM32F40?VG", "STM32F407VG"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("STM32F1*", "STM32F407VG"));
+        assert!(!wildcard_match("STM32F40?", "STM32F407VG"));
+    }
+
+    #[test]
+    fn parses_children() {
+        let conds = parse(
+            "<conditions>
+               <condition id=\"CM4 GCC\">
+                 <accept Dcore=\"Cortex-M4\"/>
+                 <require Tcompiler=\"GCC\" Toptions=\"AC6\"/>
+                 <deny Dvendor=\"ARM:82\"/>
+               </condition>
+             </conditions>",
+        );
+        let cond = &conds.0[0];
+        assert_eq!(cond.accept[0].device_core, Some(String::from("Cortex-M4")));
+        assert_eq!(cond.require[0].compiler, Some(String::from("GCC")));
+        assert_eq!(cond.require[0].compiler_options, Some(String::from("AC6")));
+        assert_eq!(cond.deny[0].device_vendor, Some(String::from("ARM:82")));
+    }
+
+    #[test]
+    fn accept_require_deny() {
+        let conds = parse(
+            "<conditions>
+               <condition id=\"CM3 or CM4\">
+                 <accept Dcore=\"Cortex-M3\"/>
+                 <accept Dcore=\"Cortex-M4\"/>
+               </condition>
+               <condition id=\"GCC\">
+                 <require Tcompiler=\"GCC\"/>
+               </condition>
+               <condition id=\"Not ST\">
+                 <deny Dvendor=\"STMicroelectronics:13\"/>
+               </condition>
+               <condition id=\"FPU\">
+                 <require Dfpu=\"FPU\"/>
+                 <deny Dmpu=\"MPU\"/>
+               </condition>
+             </conditions>",
+        );
+        let lookup = lookup(&conds);
+        let ctx = stm32_gcc();
+        assert!(lookup["CM3 or CM4"].evaluate(&ctx, &lookup));
+        assert!(lookup["GCC"].evaluate(&ctx, &lookup));
+        assert!(!lookup["Not ST"].evaluate(&ctx, &lookup));
+        assert!(lookup["FPU"].evaluate(&ctx, &lookup));
+        let ctx = TargetContext {
+            core: Some(String::from("Cortex-M0")),
+            compiler: Some(String::from("IAR")),
+            fpu: None,
+            ..stm32_gcc()
+        };
+        assert!(!lookup["CM3 or CM4"].evaluate(&ctx, &lookup));
+        assert!(!lookup["GCC"].evaluate(&ctx, &lookup));
+        assert!(!lookup["FPU"].evaluate(&ctx, &lookup));
+    }
+
+    #[test]
+    fn nested_and_components() {
+        let conds = parse(
+            "<conditions>
+               <condition id=\"CMSIS Core\">
+                 <require Cclass=\"CMSIS\" Cgroup=\"CORE\" Cversion=\"5.0.0\"/>
+               </condition>
+               <condition id=\"Device\">
+                 <require condition=\"CMSIS Core\"/>
+                 <require Dname=\"STM32F4*\"/>
+               </condition>
+             </conditions>",
+        );
+        let lookup = lookup(&conds);
+        let mut ctx = stm32_gcc();
+        assert!(!lookup["Device"].evaluate(&ctx, &lookup));
+        ctx.components.push(SelectedComponent {
+            vendor: String::from("ARM"),
+            class: String::from("CMSIS"),
+            group: String::from("CORE"),
+            version: String::from("5.1.2"),
+            ..Default::default()
+        });
+        assert!(lookup["Device"].evaluate(&ctx, &lookup));
+        ctx.components[0].version = String::from("4.5.0");
+        assert!(!lookup["Device"].evaluate(&ctx, &lookup));
+        ctx.components.clear();
+        ctx.assume_components = true;
+        assert!(lookup["Device"].evaluate(&ctx, &lookup));
+    }
+
+    #[test]
+    fn cycles_are_false() {
+        let conds = parse(
+            "<conditions>
+               <condition id=\"A\">
+                 <require condition=\"B\"/>
+               </condition>
+               <condition id=\"B\">
+                 <accept condition=\"A\"/>
+               </condition>
+               <condition id=\"C\">
+                 <require condition=\"Missing\"/>
+               </condition>
+             </conditions>",
+        );
+        let lookup = lookup(&conds);
+        let ctx = stm32_gcc();
+        assert!(!lookup["A"].evaluate(&ctx, &lookup));
+        assert!(!lookup["B"].evaluate(&ctx, &lookup));
+        assert!(!lookup["C"].evaluate(&ctx, &lookup));
+        assert_eq!(lookup["A"].bad_reference(&lookup), Some("A"));
+        assert_eq!(lookup["C"].bad_reference(&lookup), Some("Missing"));
+    }
+
+    #[test]
+    fn dangling_deny() {
+        let conds = parse(
+            "<conditions>
+               <condition id=\"Typo\">
+                 <require Dcore=\"Cortex-M4\"/>
+                 <deny condition=\"typo\"/>
+               </condition>
+               <condition id=\"Uses Typo\">
+                 <accept condition=\"Typo\"/>
+                 <accept Dcore=\"Cortex-M4\"/>
+               </condition>
+             </conditions>",
+        );
+        let lookup = lookup(&conds);
+        let ctx = stm32_gcc();
+        assert!(!lookup["Typo"].evaluate(&ctx, &lookup));
+        assert!(!lookup["Uses Typo"].evaluate(&ctx, &lookup));
+        assert_eq!(lookup["Uses Typo"].bad_reference(&lookup), Some("typo"));
+    }
+
+    #[test]
+    fn numeric_flags() {
+        let conds = parse(
+            "<conditions>
+               <condition id=\"FPU\"><require Dfpu=\"1\"/></condition>
+               <condition id=\"No FPU\"><require Dfpu=\"0\"/></condition>
+               <condition id=\"MPU\"><require Dmpu=\"1\"/></condition>
+               <condition id=\"TZ\"><require Dtz=\"1\"/></condition>
+               <condition id=\"DSP\"><require Ddsp=\"1\"/></condition>
+             </conditions>",
+        );
+        let lookup = lookup(&conds);
+        let ctx = TargetContext {
+            mpu: Some(String::from("MPU")),
+            trustzone: Some(String::from("TZ")),
+            dsp: Some(String::from("DSP")),
+            ..stm32_gcc()
+        };
+        assert!(lookup["FPU"].evaluate(&ctx, &lookup));
+        assert!(!lookup["No FPU"].evaluate(&ctx, &lookup));
+        assert!(lookup["MPU"].evaluate(&ctx, &lookup));
+        assert!(lookup["TZ"].evaluate(&ctx, &lookup));
+        assert!(lookup["DSP"].evaluate(&ctx, &lookup));
+        let ctx = TargetContext {
+            fpu: None,
+            ..Default::default()
+        };
+        assert!(!lookup["FPU"].evaluate(&ctx, &lookup));
+        assert!(lookup["No FPU"].evaluate(&ctx, &lookup));
+        assert!(!lookup["MPU"].evaluate(&ctx, &lookup));
+        assert!(!lookup["TZ"].evaluate(&ctx, &lookup));
+        assert!(!lookup["DSP"].evaluate(&ctx, &lookup));
+    }
+}
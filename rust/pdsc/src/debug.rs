@@ -0,0 +1,424 @@
+use std::path::PathBuf;
+
+use minidom::{Element, Error, ErrorKind};
+use slog::Logger;
+
+use utils::parse::{assert_root_name, attr_map, attr_parse, attr_parse_hex, FromElem};
+use utils::ResultLogExt;
+
+use device::NumberBool;
+
+fn attr_bool(e: &Element, name: &str, elemname: &'static str) -> Option<bool> {
+    attr_parse(e, name, elemname).map(|nb: NumberBool| nb.into()).ok()
+}
+
+/// minidom does not expand entities in attribute values, and the expressions
+/// of a `control` element commonly contain `&amp;`, `&lt;` and `&gt;`.
+fn attr_expr(e: &Element, name: &str) -> Option<String> {
+    e.attr(name).map(|expr| {
+        expr.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    })
+}
+
+fn attr_id(e: &Element, name: &str, elemname: &'static str) -> Result<u32, Error> {
+    attr_parse_hex(e, name, elemname).map(|id| id as u32)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DebugConfig {
+    pub default: Option<String>,
+    pub clock: Option<u64>,
+    pub swj: Option<bool>,
+    pub dormant: Option<bool>,
+    pub sdf: Option<PathBuf>,
+}
+
+impl DebugConfig {
+    fn merge(self, parent: &Self) -> Self {
+        DebugConfig {
+            default: self.default.or_else(|| parent.default.clone()),
+            clock: self.clock.or(parent.clock),
+            swj: self.swj.or(parent.swj),
+            dormant: self.dormant.or(parent.dormant),
+            sdf: self.sdf.or_else(|| parent.sdf.clone()),
+        }
+    }
+}
+
+impl FromElem for DebugConfig {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "debugconfig")?;
+        Ok(DebugConfig {
+            default: attr_map(e, "default", "debugconfig").ok(),
+            clock: attr_parse_hex(e, "clock", "debugconfig").ok(),
+            swj: attr_bool(e, "swj", "debugconfig"),
+            dormant: attr_bool(e, "dormant", "debugconfig"),
+            sdf: attr_map(e, "sdf", "debugconfig").ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortInterface {
+    pub tap_index: Option<u32>,
+    pub idcode: Option<u64>,
+    pub target_select: Option<u64>,
+    pub ir_length: Option<u32>,
+}
+
+impl FromElem for PortInterface {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        Ok(PortInterface {
+            tap_index: attr_id(e, "tapindex", "debugport").ok(),
+            idcode: attr_parse_hex(e, "idcode", "debugport").ok(),
+            target_select: attr_parse_hex(e, "targetsel", "debugport").ok(),
+            ir_length: attr_id(e, "irlen", "debugport").ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugPort {
+    pub dp: u32,
+    pub swd: Option<PortInterface>,
+    pub jtag: Option<PortInterface>,
+    pub cjtag: Option<PortInterface>,
+}
+
+impl FromElem for DebugPort {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "debugport")?;
+        let mut port = DebugPort {
+            dp: attr_id(e, "__dp", "debugport").unwrap_or(0),
+            swd: None,
+            jtag: None,
+            cjtag: None,
+        };
+        for child in e.children() {
+            let iface = PortInterface::from_elem(child, l).ok_warn(l);
+            match child.name() {
+                "swd" => port.swd = iface,
+                "jtag" => port.jtag = iface,
+                "cjtag" => port.cjtag = iface,
+                _ => (),
+            }
+        }
+        Ok(port)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AccessPort {
+    V1 {
+        apid: u32,
+        dp: u32,
+        index: u32,
+    },
+    V2 {
+        apid: u32,
+        dp: u32,
+        address: u64,
+        parent: Option<u32>,
+    },
+}
+
+impl AccessPort {
+    fn apid(&self) -> u32 {
+        match *self {
+            AccessPort::V1 { apid, .. } => apid,
+            AccessPort::V2 { apid, .. } => apid,
+        }
+    }
+}
+
+impl FromElem for AccessPort {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        match e.name() {
+            "accessportV1" => Ok(AccessPort::V1 {
+                apid: attr_id(e, "__apid", "accessportV1")?,
+                dp: attr_id(e, "__dp", "accessportV1").unwrap_or(0),
+                index: attr_id(e, "index", "accessportV1")?,
+            }),
+            "accessportV2" => Ok(AccessPort::V2 {
+                apid: attr_id(e, "__apid", "accessportV2")?,
+                dp: attr_id(e, "__dp", "accessportV2").unwrap_or(0),
+                address: attr_parse_hex(e, "address", "accessportV2")?,
+                parent: attr_id(e, "parent", "accessportV2").ok(),
+            }),
+            other => Err(err_msg!("tried to parse an access port from element \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataPatch {
+    pub dp: Option<u32>,
+    pub ap: Option<u32>,
+    pub apid: Option<u32>,
+    pub address: u64,
+    pub value: u64,
+    pub mask: Option<u64>,
+    pub kind: Option<String>,
+    pub info: Option<String>,
+}
+
+impl FromElem for DataPatch {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "datapatch")?;
+        Ok(DataPatch {
+            dp: attr_id(e, "__dp", "datapatch").ok(),
+            ap: attr_id(e, "__ap", "datapatch").ok(),
+            apid: attr_id(e, "__apid", "datapatch").ok(),
+            address: attr_parse_hex(e, "address", "datapatch")?,
+            value: attr_parse_hex(e, "value", "datapatch")?,
+            mask: attr_parse_hex(e, "mask", "datapatch").ok(),
+            kind: attr_map(e, "type", "datapatch").ok(),
+            info: attr_map(e, "info", "datapatch").ok(),
+        })
+    }
+}
+
+/// Debug access to one processor of a device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessorDebug {
+    pub processor: Option<String>,
+    pub unit: Option<u32>,
+    pub dp: Option<u32>,
+    pub ap: Option<u32>,
+    pub apid: Option<u32>,
+    pub address: Option<u64>,
+    pub svd: Option<PathBuf>,
+    pub default_reset_sequence: Option<String>,
+    pub data_patches: Vec<DataPatch>,
+}
+
+impl FromElem for ProcessorDebug {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "debug")?;
+        Ok(ProcessorDebug {
+            processor: attr_map(e, "Pname", "debug").ok(),
+            unit: attr_id(e, "Punit", "debug").ok(),
+            dp: attr_id(e, "__dp", "debug").ok(),
+            ap: attr_id(e, "__ap", "debug").ok(),
+            apid: attr_id(e, "__apid", "debug").ok(),
+            address: attr_parse_hex(e, "address", "debug").ok(),
+            svd: attr_map(e, "svd", "debug").ok(),
+            default_reset_sequence: attr_map(e, "defaultResetSequence", "debug").ok(),
+            data_patches: DataPatch::vec_from_children(e.children(), l),
+        })
+    }
+}
+
+/// A `block` or `control` element of a debug access sequence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SequenceElement {
+    Block {
+        atomic: bool,
+        info: Option<String>,
+        code: String,
+    },
+    Control {
+        condition: Option<String>,
+        repeat_while: Option<String>,
+        /// Microseconds the `while` loop may run; a `timeout` of 0, the
+        /// default, means it runs for as long as it has to.
+        timeout: Option<u64>,
+        info: Option<String>,
+        body: Vec<SequenceElement>,
+    },
+}
+
+impl FromElem for SequenceElement {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        match e.name() {
+            "block" => Ok(SequenceElement::Block {
+                atomic: attr_bool(e, "atomic", "block").unwrap_or(false),
+                info: attr_map(e, "info", "block").ok(),
+                code: e.text(),
+            }),
+            "control" => Ok(SequenceElement::Control {
+                condition: attr_expr(e, "if"),
+                repeat_while: attr_expr(e, "while"),
+                timeout: attr_parse_hex(e, "timeout", "control")
+                    .ok()
+                    .filter(|&timeout| timeout != 0),
+                info: attr_map(e, "info", "control").ok(),
+                body: SequenceElement::vec_from_children(e.children(), l),
+            }),
+            other => Err(err_msg!("element {} is not allowed in a sequence", other)),
+        }
+    }
+}
+
+/// A named debug access sequence, such as `ResetSystem`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sequence {
+    pub name: String,
+    pub processor: Option<String>,
+    pub disable: bool,
+    pub info: Option<String>,
+    pub body: Vec<SequenceElement>,
+}
+
+impl FromElem for Sequence {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "sequence")?;
+        Ok(Sequence {
+            name: attr_map(e, "name", "sequence")?,
+            processor: attr_map(e, "Pname", "sequence").ok(),
+            disable: attr_bool(e, "disable", "sequence").unwrap_or(false),
+            info: attr_map(e, "info", "sequence").ok(),
+            body: SequenceElement::vec_from_children(e.children(), l),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugVars {
+    pub processor: Option<String>,
+    pub config_file: Option<PathBuf>,
+    pub version: Option<String>,
+    pub code: String,
+}
+
+impl FromElem for DebugVars {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "debugvars")?;
+        Ok(DebugVars {
+            processor: attr_map(e, "Pname", "debugvars").ok(),
+            config_file: attr_map(e, "configfile", "debugvars").ok(),
+            version: attr_map(e, "version", "debugvars").ok(),
+            code: e.text(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceBuffer {
+    pub start: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Trace {
+    pub serial_wire: bool,
+    pub trace_port_widths: Vec<u8>,
+    pub trace_buffer: Option<TraceBuffer>,
+}
+
+impl FromElem for Trace {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "trace")?;
+        let mut trace = Trace::default();
+        for child in e.children() {
+            match child.name() {
+                "serialwire" => trace.serial_wire = true,
+                "traceport" => {
+                    trace.trace_port_widths = child.attr("width")
+                        .unwrap_or_default()
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .filter_map(|w| w.parse().ok())
+                        .collect();
+                }
+                "tracebuffer" => {
+                    trace.trace_buffer = Some(TraceBuffer {
+                        start: attr_parse_hex(child, "start", "tracebuffer")?,
+                        size: attr_parse_hex(child, "size", "tracebuffer")?,
+                    });
+                }
+                _ => (),
+            }
+        }
+        Ok(trace)
+    }
+}
+
+/// Everything a debugger needs to connect to a device.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceDebug {
+    pub config: DebugConfig,
+    pub debug_ports: Vec<DebugPort>,
+    pub access_ports: Vec<AccessPort>,
+    pub debugs: Vec<ProcessorDebug>,
+    pub sequences: Vec<Sequence>,
+    pub debug_vars: Vec<DebugVars>,
+    pub trace: Option<Trace>,
+}
+
+/// Extend `child` with the entries of `parent` that it does not override.
+pub(crate) fn inherit<T: Clone, K: PartialEq, F: Fn(&T) -> K>(mut child: Vec<T>, parent: &[T], key: F) -> Vec<T> {
+    let inherited: Vec<T> = parent
+        .iter()
+        .filter(|p| !child.iter().any(|c| key(c) == key(p)))
+        .cloned()
+        .collect();
+    child.extend(inherited);
+    child
+}
+
+impl DeviceDebug {
+    pub(crate) fn merge(self, parent: &Self) -> Self {
+        DeviceDebug {
+            config: self.config.merge(&parent.config),
+            debug_ports: inherit(self.debug_ports, &parent.debug_ports, |p| p.dp),
+            access_ports: inherit(self.access_ports, &parent.access_ports, AccessPort::apid),
+            debugs: inherit(self.debugs, &parent.debugs, |d| d.processor.clone()),
+            sequences: inherit(self.sequences, &parent.sequences, |s| {
+                (s.name.clone(), s.processor.clone())
+            }),
+            debug_vars: inherit(self.debug_vars, &parent.debug_vars, |d| d.processor.clone()),
+            trace: self.trace.or_else(|| parent.trace.clone()),
+        }
+    }
+
+    /// Record a debug related child element of a family, subFamily, device
+    /// or variant. Other elements are ignored.
+    pub(crate) fn add_elem(&mut self, e: &Element, l: &Logger) {
+        match e.name() {
+            "debugconfig" => {
+                if let Some(config) = DebugConfig::from_elem(e, l).ok_warn(l) {
+                    self.config = config.merge(&self.config);
+                }
+            }
+            "debugport" => {
+                if let Some(port) = DebugPort::from_elem(e, l).ok_warn(l) {
+                    self.debug_ports.retain(|p| p.dp != port.dp);
+                    self.debug_ports.push(port);
+                }
+            }
+            "accessportV1" | "accessportV2" => {
+                if let Some(port) = AccessPort::from_elem(e, l).ok_warn(l) {
+                    self.access_ports.retain(|p| p.apid() != port.apid());
+                    self.access_ports.push(port);
+                }
+            }
+            "debug" => {
+                if let Some(debug) = ProcessorDebug::from_elem(e, l).ok_warn(l) {
+                    self.debugs.retain(|d| d.processor != debug.processor);
+                    self.debugs.push(debug);
+                }
+            }
+            "sequences" => {
+                for seq in Sequence::vec_from_children(e.children(), l) {
+                    self.sequences
+                        .retain(|s| s.name != seq.name || s.processor != seq.processor);
+                    self.sequences.push(seq);
+                }
+            }
+            "debugvars" => {
+                if let Some(vars) = DebugVars::from_elem(e, l).ok_warn(l) {
+                    self.debug_vars.retain(|d| d.processor != vars.processor);
+                    self.debug_vars.push(vars);
+                }
+            }
+            "trace" => {
+                self.trace = Trace::from_elem(e, l).ok_warn(l);
+            }
+            _ => (),
+        }
+    }
+}
@@ -1,83 +1,116 @@
 use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use minidom::{Error, ErrorKind, Element};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use slog::Logger;
 
 use utils::parse::{attr_map, attr_parse, attr_parse_hex, FromElem};
 use utils::ResultLogExt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-enum Core {
-    CortexM0,
-    CortexM0Plus,
-    CortexM1,
-    CortexM3,
-    CortexM4,
-    CortexM7,
-    CortexM23,
-    CortexM33,
-    SC000,
-    SC300,
-    ARMV8MBL,
-    ARMV8MML,
-    CortexR4,
-    CortexR5,
-    CortexR7,
-    CortexR8,
-    CortexA5,
-    CortexA7,
-    CortexA8,
-    CortexA9,
-    CortexA15,
-    CortexA17,
-    CortexA32,
-    CortexA35,
-    CortexA53,
-    CortexA57,
-    CortexA72,
-    CortexA73,
+use condition::TargetContext;
+use debug::{inherit, DeviceDebug};
+
+/// Declares `Core`, and the table of the `Dcore` names of its variants,
+/// from one list so that the two cannot drift apart.
+macro_rules! cores {
+    ($($variant:ident => $name:expr,)*) => {
+        /// A processor core, by its `Dcore` name in Display, `FromStr` and
+        /// serde alike.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum Core {
+            $($variant,)*
+            /// A core this crate does not know about, by its `Dcore` name.
+            Other(String),
+        }
+
+        /// Every core but `Other`, with its `Dcore` name.
+        pub(crate) const CORES: &[(Core, &str)] = &[$((Core::$variant, $name),)*];
+    };
+}
+
+cores! {
+    CortexM0 => "Cortex-M0",
+    CortexM0Plus => "Cortex-M0+",
+    CortexM1 => "Cortex-M1",
+    CortexM3 => "Cortex-M3",
+    CortexM4 => "Cortex-M4",
+    CortexM7 => "Cortex-M7",
+    CortexM23 => "Cortex-M23",
+    CortexM33 => "Cortex-M33",
+    CortexM35P => "Cortex-M35P",
+    CortexM52 => "Cortex-M52",
+    CortexM55 => "Cortex-M55",
+    CortexM85 => "Cortex-M85",
+    SC000 => "SC000",
+    SC300 => "SC300",
+    StarMC1 => "Star-MC1",
+    ARMV8MBL => "ARMV8MBL",
+    ARMV8MML => "ARMV8MML",
+    ARMV81MML => "ARMV81MML",
+    CortexR4 => "Cortex-R4",
+    CortexR5 => "Cortex-R5",
+    CortexR7 => "Cortex-R7",
+    CortexR8 => "Cortex-R8",
+    CortexR52 => "Cortex-R52",
+    CortexA5 => "Cortex-A5",
+    CortexA7 => "Cortex-A7",
+    CortexA8 => "Cortex-A8",
+    CortexA9 => "Cortex-A9",
+    CortexA15 => "Cortex-A15",
+    CortexA17 => "Cortex-A17",
+    CortexA32 => "Cortex-A32",
+    CortexA35 => "Cortex-A35",
+    CortexA53 => "Cortex-A53",
+    CortexA57 => "Cortex-A57",
+    CortexA72 => "Cortex-A72",
+    CortexA73 => "Cortex-A73",
+}
+
+impl Serialize for Core {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Core {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(::serde::de::Error::custom)
+    }
 }
 
 impl FromStr for Core {
     type Err = Error;
     fn from_str(from: &str) -> Result<Self, Error> {
-        match from {
-            "Cortex-M0" =>  Ok(Core::CortexM0),
-            "Cortex-M0+" => Ok(Core::CortexM0Plus),
-            "Cortex-M1" =>  Ok(Core::CortexM1),
-            "Cortex-M3" =>  Ok(Core::CortexM3),
-            "Cortex-M4" =>  Ok(Core::CortexM4),
-            "Cortex-M7" =>  Ok(Core::CortexM7),
-            "Cortex-M23" => Ok(Core::CortexM23),
-            "Cortex-M33" => Ok(Core::CortexM33),
-            "SC000" =>      Ok(Core::SC000),
-            "SC300" =>      Ok(Core::SC300),
-            "ARMV8MBL" =>   Ok(Core::ARMV8MBL),
-            "ARMV8MML" =>   Ok(Core::ARMV8MML),
-            "Cortex-R4" =>  Ok(Core::CortexR4),
-            "Cortex-R5" =>  Ok(Core::CortexR5),
-            "Cortex-R7" =>  Ok(Core::CortexR7),
-            "Cortex-R8" =>  Ok(Core::CortexR8),
-            "Cortex-A5" =>  Ok(Core::CortexA5),
-            "Cortex-A7" =>  Ok(Core::CortexA7),
-            "Cortex-A8" =>  Ok(Core::CortexA8),
-            "Cortex-A9" =>  Ok(Core::CortexA9),
-            "Cortex-A15" => Ok(Core::CortexA15),
-            "Cortex-A17" => Ok(Core::CortexA17),
-            "Cortex-A32" => Ok(Core::CortexA32),
-            "Cortex-A35" => Ok(Core::CortexA35),
-            "Cortex-A53" => Ok(Core::CortexA53),
-            "Cortex-A57" => Ok(Core::CortexA57),
-            "Cortex-A72" => Ok(Core::CortexA72),
-            "Cortex-A73" => Ok(Core::CortexA73),
-            unknown => Err(err_msg!("Unknown core {}", unknown)),
+        if from.is_empty() {
+            return Err(err_msg!("Empty core name"));
         }
+        Ok(CORES
+            .iter()
+            .find(|entry| entry.1 == from)
+            .map(|entry| entry.0.clone())
+            .unwrap_or_else(|| Core::Other(from.to_string())))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Display for Core {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Core::Other(ref name) => name,
+            ref known => CORES
+                .iter()
+                .find(|entry| entry.0 == *known)
+                .map(|entry| entry.1)
+                .unwrap_or_default(),
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FPU {
     None,
     SinglePrecision,
@@ -91,6 +124,7 @@ impl FromStr for FPU {
             "FPU" => Ok(FPU::SinglePrecision),
             "SP_FPU" => Ok(FPU::SinglePrecision),
             "1" => Ok(FPU::SinglePrecision),
+            "NO_FPU" => Ok(FPU::None),
             "None" => Ok(FPU::None),
             "0" => Ok(FPU::None),
             "DP_FPU" => Ok(FPU::DoublePrecision),
@@ -100,7 +134,17 @@ impl FromStr for FPU {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Display for FPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            FPU::None => "NO_FPU",
+            FPU::SinglePrecision => "SP_FPU",
+            FPU::DoublePrecision => "DP_FPU",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MPU {
     NotPresent,
     Present,
@@ -112,45 +156,218 @@ impl FromStr for MPU {
         match from {
             "MPU" => Ok(MPU::Present),
             "1" => Ok(MPU::Present),
+            "NO_MPU" => Ok(MPU::NotPresent),
             "None" => Ok(MPU::NotPresent),
             "0" => Ok(MPU::NotPresent),
-            unknown => Err(err_msg!("Unknown fpu {}", unknown)),
+            unknown => Err(err_msg!("Unknown mpu {}", unknown)),
         }
     }
 }
 
+impl fmt::Display for MPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            MPU::NotPresent => "NO_MPU",
+            MPU::Present => "MPU",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Endian {
+    Little,
+    Big,
+    Configurable,
+}
+
+impl FromStr for Endian {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from {
+            "Little-endian" => Ok(Endian::Little),
+            "Big-endian" => Ok(Endian::Big),
+            "Configurable" | "*" => Ok(Endian::Configurable),
+            unknown => Err(err_msg!("Unknown endianness {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Endian::Little => "Little-endian",
+            Endian::Big => "Big-endian",
+            Endian::Configurable => "Configurable",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MVE {
+    None,
+    Integer,
+    FloatingPoint,
+}
+
+impl FromStr for MVE {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from {
+            "NO_MVE" => Ok(MVE::None),
+            "MVE" => Ok(MVE::Integer),
+            "FP_MVE" => Ok(MVE::FloatingPoint),
+            unknown => Err(err_msg!("Unknown mve {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for MVE {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            MVE::None => "NO_MVE",
+            MVE::Integer => "MVE",
+            MVE::FloatingPoint => "FP_MVE",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Security {
+    Secure,
+    NonSecure,
+    TrustZoneDisabled,
+}
+
+impl FromStr for Security {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from {
+            "Secure" => Ok(Security::Secure),
+            "Non-secure" => Ok(Security::NonSecure),
+            "TZ-disabled" => Ok(Security::TrustZoneDisabled),
+            unknown => Err(err_msg!("Unknown security state {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Security::Secure => "Secure",
+            Security::NonSecure => "Non-secure",
+            Security::TrustZoneDisabled => "TZ-disabled",
+        })
+    }
+}
+
+fn no_mve() -> MVE {
+    MVE::None
+}
+
+/// Parse a feature flag such as `Dtz="TZ"` or `Ddsp="NO_DSP"`.
+fn attr_flag(e: &Element, name: &str, present: &str) -> Option<bool> {
+    match e.attr(name) {
+        Some("1") => Some(true),
+        Some("0") => Some(false),
+        Some(flag) if flag == present => Some(true),
+        Some(flag) if flag.starts_with("NO_") && &flag[3..] == present => Some(false),
+        _ => None,
+    }
+}
+
+fn flag_name(flag: bool, present: &str) -> String {
+    if flag {
+        present.to_string()
+    } else {
+        format!("NO_{}", present)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Processor {
-    units: u8,
-    core: Core,
-    fpu: FPU,
-    mpu: MPU,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub units: u8,
+    pub core: Core,
+    pub fpu: FPU,
+    pub mpu: MPU,
+    #[serde(default)]
+    pub endian: Option<Endian>,
+    #[serde(default)]
+    pub trustzone: bool,
+    #[serde(default)]
+    pub dsp: bool,
+    #[serde(default = "no_mve")]
+    pub mve: MVE,
+    #[serde(default)]
+    pub secure: Option<Security>,
+    #[serde(default)]
+    pub cdecp: Option<u8>,
+    #[serde(default)]
+    pub clock: Option<u64>,
+}
+
+impl Processor {
+    fn add_to_context(&self, ctx: &mut TargetContext) {
+        ctx.core = Some(self.core.to_string());
+        ctx.fpu = Some(self.fpu.to_string());
+        ctx.mpu = Some(self.mpu.to_string());
+        ctx.endian = self.endian.as_ref().map(Endian::to_string);
+        ctx.trustzone = Some(flag_name(self.trustzone, "TZ"));
+        ctx.dsp = Some(flag_name(self.dsp, "DSP"));
+        ctx.mve = Some(self.mve.to_string());
+        ctx.secure = self.secure.as_ref().map(Security::to_string);
+    }
 }
 
 #[derive(Debug, Clone)]
 struct ProcessorBuilder {
+    name: Option<String>,
     core: Option<Core>,
     units: Option<u8>,
     fpu: Option<FPU>,
     mpu: Option<MPU>,
+    endian: Option<Endian>,
+    trustzone: Option<bool>,
+    dsp: Option<bool>,
+    mve: Option<MVE>,
+    secure: Option<Security>,
+    cdecp: Option<u8>,
+    clock: Option<u64>,
 }
 
 impl ProcessorBuilder {
     fn merge(self, parent: &Self) -> Self {
         ProcessorBuilder{
+            name: self.name.or_else(|| parent.name.clone()),
             core: self.core.or_else(|| parent.core.clone()),
             units: self.units.or_else(|| parent.units.clone()),
             fpu: self.fpu.or_else(|| parent.fpu.clone()),
             mpu: self.mpu.or_else(|| parent.mpu.clone()),
+            endian: self.endian.or_else(|| parent.endian.clone()),
+            trustzone: self.trustzone.or(parent.trustzone),
+            dsp: self.dsp.or(parent.dsp),
+            mve: self.mve.or_else(|| parent.mve.clone()),
+            secure: self.secure.or_else(|| parent.secure.clone()),
+            cdecp: self.cdecp.or(parent.cdecp),
+            clock: self.clock.or(parent.clock),
         }
     }
 
     fn build(self) -> Result<Processor, Error>{
         Ok(Processor{
+            name: self.name,
             core: self.core.ok_or_else(|| err_msg!("No Core found!"))?,
             units: self.units.unwrap_or(1u8),
             fpu: self.fpu.unwrap_or(FPU::None),
             mpu: self.mpu.unwrap_or(MPU::NotPresent),
+            endian: self.endian,
+            trustzone: self.trustzone.unwrap_or(false),
+            dsp: self.dsp.unwrap_or(false),
+            mve: self.mve.unwrap_or(MVE::None),
+            secure: self.secure,
+            cdecp: self.cdecp,
+            clock: self.clock,
         })
     }
 }
@@ -158,10 +375,18 @@ impl ProcessorBuilder {
 impl FromElem for ProcessorBuilder {
     fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
         Ok(ProcessorBuilder{
+            name: attr_map(e, "Pname", "processor").ok(),
             core: attr_parse(e, "Dcore", "processor").ok(),
             units: attr_parse(e, "Punits", "processor").ok(),
             fpu: attr_parse(e, "Dfpu", "processor").ok(),
             mpu: attr_parse(e, "Dmpu", "processor").ok(),
+            endian: attr_parse(e, "Dendian", "processor").ok(),
+            trustzone: attr_flag(e, "Dtz", "TZ"),
+            dsp: attr_flag(e, "Ddsp", "DSP"),
+            mve: attr_parse(e, "Dmve", "processor").ok(),
+            secure: attr_parse(e, "Dsecure", "processor").ok(),
+            cdecp: attr_parse_hex(e, "Dcdecp", "processor").ok().map(|cp| cp as u8),
+            clock: attr_parse(e, "Dclock", "processor").ok(),
         })
     }
 }
@@ -194,7 +419,13 @@ impl ProcessorsBuilder{
                     &Some(ProcessorsBuilder::Symmetric(_)) =>
                         Err(err_msg!("Tried to merge asymmetric and symmetric processors")),
                     &Some(ProcessorsBuilder::Asymmetric(ref par_map)) => {
-                        me.extend(par_map.iter().map(|(k, v)| (k.clone(), v.clone())));
+                        for (name, parent_prc) in par_map {
+                            let merged = match me.remove(name) {
+                                Some(prc) => prc.merge(parent_prc),
+                                None => parent_prc.clone(),
+                            };
+                            me.insert(name.clone(), merged);
+                        }
                         Ok(ProcessorsBuilder::Asymmetric(me))
                     },
                     &None => Ok(ProcessorsBuilder::Asymmetric(me)),
@@ -278,7 +509,7 @@ impl MemoryPermissions {
     }
 }
 
-enum NumberBool{
+pub(crate) enum NumberBool{
     False,
     True,
 }
@@ -398,12 +629,80 @@ impl FromElem for Algorithm {
     }
 }
 
+/// The device header and preprocessor define of a `<compile>` element.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Compile {
+    pub processor: Option<String>,
+    pub header: Option<PathBuf>,
+    pub define: Option<String>,
+}
+
+impl FromElem for Compile {
+    fn from_elem(e: &Element, _l: &Logger) -> Result<Self, Error> {
+        Ok(Compile {
+            processor: attr_map(e, "Pname", "compile").ok(),
+            header: attr_map(e, "header", "compile").ok(),
+            define: attr_map(e, "define", "compile").ok(),
+        })
+    }
+}
+
+/// Tool specific settings from an `<environment>` element, keyed by the
+/// names of its children.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Environment {
+    pub name: String,
+    pub processor: Option<String>,
+    pub settings: BTreeMap<String, String>,
+}
+
+impl FromElem for Environment {
+    fn from_elem(e: &Element, _l: &Logger) -> Result<Self, Error> {
+        Ok(Environment {
+            name: attr_map(e, "name", "environment")?,
+            processor: attr_map(e, "Pname", "environment").ok(),
+            settings: e.children()
+                .map(|child| (child.name().to_string(), child.text().trim().to_string()))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub n: Option<f64>,
+    pub m: Option<f64>,
+    pub name: Option<String>,
+    pub count: Option<u32>,
+    pub processor: Option<String>,
+}
+
+impl FromElem for Feature {
+    fn from_elem(e: &Element, _l: &Logger) -> Result<Self, Error> {
+        Ok(Feature {
+            kind: attr_map(e, "type", "feature")?,
+            n: attr_parse(e, "n", "feature").ok(),
+            m: attr_parse(e, "m", "feature").ok(),
+            name: attr_map(e, "name", "feature").ok(),
+            count: attr_parse(e, "count", "feature").ok(),
+            processor: attr_map(e, "Pname", "feature").ok(),
+        })
+    }
+}
+
 #[derive(Debug)]
 struct DeviceBuilder<'dom> {
     name: Option<&'dom str>,
     algorithms: Vec<Algorithm>,
     memories: Memories,
     processor: Option<ProcessorsBuilder>,
+    debug: DeviceDebug,
+    compile: Vec<Compile>,
+    environments: Vec<Environment>,
+    features: Vec<Feature>,
+    description: Option<String>,
     vendor: Option<&'dom str>,
     family: Option<&'dom str>,
     sub_family: Option<&'dom str>
@@ -415,11 +714,42 @@ pub struct Device {
     pub memories: Memories,
     pub algorithms: Vec<Algorithm>,
     pub processor: Processors,
+    pub debug: DeviceDebug,
+    pub compile: Vec<Compile>,
+    pub environments: Vec<Environment>,
+    pub features: Vec<Feature>,
+    pub description: Option<String>,
     pub vendor: Option<String>,
     pub family: String,
     pub sub_family: Option<String>,
 }
 
+impl Device {
+    /// Describe this device for condition evaluation. Multi-core devices
+    /// use the processor named `pname`, or their first processor.
+    pub fn target_context(&self, pname: Option<&str>) -> TargetContext {
+        let mut ctx = TargetContext {
+            device_name: Some(self.name.clone()),
+            device_vendor: self.vendor.clone(),
+            device_family: Some(self.family.clone()),
+            device_sub_family: self.sub_family.clone(),
+            ..Default::default()
+        };
+        match self.processor {
+            Processors::Symmetric(ref prc) => prc.add_to_context(&mut ctx),
+            Processors::Asymmetric(ref map) => {
+                let named = pname.and_then(|name| map.get(name).map(|prc| (name, prc)));
+                let first = || map.iter().next().map(|(name, prc)| (name.as_str(), prc));
+                if let Some((name, prc)) = named.or_else(first) {
+                    ctx.processor_name = Some(name.to_string());
+                    prc.add_to_context(&mut ctx);
+                }
+            }
+        }
+        ctx
+    }
+}
+
 impl<'dom> DeviceBuilder<'dom> {
     fn from_elem(e: &'dom Element) -> Self {
         let memories = Memories(HashMap::new());
@@ -437,6 +767,11 @@ impl<'dom> DeviceBuilder<'dom> {
             memories,
             algorithms: Vec::new(),
             processor: None,
+            debug: DeviceDebug::default(),
+            compile: Vec::new(),
+            environments: Vec::new(),
+            features: Vec::new(),
+            description: None,
             family,
             sub_family,
         }
@@ -457,6 +792,11 @@ impl<'dom> DeviceBuilder<'dom> {
             name,
             memories: self.memories,
             algorithms: self.algorithms,
+            debug: self.debug,
+            compile: self.compile,
+            environments: self.environments,
+            features: self.features,
+            description: self.description,
             vendor: self.vendor.map(str::to_string),
             family,
             sub_family: self.sub_family.map(str::to_string),
@@ -473,6 +813,17 @@ impl<'dom> DeviceBuilder<'dom> {
                 Some(old_proc) => Some(old_proc.merge(&parent.processor)?),
                 None => parent.processor.clone(),
             },
+            debug: self.debug.merge(&parent.debug),
+            compile: merge_compile(self.compile, &parent.compile),
+            environments: inherit(self.environments, &parent.environments, |env| {
+                (env.name.clone(), env.processor.clone())
+            }),
+            // Features add up: only a repeat of the very same feature is
+            // dropped, so that a device can add a Timer to those of its family.
+            features: inherit(self.features, &parent.features, |feat| {
+                (feat.kind.clone(), feat.n, feat.m, feat.name.clone())
+            }),
+            description: self.description.or_else(|| parent.description.clone()),
             vendor: self.vendor.or(parent.vendor),
             family: self.family.or(parent.family),
             sub_family: self.sub_family.or(parent.sub_family),
@@ -496,13 +847,88 @@ impl<'dom> DeviceBuilder<'dom> {
         self.algorithms.push(alg);
         self
     }
+
+    /// Record any other child of a family, subFamily, device or variant.
+    fn add_elem(&mut self, e: &Element, l: &Logger) {
+        match e.name() {
+            "compile" => {
+                if let Some(compile) = Compile::from_elem(e, l).ok_warn(l) {
+                    let own: Vec<Compile> = self.compile.drain(..).collect();
+                    self.compile = merge_compile(vec![compile], &own);
+                }
+            }
+            "environment" => {
+                if let Some(env) = Environment::from_elem(e, l).ok_warn(l) {
+                    self.environments
+                        .retain(|old| old.name != env.name || old.processor != env.processor);
+                    self.environments.push(env);
+                }
+            }
+            "feature" => {
+                if let Some(feat) = Feature::from_elem(e, l).ok_warn(l) {
+                    self.features.push(feat);
+                }
+            }
+            "description" => {
+                self.description = Some(e.text().trim().to_string());
+            }
+            _ => self.debug.add_elem(e, l),
+        }
+    }
+}
+
+/// Merge `compile` elements per processor, attribute by attribute.
+fn merge_compile(child: Vec<Compile>, parent: &[Compile]) -> Vec<Compile> {
+    let mut merged: Vec<Compile> = child
+        .into_iter()
+        .map(|comp| match parent.iter().find(|p| p.processor == comp.processor) {
+            Some(par) => Compile {
+                processor: comp.processor,
+                header: comp.header.or_else(|| par.header.clone()),
+                define: comp.define.or_else(|| par.define.clone()),
+            },
+            None => comp,
+        })
+        .collect();
+    let inherited: Vec<Compile> = parent
+        .iter()
+        .filter(|p| !merged.iter().any(|c| c.processor == p.processor))
+        .cloned()
+        .collect();
+    merged.extend(inherited);
+    merged
+}
+
+fn parse_variant<'dom>(e: &'dom Element, l: &Logger) -> DeviceBuilder<'dom> {
+    let mut variant = DeviceBuilder::from_elem(e);
+    for child in e.children() {
+        match child.name() {
+            "memory" => {
+                FromElem::from_elem(child, l)
+                    .ok_warn(l)
+                    .map(|mem| variant.add_memory(mem));
+            }
+            "algorithm" => {
+                FromElem::from_elem(child, l)
+                    .ok_warn(l)
+                    .map(|alg| variant.add_algorithm(alg));
+            }
+            "processor" => {
+                FromElem::from_elem(child, l)
+                    .ok_warn(l)
+                    .map(|prc| variant.add_processor(prc));
+            }
+            _ => variant.add_elem(child, l),
+        }
+    }
+    variant
 }
 
 fn parse_device<'dom>(e: &'dom Element, l: &Logger) -> Vec<DeviceBuilder<'dom>> {
     let mut device = DeviceBuilder::from_elem(e);
     let variants = e.children()
         .filter_map(|child| match child.name() {
-            "variant" => Some(DeviceBuilder::from_elem(child)),
+            "variant" => Some(parse_variant(child, l)),
             "memory" => {
                 FromElem::from_elem(child, l)
                     .ok_warn(l)
@@ -521,7 +947,10 @@ fn parse_device<'dom>(e: &'dom Element, l: &Logger) -> Vec<DeviceBuilder<'dom>>
                     .map(|prc| device.add_processor(prc));
                 None
             }
-            _ => None,
+            _ => {
+                device.add_elem(child, l);
+                None
+            }
         })
         .collect::<Vec<_>>();
     if variants.is_empty() {
@@ -557,7 +986,10 @@ fn parse_sub_family<'dom>(e: &'dom Element, l: &Logger) -> Vec<DeviceBuilder<'do
                     .map(|prc| sub_family_device.add_processor(prc));
                 Vec::new()
             }
-            _ => Vec::new(),
+            _ => {
+                sub_family_device.add_elem(child, l);
+                Vec::new()
+            }
         })
         .collect::<Vec<_>>();
     devices
@@ -590,7 +1022,10 @@ fn parse_family<'dom>(e: &Element, l: &Logger) -> Result<Vec<Device>, Error> {
                     .map(|prc| family_device.add_processor(prc));
                 Vec::new()
             }
-            _ => Vec::new(),
+            _ => {
+                family_device.add_elem(child, l);
+                Vec::new()
+            }
         })
         .collect::<Vec<_>>();
     all_devices
@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use minidom::{Element, Error};
+use slog::Logger;
+
+use utils::parse::{assert_root_name, attr_map, attr_parse, child_text, get_child_no_ns, FromElem};
+use utils::ResultLogExt;
+
+use device::NumberBool;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExampleBoard {
+    pub name: String,
+    pub vendor: String,
+    pub revision: Option<String>,
+}
+
+impl FromElem for ExampleBoard {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        Ok(ExampleBoard {
+            name: attr_map(e, "name", "board")?,
+            vendor: attr_map(e, "vendor", "board")?,
+            revision: attr_map(e, "revision", "board").ok(),
+        })
+    }
+}
+
+/// A project file of an example, for one IDE or build environment.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExampleProject {
+    pub environment: String,
+    pub load: PathBuf,
+}
+
+impl FromElem for ExampleProject {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        Ok(ExampleProject {
+            environment: attr_map(e, "name", "environment")?,
+            load: attr_map(e, "load", "environment")?,
+        })
+    }
+}
+
+/// A component an example uses, from its `attributes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExampleComponent {
+    pub vendor: Option<String>,
+    pub class: String,
+    pub group: Option<String>,
+    pub sub_group: Option<String>,
+    pub version: Option<String>,
+}
+
+impl FromElem for ExampleComponent {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        Ok(ExampleComponent {
+            vendor: attr_map(e, "Cvendor", "component").ok(),
+            class: attr_map(e, "Cclass", "component")?,
+            group: attr_map(e, "Cgroup", "component").ok(),
+            sub_group: attr_map(e, "Csub", "component").ok(),
+            version: attr_map(e, "Cversion", "component").ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Example {
+    pub name: String,
+    pub doc: PathBuf,
+    pub folder: PathBuf,
+    pub archive: Option<PathBuf>,
+    pub version: Option<String>,
+    pub public: bool,
+    pub description: String,
+    pub boards: Vec<ExampleBoard>,
+    pub projects: Vec<ExampleProject>,
+    pub components: Vec<ExampleComponent>,
+    pub categories: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+impl FromElem for Example {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "example")?;
+        let name: String = attr_map(e, "name", "example")?;
+        let l = l.new(o!("Example" => name.clone()));
+        let mut example = Example {
+            name,
+            doc: attr_map(e, "doc", "example")?,
+            folder: attr_map(e, "folder", "example")?,
+            archive: attr_map(e, "archive", "example").ok(),
+            version: attr_map(e, "version", "example").ok(),
+            public: attr_parse(e, "public", "example")
+                .map(|nb: NumberBool| nb.into())
+                .unwrap_or(true),
+            description: child_text(e, "description", "example").unwrap_or_default(),
+            boards: Vec::new(),
+            projects: Vec::new(),
+            components: Vec::new(),
+            categories: Vec::new(),
+            keywords: Vec::new(),
+        };
+        for child in e.children() {
+            match child.name() {
+                "board" => example.boards.extend(ExampleBoard::from_elem(child, &l).ok_warn(&l)),
+                "project" => {
+                    example.projects = ExampleProject::vec_from_children(child.children(), &l)
+                }
+                _ => (),
+            }
+        }
+        if let Some(attributes) = get_child_no_ns(e, "attributes") {
+            for child in attributes.children() {
+                match child.name() {
+                    "component" => example
+                        .components
+                        .extend(ExampleComponent::from_elem(child, &l).ok_warn(&l)),
+                    "category" => example.categories.push(child.text().trim().to_string()),
+                    "keyword" => example.keywords.push(child.text().trim().to_string()),
+                    _ => (),
+                }
+            }
+        }
+        Ok(example)
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use minidom::{Element, Error};
+use slog::Logger;
+
+use utils::parse::{assert_root_name, attr_map, child_text, get_child_no_ns, FromElem};
+
+use component::FileRef;
+
+/// A command to run a generator. `host` is one of `win`, `linux`, `mac`
+/// or `all`, and is `None` when the command applies to every host.
+#[derive(Debug, Clone, Serialize)]
+pub struct GeneratorCommand {
+    pub host: Option<String>,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeneratorArgument {
+    pub host: Option<String>,
+    pub switch: Option<String>,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Generator {
+    pub id: String,
+    pub vendor: Option<String>,
+    pub version: Option<String>,
+    pub description: String,
+    pub working_dir: Option<PathBuf>,
+    pub commands: Vec<GeneratorCommand>,
+    pub arguments: Vec<GeneratorArgument>,
+    pub gpdsc: Option<PathBuf>,
+    pub files: Vec<FileRef>,
+}
+
+impl FromElem for Generator {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "generator")?;
+        let id: String = attr_map(e, "id", "generator")?;
+        let l = l.new(o!("Generator" => id.clone()));
+        let commands = e.children()
+            .filter(|child| child.name() == "command")
+            .map(|child| GeneratorCommand {
+                host: attr_map(child, "host", "command").ok(),
+                path: child.text().trim().to_string(),
+            })
+            .collect();
+        let arguments = get_child_no_ns(e, "arguments")
+            .map(|args| {
+                args.children()
+                    .filter(|child| child.name() == "argument")
+                    .map(|child| GeneratorArgument {
+                        host: attr_map(child, "host", "argument").ok(),
+                        switch: attr_map(child, "switch", "argument").ok(),
+                        value: child.text().trim().to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Generator {
+            vendor: attr_map(e, "Gvendor", "generator").ok(),
+            version: attr_map(e, "Gversion", "generator").ok(),
+            description: child_text(e, "description", "generator").unwrap_or_default(),
+            working_dir: child_text(e, "workingDir", "generator").ok().map(PathBuf::from),
+            commands,
+            arguments,
+            gpdsc: get_child_no_ns(e, "gpdsc").and_then(|g| attr_map(g, "name", "gpdsc").ok()),
+            files: get_child_no_ns(e, "files")
+                .map(|files| FileRef::vec_from_children(files.children(), &l))
+                .unwrap_or_default(),
+            id,
+        })
+    }
+}
@@ -4,6 +4,7 @@ extern crate utils;
 extern crate slog;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde;
 extern crate serde_json;
 extern crate failure;
 
@@ -17,33 +18,65 @@ use std::path::Path;
 use std::collections::{HashMap, BTreeMap};
 use minidom::{Element, Error, ErrorKind};
 use slog::Logger;
+use serde::Serialize;
 
-use utils::parse::{assert_root_name, attr_map, child_text, get_child_no_ns, FromElem};
+use utils::parse::{assert_root_name, attr_map, attr_parse, child_text, get_child_no_ns, FromElem};
 use utils::ResultLogExt;
-use failure::Error as FailError;
+use failure::{err_msg, Error as FailError};
 
+mod api;
+mod board;
 mod component;
 mod condition;
+mod debug;
 mod device;
+mod example;
+mod generator;
+mod requirements;
+pub mod sequence;
+mod solver;
+mod version;
+pub use api::{Api, Taxonomy};
+pub use board::{Board, BoardDevice, BoardImage, Book, DebugInterface};
 pub use component::{ComponentBuilders, FileRef};
-pub use condition::{Condition, Conditions};
-pub use device::{Device, Devices, Memories, Algorithm, Processors};
+pub use condition::{Condition, ConditionComponent, Conditions, SelectedComponent, TargetContext};
+pub use debug::{AccessPort, DataPatch, DebugConfig, DebugPort, DebugVars, DeviceDebug, PortInterface,
+                ProcessorDebug, Sequence, SequenceElement, Trace, TraceBuffer};
+pub use device::{Compile, Core, Device, Devices, Endian, Environment, Feature, Memories, Algorithm,
+                 Processor, Processors, Security, FPU, MPU, MVE};
+pub use example::{Example, ExampleBoard, ExampleComponent, ExampleProject};
+pub use generator::{Generator, GeneratorArgument, GeneratorCommand};
+pub use requirements::{License, LicenseSet, Requirement, Requirements};
+pub use solver::{solve, ComponentRequest, Conflict, Resolution, Selected, SelectionReason};
+pub use version::{Version, VersionReq};
 
+#[derive(Debug, Clone, Serialize)]
 pub struct Release {
-    pub version: String,
+    pub version: Version,
     pub text: String,
+    pub date: Option<String>,
+    /// The date from which the release is deprecated.
+    pub deprecated: Option<String>,
+    /// The pack that replaces a deprecated pack, as `Vendor.Name`.
+    pub replacement: Option<String>,
+    pub url: Option<String>,
 }
 
 impl FromElem for Release {
     fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
         assert_root_name(e, "release")?;
         Ok(Self {
-            version: attr_map(e, "version", "release")?,
+            version: attr_parse(e, "version", "release")?,
             text: e.text(),
+            date: attr_map(e, "date", "release").ok(),
+            deprecated: attr_map(e, "deprecated", "release").ok(),
+            replacement: attr_map(e, "replacement", "release").ok(),
+            url: attr_map(e, "url", "release").ok(),
         })
     }
 }
 
+/// Releases of a pack, newest first.
 #[derive(Default)]
 pub struct Releases(Vec<Release>);
 
@@ -51,17 +84,31 @@ impl Releases {
     pub fn latest_release(&self) -> &Release {
         &self.0[0]
     }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Release> {
+        self.0.iter()
+    }
+
+    /// The newest release with a version matching `req`.
+    pub fn release_for(&self, req: &VersionReq) -> Option<&Release> {
+        self.0.iter().find(|rel| req.matches(&rel.version))
+    }
+
+    pub fn find(&self, version: &Version) -> Option<&Release> {
+        self.0.iter().find(|rel| &rel.version == version)
+    }
 }
 
 impl FromElem for Releases {
     fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
         assert_root_name(e, "releases")?;
-        let to_ret: Vec<_> = e.children()
+        let mut to_ret: Vec<_> = e.children()
             .flat_map(|c| Release::from_elem(c, l).ok_warn(l))
             .collect();
         if to_ret.len() == 0usize {
             Err(err_msg!("There must be at least one release!"))
         } else {
+            to_ret.sort_by(|a, b| b.version.cmp(&a.version));
             Ok(Releases(to_ret))
         }
     }
@@ -74,6 +121,16 @@ pub struct DumpDevice<'a> {
     memories: Cow<'a, Memories>,
     algorithms: Cow<'a, Vec<Algorithm>>,
     processor: Cow<'a, Processors>,
+    #[serde(default)]
+    debug: Cow<'a, DeviceDebug>,
+    #[serde(default)]
+    compile: Cow<'a, [Compile]>,
+    #[serde(default)]
+    environments: Cow<'a, [Environment]>,
+    #[serde(default)]
+    features: Cow<'a, [Feature]>,
+    #[serde(default)]
+    description: Option<Cow<'a, str>>,
     from_pack: FromPack<'a>,
     vendor: Option<&'a str>,
     family: &'a str,
@@ -106,6 +163,11 @@ impl<'a> DumpDevice<'a> {
             memories: Cow::Borrowed(&dev.memories),
             algorithms: Cow::Borrowed(&dev.algorithms),
             processor: Cow::Borrowed(&dev.processor),
+            debug: Cow::Borrowed(&dev.debug),
+            compile: Cow::Borrowed(&dev.compile),
+            environments: Cow::Borrowed(&dev.environments),
+            features: Cow::Borrowed(&dev.features),
+            description: dev.description.as_ref().map(|d| Cow::Borrowed(d.as_str())),
             from_pack: from_pack,
             vendor: dev.vendor.as_ref().map(String::as_str),
             family: &dev.family,
@@ -125,6 +187,13 @@ pub struct Package {
     pub conditions: Conditions,
     pub devices: Devices,
     pub boards: Vec<Board>,
+    pub examples: Vec<Example>,
+    pub generators: Vec<Generator>,
+    pub apis: Vec<Api>,
+    pub taxonomy: Vec<Taxonomy>,
+    pub requirements: Requirements,
+    pub keywords: Vec<String>,
+    pub license_sets: Vec<LicenseSet>,
 }
 
 impl FromElem for Package {
@@ -152,6 +221,28 @@ impl FromElem for Package {
         let boards = get_child_no_ns(e, "boards")
             .map(|c| Board::vec_from_children(c.children(), &l))
             .unwrap_or_default();
+        let section = |name| get_child_no_ns(e, name).map(Element::children);
+        let examples = section("examples")
+            .map(|c| Example::vec_from_children(c, &l))
+            .unwrap_or_default();
+        let generators = section("generators")
+            .map(|c| Generator::vec_from_children(c, &l))
+            .unwrap_or_default();
+        let apis = section("apis")
+            .map(|c| Api::vec_from_children(c, &l))
+            .unwrap_or_default();
+        let taxonomy = section("taxonomy")
+            .map(|c| Taxonomy::vec_from_children(c, &l))
+            .unwrap_or_default();
+        let requirements = get_child_no_ns(e, "requirements")
+            .and_then(|c| Requirements::from_elem(c, &l).ok_warn(&l))
+            .unwrap_or_default();
+        let keywords = section("keywords")
+            .map(|c| c.map(|k| k.text().trim().to_string()).collect())
+            .unwrap_or_default();
+        let license_sets = section("licenseSets")
+            .map(|c| LicenseSet::vec_from_children(c, &l))
+            .unwrap_or_default();
         Ok(Self {
             name,
             description,
@@ -163,31 +254,18 @@ impl FromElem for Package {
             conditions,
             devices,
             boards,
+            examples,
+            generators,
+            apis,
+            taxonomy,
+            requirements,
+            keywords,
+            license_sets,
         })
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Board {
-    name: String,
-    mounted_devices: Vec<String>,
-}
-
-impl FromElem for Board {
-    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
-        Ok(Self {
-            name: attr_map(e, "name", "board")?,
-            mounted_devices: e.children()
-                .flat_map(|c| match c.name() {
-                    "mountedDevice" => attr_map(c, "Dname", "mountedDevice").ok(),
-                    _ => None,
-                })
-                .collect(),
-        })
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Component {
     pub vendor: String,
     pub class: String,
@@ -205,6 +283,38 @@ pub struct Component {
     pub files: Vec<FileRef>,
 }
 
+impl Component {
+    /// The component ID, as `Vendor::Class:Group:Sub&Variant@Version`.
+    pub fn id(&self) -> String {
+        let mut id = format!("{}::{}:{}", self.vendor, self.class, self.group);
+        if let Some(ref sub) = self.sub_group {
+            id.push(':');
+            id.push_str(sub);
+        }
+        if let Some(ref variant) = self.variant {
+            id.push('&');
+            id.push_str(variant);
+        }
+        id.push('@');
+        id.push_str(&self.version);
+        id
+    }
+}
+
+impl<'a> From<&'a Component> for SelectedComponent {
+    fn from(comp: &'a Component) -> Self {
+        SelectedComponent {
+            vendor: comp.vendor.clone(),
+            class: comp.class.clone(),
+            group: comp.group.clone(),
+            sub_group: comp.sub_group.clone(),
+            variant: comp.variant.clone(),
+            version: comp.version.clone(),
+            api_version: comp.api_version.clone(),
+        }
+    }
+}
+
 type Components = Vec<Component>;
 
 impl Package {
@@ -221,7 +331,7 @@ impl Package {
                     sub_group: comp.sub_group,
                     variant: comp.variant,
                     version: comp.version.unwrap_or_else(|| {
-                        self.releases.latest_release().version.clone()
+                        self.releases.latest_release().version.to_string()
                     }),
                     api_version: comp.api_version,
                     condition: comp.condition,
@@ -236,6 +346,34 @@ impl Package {
             .collect()
     }
 
+    /// The components, and the files within them, whose conditions hold for
+    /// the target described by `ctx`.
+    pub fn make_components_for(&self, ctx: &TargetContext, l: &Logger) -> Components {
+        let lookup = self.make_condition_lookup(l);
+        let applies = |condition: &Option<String>| match *condition {
+            Some(ref id) => match lookup.get(id.as_str()) {
+                Some(cond) => cond.evaluate(ctx, &lookup),
+                None => {
+                    warn!(l, "Unknown condition '{}' treated as false", id);
+                    false
+                }
+            },
+            None => true,
+        };
+        self.make_components()
+            .into_iter()
+            .filter(|comp| applies(&comp.condition))
+            .map(|comp| {
+                let files = comp.files
+                    .iter()
+                    .filter(|file| applies(&file.condition))
+                    .cloned()
+                    .collect();
+                Component { files, ..comp }
+            })
+            .collect()
+    }
+
     pub fn make_condition_lookup<'a>(&'a self, l: &Logger) -> HashMap<&'a str, &'a Condition> {
         let mut map = HashMap::with_capacity(self.conditions.0.iter().count());
         for cond in self.conditions.0.iter() {
@@ -243,6 +381,12 @@ impl Package {
                 warn!(l, "Duplicate Condition found {}", dup.id);
             }
         }
+        for cond in self.conditions.0.iter() {
+            if let Some(id) = cond.bad_reference(&map) {
+                warn!(l, "Condition '{}' references unknown or cyclic condition '{}'; it never holds",
+                      cond.id, id);
+            }
+        }
         map
     }
 
@@ -250,7 +394,7 @@ impl Package {
         let from_pack = FromPack::new(
             &self.vendor,
             &self.name,
-            &self.releases.latest_release().version,
+            self.releases.latest_release().version.as_str(),
             &self.url,
         );
         self.devices
@@ -325,11 +469,43 @@ pub fn dump_devices<'a, P: AsRef<Path>, I: IntoIterator<Item = &'a Package>>(
                 println!("Could not open file {:?}", to_file.as_ref());
             }
         }
-        None => println!("{}", &serde_json::to_string_pretty(&devices).unwrap()),
+        None => println!("{}", &serde_json::to_string_pretty(&boards).unwrap()),
     }
     Ok(())
 }
 
+/// Find a device by name in any of the packs.
+pub fn find_device<'a, I>(pdscs: I, name: &str) -> Option<&'a Device>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    pdscs.into_iter().filter_map(|pdsc| pdsc.devices.0.get(name)).next()
+}
+
+/// Dump, as JSON, the components of all packs that apply to a device when
+/// built with a particular compiler, such as `GCC`, `ARMCC` or `IAR`.
+pub fn dumps_components_for_device<'a, I>(
+    pdscs: I,
+    device: &str,
+    compiler: &str,
+    l: &Logger,
+) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    let pdscs: Vec<&Package> = pdscs.into_iter().collect();
+    let ctx = match find_device(pdscs.iter().cloned(), device) {
+        Some(dev) => TargetContext {
+            assume_components: true,
+            ..dev.target_context(None)
+        }.with_compiler(compiler),
+        None => return Err(err_msg(format!("Device {} not found in any pack", device))),
+    };
+    let components = pdscs
+        .iter()
+        .flat_map(|pdsc| pdsc.make_components_for(&ctx, l).into_iter())
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&components)?)
+}
+
 pub fn dumps_components<'a, I>(pdscs: I) -> Result<String, FailError>
     where I: IntoIterator<Item = &'a Package>,
 {
@@ -339,3 +515,440 @@ pub fn dumps_components<'a, I>(pdscs: I) -> Result<String, FailError>
         .collect::<Vec<_>>();
     Ok(serde_json::to_string_pretty(&components)?)
 }
+
+fn dumps_section<'a, I, T, F>(pdscs: I, section: F) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+          T: Serialize + 'a,
+          F: Fn(&'a Package) -> &'a Vec<T>,
+{
+    let items = pdscs
+        .into_iter()
+        .flat_map(|pdsc| section(pdsc).iter())
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&items)?)
+}
+
+pub fn dumps_examples<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    dumps_section(pdscs, |pdsc| &pdsc.examples)
+}
+
+pub fn dumps_generators<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    dumps_section(pdscs, |pdsc| &pdsc.generators)
+}
+
+pub fn dumps_apis<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    dumps_section(pdscs, |pdsc| &pdsc.apis)
+}
+
+pub fn dumps_taxonomy<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    dumps_section(pdscs, |pdsc| &pdsc.taxonomy)
+}
+
+/// The requirements of each pack, in order.
+pub fn dumps_requirements<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    let requirements = pdscs
+        .into_iter()
+        .map(|pdsc| &pdsc.requirements)
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&requirements)?)
+}
+
+pub fn dumps_keywords<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    dumps_section(pdscs, |pdsc| &pdsc.keywords)
+}
+
+pub fn dumps_license_sets<'a, I>(pdscs: I) -> Result<String, FailError>
+    where I: IntoIterator<Item = &'a Package>,
+{
+    dumps_section(pdscs, |pdsc| &pdsc.license_sets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{Discard, Logger};
+
+    const PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"1.0.0\"/></releases>
+      <conditions>
+        <condition id=\"GCC\"><require Tcompiler=\"GCC\"/></condition>
+        <condition id=\"CM0\"><require Dcore=\"Cortex-M0+\"/></condition>
+        <condition id=\"CM4\"><require Dcore=\"Cortex-M4\"/></condition>
+      </conditions>
+      <components>
+        <component Cclass=\"Device\" Cgroup=\"Startup\" condition=\"CM0\">
+          <description>startup</description>
+          <files>
+            <file category=\"sourceAsm\" name=\"gcc/startup.S\" condition=\"GCC\"/>
+            <file category=\"sourceC\" name=\"system.c\"/>
+          </files>
+        </component>
+        <component Cclass=\"Device\" Cgroup=\"Other\" condition=\"CM4\">
+          <description>other</description>
+        </component>
+      </components>
+      <devices>
+        <family Dfamily=\"MyFamily\">
+          <processor Dcore=\"Cortex-M0+\"/>
+          <device Dname=\"MyDevice\"/>
+        </family>
+      </devices>
+    </package>";
+
+    #[test]
+    fn components_for_device() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(PDSC, &log).unwrap();
+        let device = find_device(Some(&pack), "MyDevice").unwrap();
+        let ctx = device.target_context(None).with_compiler("GCC");
+        let comps = pack.make_components_for(&ctx, &log);
+        assert_eq!(comps.len(), 1);
+        assert_eq!(comps[0].group, "Startup");
+        assert_eq!(comps[0].files.len(), 2);
+        let ctx = device.target_context(None).with_compiler("IAR");
+        let comps = pack.make_components_for(&ctx, &log);
+        assert_eq!(comps[0].files.len(), 1);
+        assert!(dumps_components_for_device(Some(&pack), "NoDevice", "GCC", &log).is_err());
+    }
+
+    const PROCESSOR_PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"1.0.0\"/></releases>
+      <devices>
+        <family Dfamily=\"MyFamily\">
+          <processor Dcore=\"Cortex-M55\" Dfpu=\"DP_FPU\" Dmve=\"FP_MVE\" Dtz=\"TZ\" Ddsp=\"DSP\"
+                     Dendian=\"Little-endian\" Dclock=\"160000000\"/>
+          <device Dname=\"Single\">
+            <processor Dsecure=\"Secure\" Dcdecp=\"0x3\"/>
+          </device>
+          <device Dname=\"Future\">
+            <processor Dcore=\"Cortex-M99\"/>
+          </device>
+        </family>
+      </devices>
+    </package>";
+
+    #[test]
+    fn processor_attributes() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(PROCESSOR_PDSC, &log).unwrap();
+        let single = find_device(Some(&pack), "Single").unwrap();
+        match single.processor {
+            Processors::Symmetric(ref prc) => {
+                assert_eq!(prc.core, Core::CortexM55);
+                assert_eq!(prc.mve, MVE::FloatingPoint);
+                assert!(prc.trustzone && prc.dsp);
+                assert_eq!(prc.endian, Some(Endian::Little));
+                assert_eq!(prc.secure, Some(Security::Secure));
+                assert_eq!(prc.cdecp, Some(3));
+                assert_eq!(prc.clock, Some(160_000_000));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        let ctx = single.target_context(None);
+        assert_eq!(ctx.mve, Some("FP_MVE".to_string()));
+        assert_eq!(ctx.trustzone, Some("TZ".to_string()));
+        let future = find_device(Some(&pack), "Future").unwrap();
+        match future.processor {
+            Processors::Symmetric(ref prc) => {
+                assert_eq!(prc.core, Core::Other("Cortex-M99".to_string()))
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    /// Check that every value prints as the name it is parsed from, and
+    /// survives a trip through JSON.
+    fn round_trip<T>(values: &[T])
+    where
+        T: ::std::fmt::Display + ::std::str::FromStr + PartialEq + ::std::fmt::Debug
+            + ::serde::Serialize + for<'de> ::serde::Deserialize<'de>,
+        <T as ::std::str::FromStr>::Err: ::std::fmt::Debug,
+    {
+        for value in values {
+            assert_eq!(&value.to_string().parse::<T>().unwrap(), value);
+            let json = serde_json::to_string(value).unwrap();
+            assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn processor_names_round_trip() {
+        let mut cores: Vec<Core> = device::CORES.iter().map(|entry| entry.0.clone()).collect();
+        cores.push(Core::Other("Cortex-M99".to_string()));
+        round_trip(&cores);
+        for &(ref core, name) in device::CORES {
+            assert_eq!(core.to_string(), name);
+        }
+        assert_eq!(serde_json::to_string(&Core::CortexM4).unwrap(), "\"Cortex-M4\"");
+        assert_eq!(serde_json::to_string(&cores[cores.len() - 1]).unwrap(), "\"Cortex-M99\"");
+        round_trip(&[FPU::None, FPU::SinglePrecision, FPU::DoublePrecision]);
+        round_trip(&[MPU::NotPresent, MPU::Present]);
+        round_trip(&[Endian::Little, Endian::Big, Endian::Configurable]);
+        round_trip(&[MVE::None, MVE::Integer, MVE::FloatingPoint]);
+        round_trip(&[Security::Secure, Security::NonSecure, Security::TrustZoneDisabled]);
+    }
+
+    const FEATURE_PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"1.0.0\"/></releases>
+      <devices>
+        <family Dfamily=\"STM32F4\">
+          <processor Dcore=\"Cortex-M4\"/>
+          <description>The family</description>
+          <feature type=\"USBD\" n=\"1\"/>
+          <feature type=\"Timer\" n=\"4\" m=\"16\"/>
+          <feature type=\"Timer\" n=\"2\" m=\"32\"/>
+          <environment name=\"uv\"><CMisc>--C99</CMisc></environment>
+          <subFamily DsubFamily=\"STM32F407\">
+            <compile header=\"Include/stm32f4xx.h\"/>
+            <feature type=\"CAN\" n=\"2\"/>
+            <device Dname=\"STM32F407VG\">
+              <compile define=\"STM32F407xx\"/>
+              <feature type=\"IOs\" n=\"82\"/>
+              <feature type=\"Timer\" n=\"1\" m=\"32\" name=\"Low-power\"/>
+              <feature type=\"Timer\" n=\"2\" m=\"32\"/>
+              <variant Dvariant=\"STM32F407VGTx\">
+                <feature type=\"QFP\" n=\"100\" name=\"LQFP\"/>
+                <description>The variant</description>
+              </variant>
+            </device>
+          </subFamily>
+        </family>
+      </devices>
+    </package>";
+
+    #[test]
+    fn device_features_inherit() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(FEATURE_PDSC, &log).unwrap();
+        let device = find_device(Some(&pack), "STM32F407VGTx").unwrap();
+        assert_eq!(device.compile.len(), 1);
+        assert_eq!(device.compile[0].header, Some("Include/stm32f4xx.h".into()));
+        assert_eq!(device.compile[0].define, Some("STM32F407xx".to_string()));
+        let mut kinds: Vec<_> = device.features.iter().map(|f| f.kind.as_str()).collect();
+        kinds.sort();
+        assert_eq!(kinds, vec!["CAN", "IOs", "QFP", "Timer", "Timer", "Timer", "USBD"]);
+        let mut timers: Vec<_> = device
+            .features
+            .iter()
+            .filter(|f| f.kind == "Timer")
+            .map(|f| (f.n, f.m, f.name.clone()))
+            .collect();
+        timers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(timers, vec![
+            (Some(1.0), Some(32.0), Some("Low-power".to_string())),
+            (Some(2.0), Some(32.0), None),
+            (Some(4.0), Some(16.0), None),
+        ]);
+        assert_eq!(device.description, Some("The variant".to_string()));
+        assert_eq!(device.environments[0].settings["CMisc"], "--C99");
+    }
+
+    #[test]
+    fn boards() {
+        let log = Logger::root(Discard, o!());
+        let xml = "<board xmlns=\"\" vendor=\"Keil\" name=\"MCBSTM32F400\" revision=\"Ver 1.2\">
+          <description>Keil MCBSTM32F400 Development Board</description>
+          <image small=\"Images/small.png\" large=\"Images/large.png\"/>
+          <book category=\"manual\" name=\"Docs/manual.pdf\" title=\"User Manual\"/>
+          <mountedDevice deviceIndex=\"0\" Dvendor=\"STMicroelectronics:13\" Dname=\"STM32F407IG\"/>
+          <compatibleDevice deviceIndex=\"0\" Dvendor=\"STMicroelectronics:13\" Dname=\"STM32F417IG\"/>
+          <debugInterface adapter=\"JTAG/SW\" connector=\"20 pin JTAG\"/>
+          <feature type=\"ODbg\" n=\"1\" name=\"On-board ST-LINK\"/>
+        </board>";
+        let board = Board::from_string(xml, &log).unwrap();
+        assert_eq!(board.mounted_devices, vec!["STM32F407IG".to_string()]);
+        assert_eq!(board.vendor, Some("Keil".to_string()));
+        assert_eq!(board.mounted[0].vendor, Some("STMicroelectronics:13".to_string()));
+        assert!(board.supports_device("STM32F417IG"));
+        assert_eq!(board.debug_interfaces[0].adapter, "JTAG/SW");
+        let old: Board =
+            serde_json::from_str("{\"name\": \"Old\", \"mounted_devices\": [\"Dev\"]}").unwrap();
+        assert_eq!(old.mounted_devices, vec!["Dev".to_string()]);
+        let json = serde_json::to_value(&board).unwrap();
+        assert_eq!(json["name"], "MCBSTM32F400");
+    }
+
+    const SECTIONS_PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"1.0.0\"/></releases>
+      <keywords><keyword>RTOS</keyword><keyword>Kernel</keyword></keywords>
+      <requirements>
+        <packages><package vendor=\"ARM\" name=\"CMSIS\" version=\"5.0.0:6.0.0\"/></packages>
+        <languages><language name=\"C\" version=\"99\"/></languages>
+      </requirements>
+      <licenseSets>
+        <licenseSet id=\"all\" default=\"true\" gating=\"true\">
+          <license name=\"LICENSE.txt\" title=\"Apache 2.0\" spdx=\"Apache-2.0\"/>
+        </licenseSet>
+      </licenseSets>
+      <taxonomy>
+        <description Cclass=\"RTOS\" doc=\"doc/rtos.html\">Real-time Operating System</description>
+      </taxonomy>
+      <apis>
+        <api Cclass=\"RTOS\" Cgroup=\"Kernel\" Capiversion=\"2.1.0\" exclusive=\"1\">
+          <description>RTOS API</description>
+          <files><file category=\"header\" name=\"Include/cmsis_os2.h\"/></files>
+        </api>
+      </apis>
+      <generators>
+        <generator id=\"CubeMX\" Gvendor=\"ST\">
+          <description>STM32CubeMX</description>
+          <command host=\"win\">STM32CubeMX.exe</command>
+          <arguments><argument host=\"win\" switch=\"-s\">$P</argument></arguments>
+          <gpdsc name=\"$PCubeMX/gen.gpdsc\"/>
+        </generator>
+      </generators>
+      <examples>
+        <example name=\"Blinky\" doc=\"Abstract.txt\" folder=\"Blinky\">
+          <description>Blinky example</description>
+          <board name=\"MCB\" vendor=\"Keil\"/>
+          <project><environment name=\"uv\" load=\"Blinky.uvprojx\"/></project>
+          <attributes>
+            <component Cclass=\"RTOS\" Cgroup=\"Kernel\"/>
+            <category>Getting Started</category>
+            <keyword>LED</keyword>
+          </attributes>
+        </example>
+      </examples>
+    </package>";
+
+    #[test]
+    fn package_sections() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(SECTIONS_PDSC, &log).unwrap();
+        assert_eq!(pack.keywords, vec!["RTOS".to_string(), "Kernel".to_string()]);
+        assert_eq!(pack.requirements.packages[0].version, Some("5.0.0:6.0.0".to_string()));
+        assert_eq!(pack.requirements.languages[0].name, "C");
+        assert!(pack.license_sets[0].gating);
+        assert_eq!(pack.license_sets[0].licenses[0].spdx, Some("Apache-2.0".to_string()));
+        assert_eq!(pack.taxonomy[0].description, "Real-time Operating System");
+        assert!(pack.apis[0].exclusive);
+        assert_eq!(pack.apis[0].files.len(), 1);
+        assert_eq!(pack.generators[0].commands[0].path, "STM32CubeMX.exe");
+        assert_eq!(pack.generators[0].arguments[0].switch, Some("-s".to_string()));
+        let example = &pack.examples[0];
+        assert_eq!(example.projects[0].environment, "uv");
+        assert_eq!(example.components[0].class, "RTOS");
+        assert_eq!(example.categories, vec!["Getting Started".to_string()]);
+        let dumped: serde_json::Value =
+            serde_json::from_str(&dumps_examples(Some(&pack)).unwrap()).unwrap();
+        assert_eq!(dumped[0]["name"], "Blinky");
+
+        let parse = |dumped: String| serde_json::from_str::<serde_json::Value>(&dumped).unwrap();
+        let requirements = parse(dumps_requirements(Some(&pack)).unwrap());
+        assert_eq!(requirements[0]["packages"][0]["name"], "CMSIS");
+        assert_eq!(requirements[0]["languages"][0]["version"], "99");
+        let keywords = parse(dumps_keywords(Some(&pack)).unwrap());
+        assert_eq!(keywords[0], "RTOS");
+        assert_eq!(keywords[1], "Kernel");
+        let license_sets = parse(dumps_license_sets(Some(&pack)).unwrap());
+        assert_eq!(license_sets[0]["id"], "all");
+        assert_eq!(license_sets[0]["licenses"][0]["spdx"], "Apache-2.0");
+    }
+
+    #[test]
+    fn releases_sorted_by_version() {
+        let log = Logger::root(Discard, o!());
+        let xml = "<releases xmlns=\"\">
+          <release version=\"1.2.0\" date=\"2018-01-01\">Old</release>
+          <release version=\"1.10.0\" date=\"2019-01-01\" deprecated=\"2020-01-01\"
+                   replacement=\"Vendor.Other\">Newest</release>
+          <release version=\"1.9.0-rc1\" url=\"http://example.com/\">RC</release>
+        </releases>";
+        let releases = Releases::from_string(xml, &log).unwrap();
+        assert_eq!(releases.latest_release().version.as_str(), "1.10.0");
+        assert_eq!(releases.latest_release().replacement, Some("Vendor.Other".to_string()));
+        let req: VersionReq = "<1.10".parse().unwrap();
+        assert_eq!(releases.release_for(&req).unwrap().text, "RC");
+        let req: VersionReq = "~1.2".parse().unwrap();
+        assert_eq!(releases.release_for(&req).unwrap().date, Some("2018-01-01".to_string()));
+        assert!(releases.release_for(&"2.0.0".parse().unwrap()).is_none());
+    }
+
+    const DEBUG_PDSC: &'static str = "<package>
+      <vendor>MyVendor</vendor>
+      <name>MyPack</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"1.0.0\"/></releases>
+      <devices>
+        <family Dfamily=\"MyFamily\">
+          <processor Dcore=\"Cortex-M4\"/>
+          <debugconfig default=\"swd\" clock=\"10000000\"/>
+          <debugport __dp=\"0\"><swd idcode=\"0x2BA01477\"/></debugport>
+          <debug svd=\"family.svd\"/>
+          <sequences>
+            <sequence name=\"ResetSystem\">
+              <block>Write32(0xE000ED0C, 0x05FA0004);</block>
+            </sequence>
+            <sequence name=\"DebugPortStart\">
+              <control if=\"__protocol == 1\" while=\"0\">
+                <block atomic=\"1\">__var x = 0;</block>
+              </control>
+            </sequence>
+          </sequences>
+          <device Dname=\"MyDevice\">
+            <debug svd=\"device.svd\"/>
+            <sequences>
+              <sequence name=\"ResetSystem\" disable=\"1\"/>
+            </sequences>
+          </device>
+        </family>
+      </devices>
+    </package>";
+
+    #[test]
+    fn device_debug_inherits() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(DEBUG_PDSC, &log).unwrap();
+        let device = find_device(Some(&pack), "MyDevice").unwrap();
+        let debug = &device.debug;
+        assert_eq!(debug.config.default, Some("swd".to_string()));
+        assert_eq!(debug.config.clock, Some(10_000_000));
+        assert_eq!(debug.debug_ports.len(), 1);
+        assert_eq!(
+            debug.debug_ports[0].swd.as_ref().and_then(|s| s.idcode),
+            Some(0x2BA0_1477)
+        );
+        assert_eq!(debug.debugs.len(), 1);
+        assert_eq!(debug.debugs[0].svd, Some("device.svd".into()));
+        assert_eq!(debug.sequences.len(), 2);
+        let reset = debug.sequences.iter().find(|s| s.name == "ResetSystem").unwrap();
+        assert!(reset.disable);
+        let start = debug.sequences.iter().find(|s| s.name == "DebugPortStart").unwrap();
+        match start.body[0] {
+            SequenceElement::Control { ref condition, ref body, .. } => {
+                assert_eq!(condition.as_ref().map(String::as_str), Some("__protocol == 1"));
+                assert_eq!(body.len(), 1);
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use minidom::{Element, Error, ErrorKind};
+use slog::Logger;
+
+use utils::parse::{assert_root_name, attr_map, attr_parse, get_child_no_ns, FromElem};
+use utils::ResultLogExt;
+
+use device::NumberBool;
+
+/// A pack, compiler or language a pack requires. `version` is a version
+/// or a `min:max` range.
+#[derive(Debug, Clone, Serialize)]
+pub struct Requirement {
+    pub vendor: Option<String>,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl FromElem for Requirement {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        let elemname = match e.name() {
+            "package" => "package",
+            "compiler" => "compiler",
+            "language" => "language",
+            other => return Err(err_msg!("{} is not a requirement", other)),
+        };
+        Ok(Requirement {
+            vendor: attr_map(e, "vendor", elemname).ok(),
+            name: attr_map(e, "name", elemname)?,
+            version: attr_map(e, "version", elemname).ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Requirements {
+    pub packages: Vec<Requirement>,
+    pub compilers: Vec<Requirement>,
+    pub languages: Vec<Requirement>,
+}
+
+impl FromElem for Requirements {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "requirements")?;
+        let section = |name: &str| {
+            get_child_no_ns(e, name)
+                .map(|sec| Requirement::vec_from_children(sec.children(), l))
+                .unwrap_or_default()
+        };
+        Ok(Requirements {
+            packages: section("packages"),
+            compilers: section("compilers"),
+            languages: section("languages"),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct License {
+    pub name: String,
+    pub title: Option<String>,
+    pub spdx: Option<String>,
+}
+
+impl FromElem for License {
+    fn from_elem(e: &Element, _: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "license")?;
+        Ok(License {
+            name: attr_map(e, "name", "license")?,
+            title: attr_map(e, "title", "license").ok(),
+            spdx: attr_map(e, "spdx", "license").ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LicenseSet {
+    pub id: String,
+    pub default: bool,
+    /// The licenses must be accepted before the pack is used.
+    pub gating: bool,
+    pub licenses: Vec<License>,
+}
+
+impl FromElem for LicenseSet {
+    fn from_elem(e: &Element, l: &Logger) -> Result<Self, Error> {
+        assert_root_name(e, "licenseSet")?;
+        let flag = |name| {
+            attr_parse(e, name, "licenseSet")
+                .map(|nb: NumberBool| nb.into())
+                .unwrap_or(false)
+        };
+        Ok(LicenseSet {
+            id: attr_map(e, "id", "licenseSet")?,
+            default: flag("default"),
+            gating: flag("gating"),
+            licenses: e.children()
+                .filter(|child| child.name() == "license")
+                .flat_map(|child| License::from_elem(child, l).ok_warn(l))
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,1265 @@
+//! Parsing and execution of CMSIS debug access sequences.
+//!
+//! The code of every `<block>` and the expressions of every `<control>` in a
+//! `<sequence>` are parsed into a small AST, which an `Interpreter` then runs
+//! against an implementation of `DebugAccess`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::{err_msg, Error, Fail};
+
+use debug::{DeviceDebug, Sequence, SequenceElement};
+
+macro_rules! seq_err {
+    ($($arg:tt)*) => {
+        err_msg(format!($($arg)*))
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn from_token(tok: &str) -> Option<(Self, u8)> {
+        use self::BinaryOp::*;
+        Some(match tok {
+            "*" => (Mul, 10),
+            "/" => (Div, 10),
+            "%" => (Rem, 10),
+            "+" => (Add, 9),
+            "-" => (Sub, 9),
+            "<<" => (Shl, 8),
+            ">>" => (Shr, 8),
+            "<" => (Lt, 7),
+            "<=" => (Le, 7),
+            ">" => (Gt, 7),
+            ">=" => (Ge, 7),
+            "==" => (Eq, 6),
+            "!=" => (Ne, 6),
+            "&" => (BitAnd, 5),
+            "^" => (BitXor, 4),
+            "|" => (BitOr, 3),
+            "&&" => (And, 2),
+            "||" => (Or, 1),
+            _ => return None,
+        })
+    }
+
+    fn from_assign(tok: &str) -> Option<Option<Self>> {
+        use self::BinaryOp::*;
+        Some(match tok {
+            "=" => None,
+            "*=" => Some(Mul),
+            "/=" => Some(Div),
+            "%=" => Some(Rem),
+            "+=" => Some(Add),
+            "-=" => Some(Sub),
+            "<<=" => Some(Shl),
+            ">>=" => Some(Shr),
+            "&=" => Some(BitAnd),
+            "^=" => Some(BitXor),
+            "|=" => Some(BitOr),
+            _ => return None,
+        })
+    }
+
+    fn apply(self, lhs: u64, rhs: u64) -> Result<u64, Error> {
+        use self::BinaryOp::*;
+        Ok(match self {
+            Mul => lhs.wrapping_mul(rhs),
+            Div => lhs.checked_div(rhs).ok_or_else(|| err_msg("division by zero"))?,
+            Rem => lhs.checked_rem(rhs).ok_or_else(|| err_msg("division by zero"))?,
+            Add => lhs.wrapping_add(rhs),
+            Sub => lhs.wrapping_sub(rhs),
+            Shl => if rhs < 64 { lhs << rhs } else { 0 },
+            Shr => if rhs < 64 { lhs >> rhs } else { 0 },
+            Lt => (lhs < rhs) as u64,
+            Le => (lhs <= rhs) as u64,
+            Gt => (lhs > rhs) as u64,
+            Ge => (lhs >= rhs) as u64,
+            Eq => (lhs == rhs) as u64,
+            Ne => (lhs != rhs) as u64,
+            BitAnd => lhs & rhs,
+            BitXor => lhs ^ rhs,
+            BitOr => lhs | rhs,
+            And => (lhs != 0 && rhs != 0) as u64,
+            Or => (lhs != 0 || rhs != 0) as u64,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(u64),
+    Str(String),
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Assignment to a variable; the operator is set for compound assignments.
+    Assign(String, Option<BinaryOp>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    /// `__var name = value;`
+    Declare(String, Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Block {
+        atomic: bool,
+        body: Vec<Statement>,
+    },
+    Control {
+        condition: Option<Expr>,
+        repeat_while: Option<Expr>,
+        /// Timeout of the `while` loop in microseconds.
+        timeout: Option<u64>,
+        body: Vec<Node>,
+    },
+}
+
+/// A parsed `<sequence>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub name: String,
+    pub processor: Option<String>,
+    pub disable: bool,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Ident(String),
+    Str(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(ref s) => write!(f, "{}", s),
+            Token::Str(ref s) => write!(f, "\"{}\"", s),
+            Token::Punct(p) => write!(f, "{}", p),
+        }
+    }
+}
+
+const PUNCTUATION: &[&str] = &[
+    "<<=", ">>=", "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "+=", "-=", "*=", "/=", "%=",
+    "&=", "|=", "^=", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "=", "(", ")",
+    ",", ";", "?", ":",
+];
+
+fn tokenize(code: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = code;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(tokens);
+        }
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map(|i| &rest[i..]).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("/*") {
+            let end = rest.find("*/").ok_or_else(|| err_msg("unterminated comment"))?;
+            rest = &rest[end + 2..];
+            continue;
+        }
+        let first = rest.chars().next().unwrap_or_default();
+        if first.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let literal = rest[..len].trim_end_matches(&['u', 'U', 'l', 'L'][..]);
+            let number = if literal.starts_with("0x") || literal.starts_with("0X") {
+                u64::from_str_radix(&literal[2..], 16)
+            } else {
+                literal.parse()
+            };
+            tokens.push(Token::Number(number.map_err(|_| seq_err!("invalid number {}", &rest[..len]))?));
+            rest = &rest[len..];
+        } else if first.is_ascii_alphabetic() || first == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if first == '"' {
+            let mut string = String::new();
+            let mut chars = rest.char_indices().skip(1);
+            let mut end = None;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = Some(i + 1);
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, 't')) => string.push('\t'),
+                        Some((_, other)) => string.push(other),
+                        None => break,
+                    },
+                    other => string.push(other),
+                }
+            }
+            let end = end.ok_or_else(|| err_msg("unterminated string literal"))?;
+            tokens.push(Token::Str(string));
+            rest = &rest[end..];
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| rest.starts_with(*p))
+                .ok_or_else(|| seq_err!("unexpected character '{}'", first))?;
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        }
+    }
+}
+
+struct Parser {
+    tokens: VecDeque<Token>,
+}
+
+impl Parser {
+    fn new(code: &str) -> Result<Self, Error> {
+        Ok(Parser {
+            tokens: tokenize(code)?.into_iter().collect(),
+        })
+    }
+
+    fn peek_punct(&self) -> Option<&'static str> {
+        match self.tokens.front() {
+            Some(&Token::Punct(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.peek_punct() == Some(punct) {
+            self.tokens.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), Error> {
+        match self.tokens.pop_front() {
+            Some(Token::Punct(p)) if p == punct => Ok(()),
+            Some(other) => Err(seq_err!("expected '{}', found '{}'", punct, other)),
+            None => Err(seq_err!("expected '{}', found end of input", punct)),
+        }
+    }
+
+    fn statements(&mut self) -> Result<Vec<Statement>, Error> {
+        let mut stmts = Vec::new();
+        while !self.tokens.is_empty() {
+            if self.eat(";") {
+                continue;
+            }
+            if self.tokens.front() == Some(&Token::Ident("__var".to_string())) {
+                self.tokens.pop_front();
+                let name = match self.tokens.pop_front() {
+                    Some(Token::Ident(name)) => name,
+                    _ => return Err(err_msg("expected a variable name after __var")),
+                };
+                let value = if self.eat("=") {
+                    self.expr()?
+                } else {
+                    Expr::Number(0)
+                };
+                stmts.push(Statement::Declare(name, value));
+            } else {
+                stmts.push(Statement::Expr(self.expr()?));
+            }
+            self.expect(";")?;
+        }
+        Ok(stmts)
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        let lhs = self.ternary()?;
+        let op = match self.peek_punct().and_then(BinaryOp::from_assign) {
+            Some(op) => op,
+            None => return Ok(lhs),
+        };
+        self.tokens.pop_front();
+        match lhs {
+            Expr::Var(name) => Ok(Expr::Assign(name, op, Box::new(self.expr()?))),
+            other => Err(seq_err!("cannot assign to {:?}", other)),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<Expr, Error> {
+        let cond = self.binary(1)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+        let then = self.expr()?;
+        self.expect(":")?;
+        let otherwise = self.ternary()?;
+        Ok(Expr::Ternary(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, min_prec: u8) -> Result<Expr, Error> {
+        let mut lhs = self.unary()?;
+        while let Some((op, prec)) = self.peek_punct().and_then(BinaryOp::from_token) {
+            if prec < min_prec {
+                break;
+            }
+            self.tokens.pop_front();
+            let rhs = self.binary(prec + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        let op = match self.peek_punct() {
+            Some("-") => UnaryOp::Neg,
+            Some("!") => UnaryOp::Not,
+            Some("~") => UnaryOp::BitNot,
+            Some("+") => {
+                self.tokens.pop_front();
+                return self.unary();
+            }
+            _ => return self.primary(),
+        };
+        self.tokens.pop_front();
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        match self.tokens.pop_front() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::Ident(name)) => {
+                if !self.eat("(") {
+                    return Ok(Expr::Var(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::Punct("(")) => {
+                let inner = self.expr()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(other) => Err(seq_err!("unexpected '{}'", other)),
+            None => Err(err_msg("unexpected end of input")),
+        }
+    }
+}
+
+/// Parse the statements of a `<block>` or `<debugvars>` element.
+pub fn parse_statements(code: &str) -> Result<Vec<Statement>, Error> {
+    Parser::new(code)?.statements()
+}
+
+/// Parse a single expression, such as the `if` or `while` of a `<control>`.
+pub fn parse_expr(code: &str) -> Result<Expr, Error> {
+    let mut parser = Parser::new(code)?;
+    let expr = parser.expr()?;
+    match parser.tokens.pop_front() {
+        None => Ok(expr),
+        Some(tok) => Err(seq_err!("unexpected '{}' after expression", tok)),
+    }
+}
+
+fn parse_elements(elems: &[SequenceElement]) -> Result<Vec<Node>, Error> {
+    elems
+        .iter()
+        .map(|elem| match *elem {
+            SequenceElement::Block { atomic, ref code, .. } => Ok(Node::Block {
+                atomic,
+                body: parse_statements(code)?,
+            }),
+            SequenceElement::Control {
+                ref condition,
+                ref repeat_while,
+                timeout,
+                ref body,
+                ..
+            } => Ok(Node::Control {
+                condition: match *condition {
+                    Some(ref c) => Some(parse_expr(c)?),
+                    None => None,
+                },
+                repeat_while: match *repeat_while {
+                    Some(ref w) => Some(parse_expr(w)?),
+                    None => None,
+                },
+                timeout,
+                body: parse_elements(body)?,
+            }),
+        })
+        .collect()
+}
+
+impl Program {
+    pub fn from_sequence(seq: &Sequence) -> Result<Self, Error> {
+        let body = parse_elements(&seq.body)
+            .map_err(|e| seq_err!("in sequence {}: {}", seq.name, e))?;
+        Ok(Program {
+            name: seq.name.clone(),
+            processor: seq.processor.clone(),
+            disable: seq.disable,
+            body,
+        })
+    }
+}
+
+/// The operations that debug access sequences may perform on a target.
+///
+/// Only the memory and DP/AP register accesses are required; the `DAP_*`
+/// functions fail unless an implementation supports them.
+pub trait DebugAccess {
+    fn read_memory(&mut self, ap: u64, address: u64, bits: u8) -> Result<u64, Error>;
+    fn write_memory(&mut self, ap: u64, address: u64, bits: u8, value: u64) -> Result<(), Error>;
+    fn read_dp(&mut self, dp: u64, address: u64) -> Result<u64, Error>;
+    fn write_dp(&mut self, dp: u64, address: u64, value: u64) -> Result<(), Error>;
+    fn read_ap(&mut self, ap: u64, address: u64) -> Result<u64, Error>;
+    fn write_ap(&mut self, ap: u64, address: u64, value: u64) -> Result<(), Error>;
+
+    fn delay(&mut self, micros: u64) -> Result<(), Error> {
+        thread::sleep(Duration::from_micros(micros));
+        Ok(())
+    }
+
+    fn write_abort(&mut self, _dp: u64, _value: u64) -> Result<(), Error> {
+        Err(err_msg("DAP_WriteABORT is not supported"))
+    }
+
+    fn swj_pins(&mut self, _out: u64, _select: u64, _wait: u64) -> Result<u64, Error> {
+        Err(err_msg("DAP_SWJ_Pins is not supported"))
+    }
+
+    fn swj_clock(&mut self, _hz: u64) -> Result<(), Error> {
+        Err(err_msg("DAP_SWJ_Clock is not supported"))
+    }
+
+    fn swj_sequence(&mut self, _count: u64, _value: u64) -> Result<(), Error> {
+        Err(err_msg("DAP_SWJ_Sequence is not supported"))
+    }
+
+    fn jtag_sequence(&mut self, _count: u64, _tms: u64, _tdi: u64) -> Result<u64, Error> {
+        Err(err_msg("DAP_JTAG_Sequence is not supported"))
+    }
+
+    fn message(&mut self, _kind: u64, _text: &str) {}
+
+    /// Answer a `Query` or `QueryValue`; the default answers with `default`.
+    fn query(&mut self, _kind: u64, _text: &str, default: u64) -> u64 {
+        default
+    }
+}
+
+/// Variables that are defined before any sequence runs.
+pub const PREDEFINED_VARIABLES: &[&str] = &[
+    "__protocol",
+    "__connection",
+    "__dp",
+    "__ap",
+    "__apid",
+    "__traceout",
+    "__errorcontrol",
+    "__FlashOp",
+    "__FlashAddr",
+    "__FlashLen",
+    "__FlashArg",
+    "__Result",
+];
+
+const MAX_CALL_DEPTH: usize = 32;
+
+fn for_processor(processor: &Option<String>, pname: Option<&str>) -> bool {
+    match *processor {
+        Some(ref p) => Some(p.as_str()) == pname,
+        None => true,
+    }
+}
+
+/// The condition of a `<control while=...>` did not clear within its
+/// `timeout`, which aborts the sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceTimeout {
+    /// The sequence holding the loop.
+    pub sequence: String,
+    /// The timeout, in microseconds.
+    pub timeout: u64,
+}
+
+impl fmt::Display for SequenceTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sequence {} timed out after {}us", self.sequence, self.timeout)
+    }
+}
+
+impl Fail for SequenceTimeout {}
+
+/// Runs the sequences of a device against a `DebugAccess`.
+pub struct Interpreter<'a, A: DebugAccess + 'a> {
+    access: &'a mut A,
+    programs: Vec<Program>,
+    processor: Option<String>,
+    globals: HashMap<String, u64>,
+    frames: Vec<HashMap<String, u64>>,
+}
+
+impl<'a, A: DebugAccess + 'a> Interpreter<'a, A> {
+    pub fn new(access: &'a mut A, programs: Vec<Program>) -> Self {
+        Interpreter {
+            access,
+            programs,
+            processor: None,
+            globals: PREDEFINED_VARIABLES
+                .iter()
+                .map(|name| (name.to_string(), 0))
+                .collect(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Build an interpreter from the sequences and debug variables of a
+    /// device, as seen from the processor `pname`.
+    pub fn for_device(
+        access: &'a mut A,
+        debug: &DeviceDebug,
+        pname: Option<&str>,
+    ) -> Result<Self, Error> {
+        let programs = debug
+            .sequences
+            .iter()
+            .filter(|s| for_processor(&s.processor, pname))
+            .map(Program::from_sequence)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut interp = Interpreter::new(access, programs);
+        interp.processor = pname.map(str::to_string);
+        let vars = debug
+            .debug_vars
+            .iter()
+            .filter(|v| for_processor(&v.processor, pname));
+        for vars in vars {
+            let stmts = parse_statements(&vars.code)?;
+            interp.exec_statements(&stmts)?;
+        }
+        Ok(interp)
+    }
+
+    pub fn var(&self, name: &str) -> Option<u64> {
+        self.frames
+            .last()
+            .and_then(|frame| frame.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+    }
+
+    /// Set a global variable, such as `__protocol` or `__dp`.
+    pub fn set_var(&mut self, name: &str, value: u64) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    fn program(&self, name: &str) -> Option<&Program> {
+        let mut matching = self.programs.iter().filter(|p| p.name == name);
+        let processor = &self.processor;
+        matching
+            .clone()
+            .find(|p| p.processor.is_some() && &p.processor == processor)
+            .or_else(|| matching.find(|p| p.processor.is_none()))
+    }
+
+    pub fn has_sequence(&self, name: &str) -> bool {
+        self.program(name).is_some()
+    }
+
+    /// Run the sequence `name`. Disabled sequences do nothing.
+    pub fn run(&mut self, name: &str) -> Result<(), Error> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(seq_err!("sequence {} nested too deeply", name));
+        }
+        let program = self
+            .program(name)
+            .cloned()
+            .ok_or_else(|| seq_err!("sequence {} is not defined", name))?;
+        if program.disable {
+            return Ok(());
+        }
+        self.frames.push(HashMap::new());
+        let res = self.exec_nodes(&program.body);
+        self.frames.pop();
+        res.map_err(|e| match e.downcast::<SequenceTimeout>() {
+            Ok(mut timeout) => {
+                if timeout.sequence.is_empty() {
+                    timeout.sequence = name.to_string();
+                }
+                timeout.into()
+            }
+            Err(e) => seq_err!("in sequence {}: {}", name, e),
+        })
+    }
+
+    fn exec_nodes(&mut self, nodes: &[Node]) -> Result<(), Error> {
+        for node in nodes {
+            match *node {
+                Node::Block { ref body, .. } => self.exec_statements(body)?,
+                Node::Control {
+                    ref condition,
+                    ref repeat_while,
+                    timeout,
+                    ref body,
+                } => {
+                    if let Some(ref cond) = *condition {
+                        if self.eval(cond)? == 0 {
+                            continue;
+                        }
+                    }
+                    match *repeat_while {
+                        Some(ref cond) => {
+                            let start = Instant::now();
+                            while self.eval(cond)? != 0 {
+                                self.exec_nodes(body)?;
+                                if let Some(timeout) = timeout {
+                                    if start.elapsed() >= Duration::from_micros(timeout) {
+                                        return Err(SequenceTimeout {
+                                            sequence: String::new(),
+                                            timeout,
+                                        }.into());
+                                    }
+                                }
+                            }
+                        }
+                        None => self.exec_nodes(body)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_statements(&mut self, stmts: &[Statement]) -> Result<(), Error> {
+        for stmt in stmts {
+            match *stmt {
+                Statement::Declare(ref name, ref value) => {
+                    let value = self.eval(value)?;
+                    match self.frames.last_mut() {
+                        Some(frame) => frame.insert(name.clone(), value),
+                        None => self.globals.insert(name.clone(), value),
+                    };
+                }
+                Statement::Expr(ref expr) => {
+                    self.eval(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, name: &str, value: u64) -> Result<(), Error> {
+        if let Some(slot) = self.frames.last_mut().and_then(|f| f.get_mut(name)) {
+            *slot = value;
+            return Ok(());
+        }
+        match self.globals.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(seq_err!("assignment to undeclared variable {}", name)),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<u64, Error> {
+        match *expr {
+            Expr::Number(n) => Ok(n),
+            Expr::Str(ref s) => Err(seq_err!("string \"{}\" used as a value", s)),
+            Expr::Var(ref name) => self
+                .var(name)
+                .ok_or_else(|| seq_err!("undeclared variable {}", name)),
+            Expr::Unary(op, ref inner) => {
+                let value = self.eval(inner)?;
+                Ok(match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as u64,
+                    UnaryOp::BitNot => !value,
+                })
+            }
+            Expr::Binary(BinaryOp::And, ref lhs, ref rhs) => {
+                Ok((self.eval(lhs)? != 0 && self.eval(rhs)? != 0) as u64)
+            }
+            Expr::Binary(BinaryOp::Or, ref lhs, ref rhs) => {
+                Ok((self.eval(lhs)? != 0 || self.eval(rhs)? != 0) as u64)
+            }
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let lhs = self.eval(lhs)?;
+                let rhs = self.eval(rhs)?;
+                op.apply(lhs, rhs)
+            }
+            Expr::Ternary(ref cond, ref then, ref otherwise) => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+            Expr::Assign(ref name, op, ref value) => {
+                let mut value = self.eval(value)?;
+                if let Some(op) = op {
+                    let current = self
+                        .var(name)
+                        .ok_or_else(|| seq_err!("undeclared variable {}", name))?;
+                    value = op.apply(current, value)?;
+                }
+                self.assign(name, value)?;
+                Ok(value)
+            }
+            Expr::Call(ref name, ref args) => self.call(name, args),
+        }
+    }
+
+    fn string_arg(args: &[Expr], index: usize, func: &str) -> Result<String, Error> {
+        match args.get(index).cloned() {
+            Some(Expr::Str(s)) => Ok(s),
+            _ => Err(seq_err!("{} expects a string as argument {}", func, index + 1)),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<u64, Error> {
+        let arity = match name {
+            "Sequence" | "LoadDebugInfo" => 1,
+            "Query" => 3,
+            "QueryValue" => 2,
+            "Message" => args.len().max(2),
+            "Read8" | "Read16" | "Read32" | "Read64" | "ReadAP" | "ReadDP" => 1,
+            "Write8" | "Write16" | "Write32" | "Write64" | "WriteAP" | "WriteDP" => 2,
+            "DAP_Delay" | "DAP_WriteABORT" | "DAP_SWJ_Clock" => 1,
+            "DAP_SWJ_Sequence" => 2,
+            "DAP_SWJ_Pins" | "DAP_JTAG_Sequence" => 3,
+            _ => return Err(seq_err!("unknown function {}", name)),
+        };
+        if args.len() != arity {
+            return Err(seq_err!(
+                "{} expects {} arguments, found {}",
+                name,
+                arity,
+                args.len()
+            ));
+        }
+        match name {
+            "Sequence" => {
+                let seq = Self::string_arg(args, 0, name)?;
+                self.run(&seq).map(|_| 0)
+            }
+            "LoadDebugInfo" => Self::string_arg(args, 0, name).map(|_| 0),
+            "Query" => {
+                let kind = self.eval(&args[0])?;
+                let text = Self::string_arg(args, 1, name)?;
+                let default = self.eval(&args[2])?;
+                Ok(self.access.query(kind, &text, default))
+            }
+            "QueryValue" => {
+                let text = Self::string_arg(args, 0, name)?;
+                let default = self.eval(&args[1])?;
+                Ok(self.access.query(0, &text, default))
+            }
+            "Message" => {
+                let kind = self.eval(&args[0])?;
+                let format = Self::string_arg(args, 1, name)?;
+                let values = args[2..]
+                    .iter()
+                    .map(|a| self.eval(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let text = format_message(&format, &values);
+                self.access.message(kind, &text);
+                Ok(0)
+            }
+            _ => {
+                let values = args
+                    .iter()
+                    .map(|a| self.eval(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let res = self.access_call(name, &values);
+                match res {
+                    Ok(value) => Ok(value),
+                    Err(_) if self.var("__errorcontrol").unwrap_or(0) & 1 != 0 => Ok(0),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    fn access_call(&mut self, name: &str, args: &[u64]) -> Result<u64, Error> {
+        let dp = self.var("__dp").unwrap_or(0);
+        let ap = self.var("__ap").unwrap_or(0);
+        let access = &mut self.access;
+        let bits = |name: &str| -> u8 {
+            match &name[name.len() - 1..] {
+                "8" => 8,
+                "6" => 16,
+                "2" => 32,
+                _ => 64,
+            }
+        };
+        let mask = |bits: u8, value: u64| if bits < 64 { value & ((1 << bits) - 1) } else { value };
+        match name {
+            "Read8" | "Read16" | "Read32" | "Read64" => {
+                let bits = bits(name);
+                access.read_memory(ap, args[0], bits).map(|v| mask(bits, v))
+            }
+            "Write8" | "Write16" | "Write32" | "Write64" => {
+                let bits = bits(name);
+                access.write_memory(ap, args[0], bits, mask(bits, args[1])).map(|_| 0)
+            }
+            "ReadAP" => access.read_ap(ap, args[0]),
+            "WriteAP" => access.write_ap(ap, args[0], args[1]).map(|_| 0),
+            "ReadDP" => access.read_dp(dp, args[0]),
+            "WriteDP" => access.write_dp(dp, args[0], args[1]).map(|_| 0),
+            "DAP_Delay" => access.delay(args[0]).map(|_| 0),
+            "DAP_WriteABORT" => access.write_abort(dp, args[0]).map(|_| 0),
+            "DAP_SWJ_Pins" => access.swj_pins(args[0], args[1], args[2]),
+            "DAP_SWJ_Clock" => access.swj_clock(args[0]).map(|_| 0),
+            "DAP_SWJ_Sequence" => access.swj_sequence(args[0], args[1]).map(|_| 0),
+            "DAP_JTAG_Sequence" => access.jtag_sequence(args[0], args[1], args[2]),
+            _ => Err(seq_err!("unknown function {}", name)),
+        }
+    }
+}
+
+/// Expand the `%d`, `%u`, `%x`, `%X` and `%%` conversions of a `Message`
+/// format, including zero padding and widths such as `%08X`.
+fn format_message(format: &str, values: &[u64]) -> String {
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&d) = chars.peek() {
+            if d.is_ascii_digit() {
+                spec.push(d);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let zero = spec.starts_with('0');
+        let width = spec.parse().unwrap_or(0);
+        let conv = chars.next();
+        let value = match conv {
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            Some('d') | Some('i') | Some('u') => values.next().map(|v| v.to_string()),
+            Some('x') => values.next().map(|v| format!("{:x}", v)),
+            Some('X') => values.next().map(|v| format!("{:X}", v)),
+            Some(other) => {
+                out.push('%');
+                out.push_str(&spec);
+                out.push(other);
+                continue;
+            }
+            None => {
+                out.push('%');
+                out.push_str(&spec);
+                continue;
+            }
+        };
+        let value = value.unwrap_or_default();
+        let pad = if zero { '0' } else { ' ' };
+        for _ in value.len()..width {
+            out.push(pad);
+        }
+        out.push_str(&value);
+    }
+    out
+}
+
+/// One operation performed on a `MockAccess`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read { ap: u64, address: u64, bits: u8, value: u64 },
+    Write { ap: u64, address: u64, bits: u8, value: u64 },
+    ReadDp { dp: u64, address: u64, value: u64 },
+    WriteDp { dp: u64, address: u64, value: u64 },
+    ReadAp { ap: u64, address: u64, value: u64 },
+    WriteAp { ap: u64, address: u64, value: u64 },
+    Delay(u64),
+    WriteAbort { dp: u64, value: u64 },
+    SwjPins { out: u64, select: u64, wait: u64 },
+    SwjClock(u64),
+    SwjSequence { count: u64, value: u64 },
+    JtagSequence { count: u64, tms: u64, tdi: u64 },
+}
+
+/// A `DebugAccess` without hardware, for testing sequences offline.
+///
+/// Memory and registers read back what was last written to them, or zero.
+/// Reads may be scripted with `queue_read` and friends; scripted values are
+/// returned, in order, before falling back to the stored value.
+#[derive(Debug, Clone, Default)]
+pub struct MockAccess {
+    pub memory: HashMap<u64, u64>,
+    pub dp_registers: HashMap<(u64, u64), u64>,
+    pub ap_registers: HashMap<(u64, u64), u64>,
+    pub answers: HashMap<String, u64>,
+    pub log: Vec<Access>,
+    pub messages: Vec<String>,
+    memory_reads: HashMap<u64, VecDeque<u64>>,
+    dp_reads: HashMap<(u64, u64), VecDeque<u64>>,
+    ap_reads: HashMap<(u64, u64), VecDeque<u64>>,
+}
+
+impl MockAccess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return `values` from the next reads of memory at `address`.
+    pub fn queue_read(&mut self, address: u64, values: &[u64]) -> &mut Self {
+        self.memory_reads
+            .entry(address)
+            .or_default()
+            .extend(values);
+        self
+    }
+
+    pub fn queue_dp_read(&mut self, dp: u64, address: u64, values: &[u64]) -> &mut Self {
+        self.dp_reads
+            .entry((dp, address))
+            .or_default()
+            .extend(values);
+        self
+    }
+
+    pub fn queue_ap_read(&mut self, ap: u64, address: u64, values: &[u64]) -> &mut Self {
+        self.ap_reads
+            .entry((ap, address))
+            .or_default()
+            .extend(values);
+        self
+    }
+
+    /// The writes to memory, as `(address, value)` pairs in order.
+    pub fn memory_writes(&self) -> Vec<(u64, u64)> {
+        self.log
+            .iter()
+            .filter_map(|a| match *a {
+                Access::Write { address, value, .. } => Some((address, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl DebugAccess for MockAccess {
+    fn read_memory(&mut self, ap: u64, address: u64, bits: u8) -> Result<u64, Error> {
+        let value = match self.memory_reads.get_mut(&address).and_then(VecDeque::pop_front) {
+            Some(value) => value,
+            None => self.memory.get(&address).cloned().unwrap_or(0),
+        };
+        self.log.push(Access::Read { ap, address, bits, value });
+        Ok(value)
+    }
+
+    fn write_memory(&mut self, ap: u64, address: u64, bits: u8, value: u64) -> Result<(), Error> {
+        self.memory.insert(address, value);
+        self.log.push(Access::Write { ap, address, bits, value });
+        Ok(())
+    }
+
+    fn read_dp(&mut self, dp: u64, address: u64) -> Result<u64, Error> {
+        let value = match self.dp_reads.get_mut(&(dp, address)).and_then(VecDeque::pop_front) {
+            Some(value) => value,
+            None => self.dp_registers.get(&(dp, address)).cloned().unwrap_or(0),
+        };
+        self.log.push(Access::ReadDp { dp, address, value });
+        Ok(value)
+    }
+
+    fn write_dp(&mut self, dp: u64, address: u64, value: u64) -> Result<(), Error> {
+        self.dp_registers.insert((dp, address), value);
+        self.log.push(Access::WriteDp { dp, address, value });
+        Ok(())
+    }
+
+    fn read_ap(&mut self, ap: u64, address: u64) -> Result<u64, Error> {
+        let value = match self.ap_reads.get_mut(&(ap, address)).and_then(VecDeque::pop_front) {
+            Some(value) => value,
+            None => self.ap_registers.get(&(ap, address)).cloned().unwrap_or(0),
+        };
+        self.log.push(Access::ReadAp { ap, address, value });
+        Ok(value)
+    }
+
+    fn write_ap(&mut self, ap: u64, address: u64, value: u64) -> Result<(), Error> {
+        self.ap_registers.insert((ap, address), value);
+        self.log.push(Access::WriteAp { ap, address, value });
+        Ok(())
+    }
+
+    fn delay(&mut self, micros: u64) -> Result<(), Error> {
+        self.log.push(Access::Delay(micros));
+        Ok(())
+    }
+
+    fn write_abort(&mut self, dp: u64, value: u64) -> Result<(), Error> {
+        self.log.push(Access::WriteAbort { dp, value });
+        Ok(())
+    }
+
+    fn swj_pins(&mut self, out: u64, select: u64, wait: u64) -> Result<u64, Error> {
+        self.log.push(Access::SwjPins { out, select, wait });
+        Ok(out & select)
+    }
+
+    fn swj_clock(&mut self, hz: u64) -> Result<(), Error> {
+        self.log.push(Access::SwjClock(hz));
+        Ok(())
+    }
+
+    fn swj_sequence(&mut self, count: u64, value: u64) -> Result<(), Error> {
+        self.log.push(Access::SwjSequence { count, value });
+        Ok(())
+    }
+
+    fn jtag_sequence(&mut self, count: u64, tms: u64, tdi: u64) -> Result<u64, Error> {
+        self.log.push(Access::JtagSequence { count, tms, tdi });
+        Ok(0)
+    }
+
+    fn message(&mut self, _kind: u64, text: &str) {
+        self.messages.push(text.to_string());
+    }
+
+    fn query(&mut self, _kind: u64, text: &str, default: u64) -> u64 {
+        self.answers.get(text).cloned().unwrap_or(default)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{Discard, Logger};
+    use minidom::Element;
+
+    fn device_debug(xml: &str) -> DeviceDebug {
+        let log = Logger::root(Discard, o!());
+        let root: Element = xml.parse().unwrap();
+        let mut debug = DeviceDebug::default();
+        for child in root.children() {
+            debug.add_elem(child, &log);
+        }
+        debug
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse_expr("1 + 2 * 3 == 7 && !0").unwrap(),
+            Expr::Binary(
+                BinaryOp::And,
+                Box::new(Expr::Binary(
+                    BinaryOp::Eq,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Number(1)),
+                        Box::new(Expr::Binary(
+                            BinaryOp::Mul,
+                            Box::new(Expr::Number(2)),
+                            Box::new(Expr::Number(3))
+                        ))
+                    )),
+                    Box::new(Expr::Number(7))
+                )),
+                Box::new(Expr::Unary(UnaryOp::Not, Box::new(Expr::Number(0))))
+            )
+        );
+        let stmts = parse_statements("__var x = 0x10; // comment\n x |= 1 << 2;").unwrap();
+        assert_eq!(stmts.len(), 2);
+        assert!(parse_statements("x = ;").is_err());
+        assert!(parse_expr("1 +").is_err());
+    }
+
+    #[test]
+    fn reset_system() {
+        let debug = device_debug(
+            "<device xmlns=\"\">
+              <debugvars>__var DbgMCU_CR = 0x00000007;</debugvars>
+              <sequences>
+                <sequence name=\"ResetSystem\">
+                  <block>
+                    __var SCS_Addr = 0xE000E000;
+                    __var AIRCR_Addr = SCS_Addr + 0xD0C;
+                    __var DHCSR_Addr = SCS_Addr + 0xDF0;
+                    Write32(AIRCR_Addr, 0x05FA0004);
+                    __var t = 0;
+                  </block>
+                  <control while=\"(Read32(DHCSR_Addr) &amp; 0x02000000) == 0\" timeout=\"500000\"/>
+                  <block>Sequence(\"Configure\");</block>
+                </sequence>
+                <sequence name=\"Configure\">
+                  <control if=\"DbgMCU_CR\">
+                    <block>Write32(0xE0042004, DbgMCU_CR); Message(0, \"CR=%08X\", DbgMCU_CR);</block>
+                  </control>
+                </sequence>
+              </sequences>
+            </device>",
+        );
+        let mut mock = MockAccess::new();
+        mock.queue_read(0xE000_EDF0, &[0, 0, 0x0200_0000]);
+        Interpreter::for_device(&mut mock, &debug, None)
+            .unwrap()
+            .run("ResetSystem")
+            .unwrap();
+        assert_eq!(
+            mock.memory_writes(),
+            vec![(0xE000_ED0C, 0x05FA_0004), (0xE004_2004, 7)]
+        );
+        let reads = mock.log.iter().filter(|a| match **a {
+            Access::Read { .. } => true,
+            _ => false,
+        });
+        assert_eq!(reads.count(), 3);
+        assert_eq!(mock.messages, vec!["CR=00000007".to_string()]);
+    }
+
+    #[test]
+    fn while_loop_timeout() {
+        let debug = device_debug(
+            "<device xmlns=\"\">
+              <sequences>
+                <sequence name=\"WaitForever\">
+                  <control while=\"(Read32(0x20000000) &amp; 1) == 0\" timeout=\"1000\"/>
+                  <block>Write32(0x20000004, 1);</block>
+                </sequence>
+                <sequence name=\"Outer\"><block>Sequence(\"WaitForever\");</block></sequence>
+              </sequences>
+            </device>",
+        );
+        let mut mock = MockAccess::new();
+        let err = Interpreter::for_device(&mut mock, &debug, None)
+            .unwrap()
+            .run("Outer")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<SequenceTimeout>(),
+            Some(&SequenceTimeout {
+                sequence: "WaitForever".to_string(),
+                timeout: 1000,
+            })
+        );
+        assert!(mock.memory_writes().is_empty());
+    }
+
+    #[test]
+    fn zero_timeout_waits() {
+        let debug = device_debug(
+            "<device xmlns=\"\">
+              <sequences>
+                <sequence name=\"Wait\">
+                  <control while=\"(Read32(0x20000000) &amp; 1) == 0\" timeout=\"0\">
+                    <block>__var polled = 1;</block>
+                  </control>
+                  <block>Write32(0x20000004, 1);</block>
+                </sequence>
+              </sequences>
+            </device>",
+        );
+        let mut mock = MockAccess::new();
+        mock.queue_read(0x2000_0000, &[0, 0, 1]);
+        Interpreter::for_device(&mut mock, &debug, None)
+            .unwrap()
+            .run("Wait")
+            .unwrap();
+        assert_eq!(mock.memory_writes(), vec![(0x2000_0004, 1)]);
+        let reads = mock.log.iter().filter(|a| match **a {
+            Access::Read { .. } => true,
+            _ => false,
+        });
+        assert_eq!(reads.count(), 3);
+    }
+
+    #[test]
+    fn dap_calls_and_errors() {
+        let debug = device_debug(
+            "<device xmlns=\"\">
+              <sequences>
+                <sequence name=\"DebugPortSetup\">
+                  <block>
+                    __var isSWJ = ((__dp &amp; 0x1) == 0) ? 1 : 0;
+                    DAP_SWJ_Sequence(51, 0x0007FFFFFFFFFFFF);
+                    DAP_SWJ_Sequence(16, 0xE79E);
+                    __var id = ReadDP(0x0);
+                    __Result = id == 0x2BA01477;
+                  </block>
+                </sequence>
+                <sequence name=\"ResetSystem\" disable=\"1\"/>
+                <sequence name=\"Broken\"><block>undefined = 1;</block></sequence>
+              </sequences>
+            </device>",
+        );
+        let mut mock = MockAccess::new();
+        mock.queue_dp_read(0, 0, &[0x2BA0_1477]);
+        {
+            let mut interp = Interpreter::for_device(&mut mock, &debug, None).unwrap();
+            interp.run("DebugPortSetup").unwrap();
+            assert_eq!(interp.var("__Result"), Some(1));
+            interp.run("ResetSystem").unwrap();
+            assert!(interp.run("Broken").is_err());
+            assert!(interp.run("Missing").is_err());
+        }
+        assert_eq!(mock.log[0], Access::SwjSequence { count: 51, value: 0x0007_FFFF_FFFF_FFFF });
+        assert_eq!(mock.log.len(), 3);
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt;
+use std::str::FromStr;
+
+use minidom::{Error, ErrorKind};
+use slog::Logger;
+
+use condition::{compare_versions, version_in_range, ConditionComponent, SelectedComponent,
+                TargetContext};
+use {Component, Package};
+
+/// A request for a component, written as
+/// `[Vendor::]Class:Group[:Sub][&Variant][@Version]`, where the version may
+/// be a `min:max` range.
+#[derive(Debug, Clone)]
+pub struct ComponentRequest {
+    pub vendor: Option<String>,
+    pub class: String,
+    pub group: String,
+    pub sub_group: Option<String>,
+    pub variant: Option<String>,
+    pub version: Option<String>,
+    pub instances: u8,
+}
+
+fn split_suffix(from: &str, sep: char) -> (&str, Option<String>) {
+    match from.find(sep) {
+        Some(idx) => (&from[..idx], Some(from[idx + 1..].to_string())),
+        None => (from, None),
+    }
+}
+
+impl FromStr for ComponentRequest {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        let (rest, version) = split_suffix(from, '@');
+        let (rest, variant) = split_suffix(rest, '&');
+        let (vendor, rest) = match rest.find("::") {
+            Some(idx) => (Some(rest[..idx].to_string()), &rest[idx + 2..]),
+            None => (None, rest),
+        };
+        let mut parts = rest.split(':');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(class), Some(group), sub_group, None)
+                if !class.is_empty() && !group.is_empty() => Ok(ComponentRequest {
+                    vendor,
+                    class: class.to_string(),
+                    group: group.to_string(),
+                    sub_group: sub_group.map(str::to_string),
+                    variant,
+                    version,
+                    instances: 1,
+                }),
+            _ => Err(err_msg!("Invalid component request {}", from)),
+        }
+    }
+}
+
+impl fmt::Display for ComponentRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref vendor) = self.vendor {
+            write!(f, "{}::", vendor)?;
+        }
+        write!(f, "{}:{}", self.class, self.group)?;
+        if let Some(ref sub) = self.sub_group {
+            write!(f, ":{}", sub)?;
+        }
+        if let Some(ref variant) = self.variant {
+            write!(f, "&{}", variant)?;
+        }
+        if let Some(ref version) = self.version {
+            write!(f, "@{}", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl ComponentRequest {
+    fn matches(&self, comp: &Component) -> bool {
+        fn opt_eq(req: &Option<String>, actual: &Option<String>) -> bool {
+            req.is_none() || req == actual
+        }
+        self.class == comp.class && self.group == comp.group
+            && opt_eq(&self.vendor, &Some(comp.vendor.clone()))
+            && opt_eq(&self.sub_group, &comp.sub_group)
+            && opt_eq(&self.variant, &comp.variant)
+            && match self.version {
+                Some(ref range) => version_in_range(range, &comp.version),
+                None => true,
+            }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum SelectionReason {
+    Requested,
+    RequiredBy(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Selected {
+    pub component: Component,
+    pub instances: u8,
+    pub reason: SelectionReason,
+}
+
+/// A consistent set of components, with one variant chosen per group.
+#[derive(Debug, Serialize)]
+pub struct Resolution {
+    pub selected: Vec<Selected>,
+}
+
+/// Why a component could not be part of a consistent selection.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub component: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.component, self.reason)
+    }
+}
+
+type Candidate = (usize, Component);
+
+/// Prefer components that are not deprecated, then default variants, then
+/// the newest version.
+fn best_candidate<'c, I>(candidates: I) -> Option<&'c Candidate>
+where
+    I: Iterator<Item = &'c Candidate>,
+{
+    candidates.max_by(|lhs, rhs| {
+        let (a, b) = (&lhs.1, &rhs.1);
+        (!a.deprecated)
+            .cmp(&!b.deprecated)
+            .then(a.is_default.cmp(&b.is_default))
+            .then_with(|| compare_versions(&a.version, &b.version))
+    })
+}
+
+fn same_group(a: &Component, b: &Component) -> bool {
+    a.vendor == b.vendor && a.class == b.class && a.group == b.group && a.sub_group == b.sub_group
+}
+
+fn describe(expr: &ConditionComponent) -> String {
+    let attrs = [
+        ("Cvendor", &expr.component_vendor),
+        ("Cclass", &expr.component_class),
+        ("Cgroup", &expr.component_group),
+        ("Csub", &expr.component_sub_group),
+        ("Cvariant", &expr.component_variant),
+        ("Cversion", &expr.component_version),
+        ("Capiversion", &expr.component_api_version),
+    ];
+    attrs
+        .iter()
+        .filter_map(|&(name, value)| value.as_ref().map(|v| format!("{}={}", name, v)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Select the requested components for a target and close the selection
+/// over the component dependencies expressed in their conditions.
+///
+/// Only components whose conditions can hold for the device, toolchain and
+/// board of `ctx` are considered. When no consistent selection exists, every
+/// requirement that could not be met is returned.
+pub fn solve<'a, I>(
+    pdscs: I,
+    ctx: &TargetContext,
+    requests: &[ComponentRequest],
+    l: &Logger,
+) -> Result<Resolution, Vec<Conflict>>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    let pdscs: Vec<&Package> = pdscs.into_iter().collect();
+    let lookups: Vec<_> = pdscs.iter().map(|p| p.make_condition_lookup(l)).collect();
+    let target = TargetContext {
+        assume_components: true,
+        components: Vec::new(),
+        ..ctx.clone()
+    };
+    let candidates: Vec<Candidate> = pdscs
+        .iter()
+        .enumerate()
+        .flat_map(|(i, p)| {
+            p.make_components_for(&target, l)
+                .into_iter()
+                .map(move |c| (i, c))
+        })
+        .collect();
+    let mut selected: Vec<(usize, Selected)> = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for req in requests.iter() {
+        let &(pack, ref comp) = match best_candidate(candidates.iter().filter(|c| req.matches(&c.1))) {
+            Some(cand) => cand,
+            None => {
+                conflicts.push(Conflict {
+                    component: req.to_string(),
+                    reason: String::from("no component matches this request for the target"),
+                });
+                continue;
+            }
+        };
+        let max_instances = comp.max_instances.unwrap_or(1);
+        if req.instances > max_instances {
+            conflicts.push(Conflict {
+                component: comp.id(),
+                reason: format!(
+                    "{} instances requested, but at most {} are allowed",
+                    req.instances, max_instances
+                ),
+            });
+            continue;
+        }
+        match selected.iter_mut().find(|s| same_group(&s.1.component, comp)) {
+            Some(entry) => {
+                let existing = &mut entry.1;
+                if existing.component.variant != comp.variant {
+                    conflicts.push(Conflict {
+                        component: comp.id(),
+                        reason: format!("conflicts with the selected {}", existing.component.id()),
+                    });
+                } else if existing.instances < req.instances {
+                    existing.instances = req.instances;
+                }
+            }
+            None => selected.push((
+                pack,
+                Selected {
+                    component: comp.clone(),
+                    instances: req.instances,
+                    reason: SelectionReason::Requested,
+                },
+            )),
+        }
+    }
+
+    let mut ctx = TargetContext {
+        assume_components: false,
+        components: selected.iter().map(|s| (&s.1.component).into()).collect(),
+        ..ctx.clone()
+    };
+    loop {
+        let mut additions: Vec<(usize, Selected)> = Vec::new();
+        for &(pack, ref sel) in selected.iter() {
+            let lookup = &lookups[pack];
+            let cond = match sel.component.condition.as_ref().and_then(|id| lookup.get(id.as_str())) {
+                Some(cond) => cond,
+                None => continue,
+            };
+            if cond.evaluate(&ctx, lookup) {
+                continue;
+            }
+            for alternatives in cond.unmet_requirements(&ctx, lookup) {
+                let satisfied = ctx.components.iter().any(|c| {
+                    alternatives.iter().any(|alt| alt.component_matches(c))
+                });
+                if satisfied {
+                    continue;
+                }
+                let taken = |c: &Component| {
+                    selected.iter().chain(additions.iter()).any(|s| same_group(&s.1.component, c))
+                };
+                let found = alternatives
+                    .iter()
+                    .filter_map(|alt| {
+                        best_candidate(candidates.iter().filter(|c| {
+                            alt.component_matches(&SelectedComponent::from(&c.1)) && !taken(&c.1)
+                        }))
+                    })
+                    .next();
+                if let Some(&(dep_pack, ref dep)) = found {
+                    ctx.components.push(dep.into());
+                    additions.push((
+                        dep_pack,
+                        Selected {
+                            component: dep.clone(),
+                            instances: 1,
+                            reason: SelectionReason::RequiredBy(sel.component.id()),
+                        },
+                    ));
+                }
+            }
+        }
+        if additions.is_empty() {
+            break;
+        }
+        selected.extend(additions);
+    }
+
+    for &(pack, ref sel) in selected.iter() {
+        let lookup = &lookups[pack];
+        if let Some(cond) = sel.component.condition.as_ref().and_then(|id| lookup.get(id.as_str())) {
+            if !cond.evaluate(&ctx, lookup) {
+                let unmet = cond.unmet_requirements(&ctx, lookup)
+                    .iter()
+                    .map(|alts| alts.iter().map(|a| describe(a)).collect::<Vec<_>>().join(" or "))
+                    .collect::<Vec<_>>();
+                let reason = if unmet.is_empty() {
+                    format!("condition '{}' does not hold", cond.id)
+                } else {
+                    format!("condition '{}' requires {}", cond.id, unmet.join(" and "))
+                };
+                conflicts.push(Conflict {
+                    component: sel.component.id(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(Resolution {
+            selected: selected.into_iter().map(|(_, s)| s).collect(),
+        })
+    } else {
+        Err(conflicts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{Discard, Logger};
+    use utils::parse::FromElem;
+
+    const PDSC: &'static str = "<package>
+      <vendor>ARM</vendor>
+      <name>Software</name>
+      <description>dummy</description>
+      <url>http://localhost/</url>
+      <releases><release version=\"5.0.0\"/></releases>
+      <conditions>
+        <condition id=\"Startup\">
+          <require Dcore=\"Cortex-M4\"/>
+          <require Cclass=\"CMSIS\" Cgroup=\"CORE\"/>
+        </condition>
+        <condition id=\"RTOS\">
+          <require condition=\"Startup\"/>
+          <accept Cclass=\"Device\" Cgroup=\"Startup\"/>
+        </condition>
+        <condition id=\"No RTOS\">
+          <deny Cclass=\"RTOS\"/>
+        </condition>
+      </conditions>
+      <components>
+        <component Cclass=\"CMSIS\" Cgroup=\"CORE\" Cversion=\"5.1.0\">
+          <description>core</description>
+        </component>
+        <component Cclass=\"Device\" Cgroup=\"Startup\" condition=\"Startup\">
+          <description>startup</description>
+        </component>
+        <component Cclass=\"RTOS\" Cgroup=\"Kernel\" Cvariant=\"Source\" isDefaultVariant=\"false\"
+                   condition=\"RTOS\">
+          <description>kernel source</description>
+        </component>
+        <component Cclass=\"RTOS\" Cgroup=\"Kernel\" Cvariant=\"Library\" condition=\"RTOS\"
+                   maxInstances=\"2\">
+          <description>kernel library</description>
+        </component>
+        <component Cclass=\"Bare\" Cgroup=\"Metal\" condition=\"No RTOS\">
+          <description>bare metal</description>
+        </component>
+      </components>
+    </package>";
+
+    fn target() -> TargetContext {
+        TargetContext {
+            core: Some(String::from("Cortex-M4")),
+            compiler: Some(String::from("GCC")),
+            ..Default::default()
+        }
+    }
+
+    fn request(s: &str) -> ComponentRequest {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_request() {
+        let req = request("ARM::CMSIS:RTOS2:Keil RTX5&Library@5.0.0:6.0.0");
+        assert_eq!(req.vendor, Some(String::from("ARM")));
+        assert_eq!(req.class, "CMSIS");
+        assert_eq!(req.group, "RTOS2");
+        assert_eq!(req.sub_group, Some(String::from("Keil RTX5")));
+        assert_eq!(req.variant, Some(String::from("Library")));
+        assert_eq!(req.version, Some(String::from("5.0.0:6.0.0")));
+        assert_eq!(req.to_string(), "ARM::CMSIS:RTOS2:Keil RTX5&Library@5.0.0:6.0.0");
+        assert!("CMSIS".parse::<ComponentRequest>().is_err());
+        assert!("A:B:C:D".parse::<ComponentRequest>().is_err());
+    }
+
+    #[test]
+    fn closes_over_dependencies() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(PDSC, &log).unwrap();
+        let res = solve(Some(&pack), &target(), &[request("RTOS:Kernel")], &log).unwrap();
+        let ids: Vec<_> = res.selected.iter().map(|s| s.component.id()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "ARM::RTOS:Kernel&Library@5.0.0",
+                "ARM::CMSIS:CORE@5.1.0",
+                "ARM::Device:Startup@5.0.0",
+            ]
+        );
+        match res.selected[2].reason {
+            SelectionReason::RequiredBy(ref by) => assert_eq!(by, "ARM::RTOS:Kernel&Library@5.0.0"),
+            ref other => panic!("unexpected reason {:?}", other),
+        }
+        let res = solve(Some(&pack), &target(), &[request("RTOS:Kernel&Source")], &log).unwrap();
+        assert_eq!(res.selected[0].component.variant, Some(String::from("Source")));
+    }
+
+    #[test]
+    fn explains_conflicts() {
+        let log = Logger::root(Discard, o!());
+        let pack = Package::from_string(PDSC, &log).unwrap();
+        let other_core = TargetContext {
+            core: Some(String::from("Cortex-M0")),
+            ..target()
+        };
+        let err = solve(Some(&pack), &other_core, &[request("RTOS:Kernel")], &log).unwrap_err();
+        assert_eq!(err[0].component, "RTOS:Kernel");
+
+        let reqs = [request("RTOS:Kernel&Source"), request("RTOS:Kernel&Library")];
+        let err = solve(Some(&pack), &target(), &reqs, &log).unwrap_err();
+        assert!(err[0].reason.contains("conflicts with"));
+
+        let err = solve(Some(&pack), &target(), &[request("Bare:Metal"), request("RTOS:Kernel")], &log)
+            .unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert!(err[0].reason.contains("No RTOS"));
+
+        let mut three = request("RTOS:Kernel");
+        three.instances = 3;
+        let err = solve(Some(&pack), &target(), &[three], &log).unwrap_err();
+        assert!(err[0].reason.contains("at most 2"));
+    }
+}
@@ -0,0 +1,376 @@
+//! Pack versions.
+//!
+//! CMSIS packs mostly follow SemVer, but not strictly: leading zeros
+//! (`1.02.0`), missing or fourth components (`5.1`, `1.0.0.1`) and
+//! pre-release tags without a dash (`1.0.0rc1`) all occur in the wild.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use failure::{err_msg, Error};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// The fourth component of versions such as `1.0.0.1`.
+    pub build: u64,
+    pub pre: Vec<String>,
+    pub metadata: Option<String>,
+    raw: String,
+}
+
+/// Split `3rc1` into `(3, "rc1")`.
+fn split_number(part: &str) -> Result<(u64, &str), Error> {
+    let end = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+    if end == 0 {
+        return Err(err_msg(format!("expected a number, found '{}'", part)));
+    }
+    let number = part[..end]
+        .parse()
+        .map_err(|_| err_msg(format!("version component {} is too large", &part[..end])))?;
+    Ok((number, &part[end..]))
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+            build: 0,
+            pre: Vec::new(),
+            metadata: None,
+            raw: format!("{}.{}.{}", major, minor, patch),
+        }
+    }
+
+    /// Parse a version, treating anything unparseable as `0.0.0`. Used
+    /// where versions are compared rather than validated.
+    pub fn parse_lossy(from: &str) -> Self {
+        from.parse().unwrap_or_else(|_| Version {
+            raw: from.to_string(),
+            ..Version::new(0, 0, 0)
+        })
+    }
+
+    /// The version as written in the pack.
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    fn numbers(&self) -> (u64, u64, u64, u64) {
+        (self.major, self.minor, self.patch, self.build)
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        let raw = from.trim();
+        let trimmed = raw.trim_start_matches(&['v', 'V'][..]);
+        let (rest, metadata) = match trimmed.find('+') {
+            Some(i) => (&trimmed[..i], Some(trimmed[i + 1..].to_string())),
+            None => (trimmed, None),
+        };
+        let (numbers, mut pre) = match rest.find('-') {
+            Some(i) => (&rest[..i], rest[i + 1..].to_string()),
+            None => (rest, String::new()),
+        };
+        let mut parts = [0u64; 4];
+        let mut count = 0;
+        for part in numbers.split('.') {
+            if count == parts.len() {
+                return Err(err_msg(format!("too many components in version {}", raw)));
+            }
+            let (number, suffix) = split_number(part)?;
+            parts[count] = number;
+            count += 1;
+            if !suffix.is_empty() {
+                if !pre.is_empty() {
+                    return Err(err_msg(format!("invalid version {}", raw)));
+                }
+                pre = suffix.to_string();
+                if count < numbers.split('.').count() {
+                    return Err(err_msg(format!("invalid version {}", raw)));
+                }
+            }
+        }
+        Ok(Version {
+            major: parts[0],
+            minor: parts[1],
+            patch: parts[2],
+            build: parts[3],
+            pre: pre.split('.')
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+            metadata,
+            raw: raw.to_string(),
+        })
+    }
+}
+
+fn compare_pre(lhs: &[String], rhs: &[String]) -> Ordering {
+    match (lhs.is_empty(), rhs.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        (false, false) => (),
+    }
+    for (l, r) in lhs.iter().zip(rhs.iter()) {
+        let ord = match (l.parse::<u64>(), r.parse::<u64>()) {
+            (Ok(l), Ok(r)) => l.cmp(&r),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => l.to_lowercase().cmp(&r.to_lowercase()),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    lhs.len().cmp(&rhs.len())
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numbers()
+            .cmp(&other.numbers())
+            .then_with(|| compare_pre(&self.pre, &other.pre))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl Hash for Version {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.numbers().hash(state);
+        for pre in &self.pre {
+            pre.to_lowercase().hash(state);
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl AsRef<str> for Version {
+    fn as_ref(&self) -> &str {
+        &self.raw
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+}
+
+/// A set of version constraints that must all hold.
+///
+/// Accepts CMSIS ranges (`1.2.0:2.0.0`, `1.2.0:`), SemVer style requirements
+/// (`^1.2`, `~1.2.3`, `>=1.0, <2.0`), `*`, and a bare version, which must
+/// match exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<(Op, Version)>,
+}
+
+impl VersionReq {
+    pub fn any() -> Self {
+        VersionReq {
+            comparators: Vec::new(),
+        }
+    }
+
+    pub fn exact(version: &Version) -> Self {
+        VersionReq {
+            comparators: vec![(Op::Exact, version.clone())],
+        }
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators.iter().all(|&(op, ref bound)| {
+            let ord = version.cmp(bound);
+            match op {
+                Op::Exact => ord == Ordering::Equal,
+                Op::Greater => ord == Ordering::Greater,
+                Op::GreaterEq => ord != Ordering::Less,
+                Op::Less => ord == Ordering::Less,
+                Op::LessEq => ord != Ordering::Greater,
+            }
+        })
+    }
+
+    fn parse_comparator(from: &str, comparators: &mut Vec<(Op, Version)>) -> Result<(), Error> {
+        let from = from.trim();
+        if from.is_empty() || from == "*" {
+            return Ok(());
+        }
+        if let Some(colon) = from.find(':') {
+            let (min, max) = (from[..colon].trim(), from[colon + 1..].trim());
+            if !min.is_empty() {
+                comparators.push((Op::GreaterEq, min.parse()?));
+            }
+            if !max.is_empty() {
+                comparators.push((Op::LessEq, max.parse()?));
+            }
+            return Ok(());
+        }
+        let ops: [(&str, Op); 6] = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Exact),
+            ("", Op::Exact),
+        ];
+        let first = from.chars().next();
+        if first == Some('^') || first == Some('~') {
+            let version: Version = from.trim_start_matches(&['^', '~'][..]).trim().parse()?;
+            let upper = if first == Some('^') && version.major > 0 {
+                Version::new(version.major + 1, 0, 0)
+            } else {
+                Version::new(version.major, version.minor + 1, 0)
+            };
+            comparators.push((Op::GreaterEq, version));
+            comparators.push((Op::Less, upper));
+            return Ok(());
+        }
+        if let Some(&(prefix, op)) = ops.iter().find(|&&(prefix, _)| from.starts_with(prefix)) {
+            let (_, version) = from.split_at(prefix.len());
+            comparators.push((op, version.trim().parse()?));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        let mut comparators = Vec::new();
+        for part in from.split(',') {
+            VersionReq::parse_comparator(part, &mut comparators)?;
+        }
+        Ok(VersionReq { comparators })
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.comparators.is_empty() {
+            return f.write_str("*");
+        }
+        let parts: Vec<String> = self.comparators
+            .iter()
+            .map(|&(op, ref version)| {
+                let op = match op {
+                    Op::Exact => "=",
+                    Op::Greater => ">",
+                    Op::GreaterEq => ">=",
+                    Op::Less => "<",
+                    Op::LessEq => "<=",
+                };
+                format!("{}{}", op, version)
+            })
+            .collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn tolerates_cmsis_quirks() {
+        assert_eq!(v("1.02.003"), Version::new(1, 2, 3));
+        assert_eq!(v("5.1"), Version::new(5, 1, 0));
+        assert_eq!(v("1.02.0").as_str(), "1.02.0");
+        assert_eq!(v("1.0.0.1").build, 1);
+        assert_eq!(v("1.0.0rc1").pre, vec!["rc1".to_string()]);
+        assert!(v("1.0.0-rc.2").is_prerelease());
+        assert!("".parse::<Version>().is_err());
+        assert!("abc".parse::<Version>().is_err());
+        assert!("1.2.3.4.5".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        let mut versions = vec![
+            v("1.0.0"),
+            v("1.10.0"),
+            v("1.2.0"),
+            v("1.0.0-rc.2"),
+            v("1.0.0-rc.10"),
+            v("1.0.0.1"),
+            v("1.0.0-beta"),
+        ];
+        versions.sort();
+        let sorted: Vec<&str> = versions.iter().map(Version::as_str).collect();
+        assert_eq!(
+            sorted,
+            vec!["1.0.0-beta", "1.0.0-rc.2", "1.0.0-rc.10", "1.0.0", "1.0.0.1", "1.2.0", "1.10.0"]
+        );
+    }
+
+    #[test]
+    fn requirements() {
+        let req: VersionReq = "1.2.0:2.0.0".parse().unwrap();
+        assert!(req.matches(&v("2.0.0")) && req.matches(&v("1.2")));
+        assert!(!req.matches(&v("2.0.1")));
+        let req: VersionReq = "^1.2".parse().unwrap();
+        assert!(req.matches(&v("1.9.9")) && !req.matches(&v("2.0.0")));
+        let req: VersionReq = "~0.3.1".parse().unwrap();
+        assert!(req.matches(&v("0.3.9")) && !req.matches(&v("0.4.0")));
+        let req: VersionReq = ">=1.0, <2".parse().unwrap();
+        assert!(req.matches(&v("1.5.0")) && !req.matches(&v("2.0.0")));
+        let req: VersionReq = "1.02.0".parse().unwrap();
+        assert!(req.matches(&v("1.2.0")) && !req.matches(&v("1.2.1")));
+        assert!(VersionReq::any().matches(&v("0.0.1")));
+    }
+}